@@ -1,47 +1,391 @@
+use crate::classes::ClassEnv;
+use anyhow::Context as _;
+
 use crate::models::*;
+use crate::system_f::FType;
+use crate::typed::*;
 use crate::utils::*;
 
 pub fn alg_w(ctx: Context, expr: Expr) -> anyhow::Result<(Substitution, MonoType)> {
-    match expr {
-        Expr::Lit(literal) => Ok((Substitution::empty(), literal.ty())),
-        Expr::Var(var) => match ctx.constrains.get(&var) {
-            Some(poly) => Ok((Substitution::empty(), poly.instantiate())),
-            None => anyhow::bail!("Undefiend variable: {var}"),
-        },
-        Expr::Abs(abs) => {
-            let beta = new_type_var();
-            let new_ctx = ctx.extend_with(abs.arg, PolyType::Mono(beta.clone()));
-            let (s1, t1) = alg_w(new_ctx, abs.body)?;
-            let infer_type = MonoType::TyApp(TypeApp {
-                func: "->".to_string(),
-                monotypes: vec![beta, t1],
-            })
-            .apply(&s1);
-
-            Ok((s1, infer_type))
+    let classes = ClassEnv::default();
+    let mut inference = Inference::new(&classes);
+    let (s, typed) = inference.typed_alg_w(ctx, expr)?;
+    Ok((s, typed.ty))
+}
+
+/// Infers the types of `expr` and of all its subexpressions, fully substituted.
+pub fn infer(ctx: Context, expr: Expr) -> anyhow::Result<TypedExpr> {
+    let (_, typed) = infer_qualified(&ClassEnv::default(), ctx, expr)?;
+    Ok(typed)
+}
+
+/// Like [`infer`], also giving the predicates that the type of `expr` is qualified
+/// with, simplified with the instances and superclasses of `classes`.
+pub fn infer_qualified(
+    classes: &ClassEnv,
+    ctx: Context,
+    expr: Expr,
+) -> anyhow::Result<(Vec<Pred>, TypedExpr)> {
+    let mut inference = Inference::new(classes);
+    let (s, typed) = inference.typed_alg_w(ctx, expr)?;
+    let preds = inference.preds.into_iter().map(|p| p.apply(&s)).collect();
+
+    Ok((classes.reduce(preds)?, typed.apply(&s)))
+}
+
+/// The constructor of an arm once instantiated.
+struct Opened {
+    con: TypedVar,
+    /// The skolems, after the existential variables they stand for.
+    skolems: Vec<(String, String)>,
+    fields: Vec<MonoType>,
+    /// The type of the value the constructor builds.
+    data_ty: MonoType,
+}
+
+/// The predicates collected while inferring, that are not part of a scheme yet.
+struct Inference<'a> {
+    classes: &'a ClassEnv,
+    preds: Vec<Pred>,
+}
+
+impl<'a> Inference<'a> {
+    fn new(classes: &'a ClassEnv) -> Self {
+        Self {
+            classes,
+            preds: vec![],
         }
-        Expr::App(app) => {
-            let (s1, t1) = alg_w(ctx.clone(), app.fun)?;
-            let (s2, t2) = alg_w(ctx.apply(&s1), app.arg)?;
-            let beta = new_type_var();
-            let s3 = unify(
-                t1.apply(&s2),
-                MonoType::TyApp(TypeApp {
+    }
+
+    /// Algorithm W, building the typed tree as it goes. The types of a node are only
+    /// substituted with what was known when it was inferred, applying the returned
+    /// substitution completes them.
+    fn typed_alg_w(
+        &mut self,
+        ctx: Context,
+        expr: Expr,
+    ) -> anyhow::Result<(Substitution, TypedExpr)> {
+        match expr {
+            Expr::Lit(literal) => {
+                let ty = literal.ty();
+                Ok((Substitution::empty(), typed(TypedNode::Lit(literal), ty)))
+            }
+            Expr::Var(var) => match ctx.constrains.get(&var) {
+                Some(poly) => {
+                    let (var, ty) = self.instantiate(var, poly);
+                    Ok((Substitution::empty(), typed(TypedNode::Var(var), ty)))
+                }
+                None => anyhow::bail!("Undefiend variable: {var}"),
+            },
+            Expr::Con(con) => match ctx.constrains.get(&con) {
+                Some(poly) => {
+                    let (con, ty) = self.instantiate(con, poly);
+                    Ok((Substitution::empty(), typed(TypedNode::Con(con), ty)))
+                }
+                None => anyhow::bail!("Undefined constructor: {con}"),
+            },
+            Expr::Abs(abs) => {
+                let beta = new_type_var();
+                let new_ctx = ctx.extend_with(abs.arg.clone(), PolyType::Mono(beta.clone()));
+                let (s1, body) = self.typed_alg_w(new_ctx, abs.body)?;
+                let infer_type = MonoType::TyApp(TypeApp {
                     func: "->".to_string(),
-                    monotypes: vec![t2, beta.clone()],
-                }),
-            )?;
-            let infer_type = beta.apply(&s3);
+                    monotypes: vec![beta.clone(), body.ty.clone()],
+                })
+                .apply(&s1);
+                let abs = TypedAbs {
+                    arg: abs.arg,
+                    arg_ty: beta,
+                    body,
+                };
+
+                Ok((s1, typed(TypedNode::Abs(Box::new(abs)), infer_type)))
+            }
+            Expr::App(app) => {
+                let (s1, fun) = self.typed_alg_w(ctx.clone(), app.fun)?;
+                let (s2, arg) = self.typed_alg_w(ctx.apply(&s1), app.arg)?;
+                let beta = new_type_var();
+                let s3 = unify_with(
+                    self.classes.synonyms(),
+                    fun.ty.clone().apply(&s2),
+                    MonoType::TyApp(TypeApp {
+                        func: "->".to_string(),
+                        monotypes: vec![arg.ty.clone(), beta.clone()],
+                    }),
+                )?;
+                let infer_type = beta.apply(&s3);
+                let app = TypedApp { fun, arg };
+
+                Ok((
+                    s3.combine(s2.combine(s1)),
+                    typed(TypedNode::App(Box::new(app)), infer_type),
+                ))
+            }
+            Expr::Let(let_expr) => {
+                let mark = self.preds.len();
+                let value = let_expr.vexpr.is_value();
+                let (s1, vexpr) = self.typed_alg_w(ctx.clone(), let_expr.vexpr)?;
+                let ctx = ctx.apply(&s1);
+                let new_ty = self.generalise(&vexpr.ty, &ctx, value, mark, &s1)?;
+                let new_ctx = ctx.extend_with(let_expr.var.clone(), new_ty.clone());
+                let (s2, aexpr) = self.typed_alg_w(new_ctx, let_expr.aexpr)?;
+                let ty = aexpr.ty.clone();
+                let elet = TypedLet {
+                    var: let_expr.var,
+                    scheme: new_ty,
+                    vexpr,
+                    aexpr,
+                };
+
+                Ok((s2.combine(s1), typed(TypedNode::Let(Box::new(elet)), ty)))
+            }
+            Expr::LetRec(let_expr) => {
+                let mark = self.preds.len();
+                let value = let_expr.vexpr.is_value();
+                let beta = new_type_var();
+                let rec_ctx = ctx.extend_with(let_expr.var.clone(), PolyType::Mono(beta.clone()));
+                let (s1, vexpr) = self.typed_alg_w(rec_ctx, let_expr.vexpr)?;
+                let s2 = unify_with(self.classes.synonyms(), beta.apply(&s1), vexpr.ty.clone())?;
+                let s1 = s2.combine(s1);
+
+                let ctx = ctx.apply(&s1);
+                let ty = vexpr.ty.clone().apply(&s1);
+                let new_ty = self.generalise(&ty, &ctx, value, mark, &s1)?;
+                let new_ctx = ctx.extend_with(let_expr.var.clone(), new_ty.clone());
+                let (s3, aexpr) = self.typed_alg_w(new_ctx, let_expr.aexpr)?;
+                let ty = aexpr.ty.clone();
+                let elet = TypedLet {
+                    var: let_expr.var,
+                    scheme: new_ty,
+                    vexpr,
+                    aexpr,
+                };
+
+                Ok((s3.combine(s1), typed(TypedNode::LetRec(Box::new(elet)), ty)))
+            }
+            Expr::Ann(ann) => {
+                let scheme = prenex(&ann.ty)?;
+                let (s1, expr) = self.typed_alg_w(ctx.clone(), ann.expr.clone())?;
+                let (inst, ty) = scheme.instantiate_fresh();
+                let s2 = unify_with(self.classes.synonyms(), expr.ty.clone(), ty.clone())?;
+
+                // the quantified variables must stay distinct variables of their own
+                let ctx = ctx.apply(&s1).apply(&s2);
+                let ctx_free_vars = ctx.free_vars();
+                let mut rigid: Vec<String> = vec![];
+                for var in inst.into_iter().map(|v| v.apply(&s2)) {
+                    match var {
+                        MonoType::TyVar(v)
+                            if !rigid.contains(&v) && !ctx_free_vars.contains(v.as_str()) =>
+                        {
+                            rigid.push(v)
+                        }
+                        _ => anyhow::bail!(
+                            "`{}` is less polymorphic than its annotation `{}`",
+                            ann.expr,
+                            ann.ty
+                        ),
+                    }
+                }
+
+                let ty = ty.apply(&s2);
+                Ok((s2.combine(s1), typed(expr.node, ty)))
+            }
+            Expr::Case(case) => {
+                let (mut s, expr) = self.typed_alg_w(ctx.clone(), case.expr)?;
+                let result = new_type_var();
+                let mut arms = vec![];
+                for arm in case.arms {
+                    let pattern = arm.pattern();
+                    let Opened {
+                        con,
+                        skolems,
+                        fields,
+                        data_ty,
+                    } = self.open(&ctx, &arm)?;
+                    let s1 =
+                        unify_with(self.classes.synonyms(), expr.ty.clone().apply(&s), data_ty)
+                            .with_context(|| format!("in the arm `{pattern}`"))?;
+                    s = s1.combine(s);
+
+                    let vars: Vec<_> = arm
+                        .vars
+                        .into_iter()
+                        .zip(fields.into_iter().map(|field| field.apply(&s)))
+                        .collect();
+                    let arm_ctx = vars.iter().fold(ctx.clone().apply(&s), |acc, (var, ty)| {
+                        acc.extend_with(var.clone(), PolyType::Mono(ty.clone()))
+                    });
+                    let (s2, body) = self.typed_alg_w(arm_ctx, arm.body)?;
+                    s = s2.combine(s);
+                    let s3 = unify_with(
+                        self.classes.synonyms(),
+                        result.clone().apply(&s),
+                        body.ty.clone(),
+                    )?;
+                    s = s3.combine(s);
 
-            Ok((s3.combine(s2.combine(s1)), infer_type))
+                    // the skolems are only known inside the arm
+                    let outside: Vec<_> = ctx
+                        .free_vars()
+                        .into_iter()
+                        .map(|var| MonoType::TyVar(var.to_string()))
+                        .chain([result.clone(), expr.ty.clone()])
+                        .map(|ty| ty.apply(&s))
+                        .collect();
+                    if let Some((var, _)) = skolems
+                        .iter()
+                        .find(|(_, skolem)| outside.iter().any(|ty| mentions(ty, skolem)))
+                    {
+                        anyhow::bail!(
+                            "Type variable `{var}` would escape its scope in the arm `{pattern}`"
+                        );
+                    }
+
+                    arms.push(TypedArm {
+                        con,
+                        skolems: skolems.into_iter().map(|(_, skolem)| skolem).collect(),
+                        vars,
+                        body,
+                    });
+                }
+
+                let ty = result.apply(&s);
+                let case = TypedCase { expr, arms };
+                Ok((s, typed(TypedNode::Case(Box::new(case)), ty)))
+            }
+        }
+    }
+
+    /// Instantiates the constructor of an arm, its existential variables with fresh
+    /// skolems.
+    fn open(&mut self, ctx: &Context, arm: &CaseArm) -> anyhow::Result<Opened> {
+        let Some(scheme) = ctx.constrains.get(&arm.con) else {
+            anyhow::bail!("Undefined constructor: {}", arm.con);
+        };
+        let (mut con, ty) = self.instantiate(arm.con.clone(), scheme);
+        let mut fields = vec![];
+        let mut data_ty = ty;
+        while let MonoType::TyApp(app) = &data_ty {
+            let [from, to] = app.monotypes.as_slice() else {
+                break;
+            };
+            if app.func != "->" {
+                break;
+            }
+            fields.push(from.clone());
+            data_ty = to.clone();
+        }
+        if fields.len() != arm.vars.len() {
+            anyhow::bail!(
+                "`{}` has {} field(s) but the arm `{}` binds {}",
+                arm.con,
+                fields.len(),
+                arm.pattern(),
+                arm.vars.len()
+            );
         }
-        Expr::Let(let_expr) => {
-            let (s1, t1) = alg_w(ctx.clone(), let_expr.vexpr)?;
-            let new_ty = t1.generalise(&ctx);
-            let new_ctx = ctx.apply(&s1).extend_with(let_expr.var, new_ty);
-            let (s2, t2) = alg_w(new_ctx, let_expr.aexpr)?;
 
-            Ok((s2.combine(s1), t2))
+        // the bound variables that the built value doesn't mention are existential
+        let (bounded, _) = scheme.split();
+        let data_vars = data_ty.free_vars();
+        let mut skolems = vec![];
+        let mut mapping = std::collections::HashMap::new();
+        for (var, inst) in bounded.into_iter().zip(con.inst.iter()) {
+            let MonoType::TyVar(fresh) = inst else {
+                unreachable!("instantiated with fresh variables")
+            };
+            if data_vars.contains(fresh.as_str()) {
+                continue;
+            }
+            let skolem = new_skolem(var);
+            if let MonoType::TyApp(app) = &skolem {
+                skolems.push((var.to_string(), app.func.clone()));
+            }
+            mapping.insert(fresh.clone(), skolem);
         }
+        let s = Substitution::from_map(mapping);
+        con.inst = con.inst.into_iter().map(|ty| ty.apply(&s)).collect();
+        let fields = fields.into_iter().map(|field| field.apply(&s)).collect();
+
+        Ok(Opened {
+            con,
+            skolems,
+            fields,
+            data_ty,
+        })
+    }
+
+    fn instantiate(&mut self, name: String, scheme: &PolyType) -> (TypedVar, MonoType) {
+        let (inst, ty) = scheme.instantiate_fresh();
+        self.preds.extend(scheme.instantiate_preds(&inst));
+        let var = TypedVar {
+            name,
+            scheme: scheme.clone(),
+            inst,
+        };
+
+        (var, ty)
+    }
+
+    /// Generalises the type of a `let`, qualified with the simplified predicates
+    /// collected since `mark` that mention its quantified variables. The others are
+    /// left for an enclosing `let` to solve, the ambiguous ones, whose variables are
+    /// neither in the type nor in `ctx`, for the top level to default. Unless the
+    /// bound expression is a `value`, the value restriction applies.
+    fn generalise(
+        &mut self,
+        ty: &MonoType,
+        ctx: &Context,
+        value: bool,
+        mark: usize,
+        s: &Substitution,
+    ) -> anyhow::Result<PolyType> {
+        let preds = self.preds.split_off(mark);
+        let preds = self
+            .classes
+            .reduce(preds.into_iter().map(|p| p.apply(s)).collect())?;
+        let generalise = match value {
+            true => MonoType::generalise_with,
+            false => MonoType::generalise_relaxed,
+        };
+        let scheme = generalise(ty, vec![], ctx);
+        let (bounded, _) = scheme.split();
+        let (retained, deferred): (Vec<_>, Vec<_>) = preds
+            .into_iter()
+            .partition(|p| p.ty.free_vars().iter().any(|v| bounded.contains(v)));
+        self.preds.extend(deferred);
+
+        Ok(generalise(ty, retained, ctx))
+    }
+}
+
+/// The scheme of an annotation, which must only have quantifiers in front. Its free
+/// variables are quantified as well.
+fn prenex(ty: &FType) -> anyhow::Result<PolyType> {
+    let mut body = ty;
+    while let FType::Forall(_, inner) = body {
+        body = inner;
     }
+    let mono = MonoType::try_from(body).with_context(|| {
+        format!("`{ty}` is of higher rank, only the bidirectional checker handles it")
+    })?;
+    let ctx = Context {
+        constrains: Default::default(),
+    };
+
+    Ok(mono.generalise(&ctx))
+}
+
+/// Whether the type constructor `con` occurs in `ty`.
+fn mentions(ty: &MonoType, con: &str) -> bool {
+    match ty {
+        MonoType::TyVar(_) => false,
+        MonoType::TyApp(app) => app.func == con || app.monotypes.iter().any(|m| mentions(m, con)),
+        MonoType::TyVarApp(app) => app.monotypes.iter().any(|m| mentions(m, con)),
+    }
+}
+
+fn typed(node: TypedNode, ty: MonoType) -> TypedExpr {
+    TypedExpr { node, ty }
 }
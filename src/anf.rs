@@ -0,0 +1,869 @@
+//! A typed A-normal form: every intermediate result is named by a `let`, functions
+//! and arguments are atoms, and every binder carries its type.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::lift::NameSupply;
+use crate::models::*;
+use crate::pretty::{Charset, TypePrinter};
+use crate::utils::*;
+
+/// A variable or constructor, with the types instantiating its type scheme, outermost
+/// quantifier first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub inst: Vec<MonoType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    Lit(Literal),
+    Var(Ident),
+    Con(Ident),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comp {
+    Atom(Atom),
+    App(Atom, Atom),
+    Abs(Box<Lambda>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    pub arg: String,
+    pub arg_ty: MonoType,
+    pub body: Term,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub var: String,
+    pub ty: PolyType,
+    pub value: Comp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Let(Binding, Box<Term>),
+    /// Binds a lambda that can refer to itself.
+    LetRec(Binding, Box<Term>),
+    Comp(Comp),
+}
+
+/// Translates `expr` to ANF, inferring the types of its binders and instantiations.
+///
+/// Binders are renamed to be unique. `letrec` may only bind lambdas, or values that
+/// don't refer to themselves.
+pub fn from_expr(ctx: &Context, expr: &Expr) -> anyhow::Result<Term> {
+    let mut names = NameSupply::new(expr);
+    let expr = names.rename(expr, &HashMap::new());
+
+    let mut translator = Translator {
+        names,
+        subst: Substitution::empty(),
+    };
+    let (term, _) = translator.term(ctx.clone(), &expr)?;
+
+    Ok(term.apply(&translator.subst))
+}
+
+enum Decl {
+    Let(Binding),
+    LetRec(Binding),
+}
+
+/// Translates and infers at once, keeping a single substitution that is applied to
+/// the whole term at the end.
+struct Translator {
+    names: NameSupply,
+    subst: Substitution,
+}
+
+impl Translator {
+    fn term(&mut self, mut ctx: Context, expr: &Expr) -> anyhow::Result<(Term, MonoType)> {
+        let mut decls = vec![];
+        let (comp, ty) = self.comp(&mut ctx, expr, &mut decls)?;
+        let term = decls
+            .into_iter()
+            .rev()
+            .fold(Term::Comp(comp), |body, decl| match decl {
+                Decl::Let(binding) => Term::Let(binding, Box::new(body)),
+                Decl::LetRec(binding) => Term::LetRec(binding, Box::new(body)),
+            });
+
+        Ok((term, ty))
+    }
+
+    /// Translates `expr` to a computation, pushing the bindings it needs to `decls` and
+    /// their variables to `ctx`.
+    fn comp(
+        &mut self,
+        ctx: &mut Context,
+        expr: &Expr,
+        decls: &mut Vec<Decl>,
+    ) -> anyhow::Result<(Comp, MonoType)> {
+        match expr {
+            Expr::Lit(lit) => Ok((Comp::Atom(Atom::Lit(lit.clone())), lit.ty())),
+            // annotations were checked by inference, ANF only keeps what it infers
+            Expr::Ann(ann) => self.comp(ctx, &ann.expr, decls),
+            Expr::Case(case) => anyhow::bail!("`case` has no ANF translation yet: {case}"),
+            Expr::Var(var) => match ctx.constrains.get(var) {
+                Some(poly) => {
+                    let (ident, ty) = instantiate(var, poly);
+                    Ok((Comp::Atom(Atom::Var(ident)), ty))
+                }
+                None => anyhow::bail!("Undefiend variable: {var}"),
+            },
+            Expr::Con(con) => match ctx.constrains.get(con) {
+                Some(poly) => {
+                    let (ident, ty) = instantiate(con, poly);
+                    Ok((Comp::Atom(Atom::Con(ident)), ty))
+                }
+                None => anyhow::bail!("Undefined constructor: {con}"),
+            },
+            Expr::Abs(abs) => {
+                let beta = new_type_var();
+                let inner = ctx.extend_with(abs.arg.clone(), PolyType::Mono(beta.clone()));
+                let (body, ty) = self.term(inner, &abs.body)?;
+                let lambda = Lambda {
+                    arg: abs.arg.clone(),
+                    arg_ty: beta.clone(),
+                    body,
+                };
+
+                Ok((Comp::Abs(Box::new(lambda)), MonoType::arrow(beta, ty)))
+            }
+            Expr::App(app) => {
+                let (fun, fun_ty) = self.atom(ctx, &app.fun, decls)?;
+                let (arg, arg_ty) = self.atom(ctx, &app.arg, decls)?;
+                let beta = new_type_var();
+                self.unify(fun_ty, MonoType::arrow(arg_ty, beta.clone()))?;
+
+                Ok((Comp::App(fun, arg), beta))
+            }
+            Expr::Let(elet) => {
+                let (value, ty) = self.comp(ctx, &elet.vexpr, decls)?;
+                let ty = self.generalise(ty, ctx, elet.vexpr.is_value());
+                ctx.constrains.insert(elet.var.clone(), ty.clone());
+                decls.push(Decl::Let(Binding {
+                    var: elet.var.clone(),
+                    ty,
+                    value,
+                }));
+
+                self.comp(ctx, &elet.aexpr, decls)
+            }
+            Expr::LetRec(elet) if !elet.vexpr.free_vars().contains(elet.var.as_str()) => {
+                let elet = Expr::Let(elet.clone());
+                self.comp(ctx, &elet, decls)
+            }
+            Expr::LetRec(elet) => {
+                if !matches!(elet.vexpr, Expr::Abs(_)) {
+                    anyhow::bail!("Only functions can be recursive in ANF: {}", elet.var);
+                }
+                let beta = new_type_var();
+                let mut inner = ctx.extend_with(elet.var.clone(), PolyType::Mono(beta.clone()));
+                let (value, ty) = self.comp(&mut inner, &elet.vexpr, &mut vec![])?;
+                self.unify(beta, ty.clone())?;
+                let ty = self.generalise(ty, ctx, true);
+                ctx.constrains.insert(elet.var.clone(), ty.clone());
+                decls.push(Decl::LetRec(Binding {
+                    var: elet.var.clone(),
+                    ty,
+                    value,
+                }));
+
+                self.comp(ctx, &elet.aexpr, decls)
+            }
+        }
+    }
+
+    /// Translates `expr` to an atom, binding it to a fresh variable if it isn't one.
+    fn atom(
+        &mut self,
+        ctx: &mut Context,
+        expr: &Expr,
+        decls: &mut Vec<Decl>,
+    ) -> anyhow::Result<(Atom, MonoType)> {
+        match self.comp(ctx, expr, decls)? {
+            (Comp::Atom(atom), ty) => Ok((atom, ty)),
+            (value, ty) => {
+                let var = self.names.fresh("tmp");
+                let poly = self.generalise(ty, ctx, matches!(value, Comp::Abs(_)));
+                let (ident, ty) = instantiate(&var, &poly);
+                ctx.constrains.insert(var.clone(), poly.clone());
+                decls.push(Decl::Let(Binding {
+                    var,
+                    ty: poly,
+                    value,
+                }));
+
+                Ok((Atom::Var(ident), ty))
+            }
+        }
+    }
+
+    fn unify(&mut self, ty1: MonoType, ty2: MonoType) -> anyhow::Result<()> {
+        let s = unify(ty1.apply(&self.subst), ty2.apply(&self.subst))?;
+        self.subst = s.combine(std::mem::replace(&mut self.subst, Substitution::empty()));
+
+        Ok(())
+    }
+
+    /// Generalises like inference does, with the value restriction unless the bound
+    /// expression is a `value`.
+    fn generalise(&self, ty: MonoType, ctx: &Context, value: bool) -> PolyType {
+        let ty = ty.apply(&self.subst);
+        let ctx = ctx.clone().apply(&self.subst);
+        match value {
+            true => ty.generalise(&ctx),
+            false => ty.generalise_relaxed(vec![], &ctx),
+        }
+    }
+}
+
+fn instantiate(name: &str, poly: &PolyType) -> (Ident, MonoType) {
+    let (inst, ty) = poly.instantiate_fresh();
+    let ident = Ident {
+        name: name.to_string(),
+        inst,
+    };
+
+    (ident, ty)
+}
+
+/// Type checks a term against the annotations it carries, without any inference,
+/// and checks that no variable is bound twice.
+pub fn check(ctx: &Context, term: &Term) -> anyhow::Result<MonoType> {
+    Checker {
+        bound: HashSet::new(),
+    }
+    .term(ctx.clone(), term)
+}
+
+struct Checker {
+    bound: HashSet<String>,
+}
+
+impl Checker {
+    fn term(&mut self, ctx: Context, term: &Term) -> anyhow::Result<MonoType> {
+        match term {
+            Term::Let(binding, body) => {
+                self.bind(&binding.var)?;
+                let ty = self.comp(ctx.clone(), &binding.value)?;
+                self.check_binding(&ctx, binding, ty)?;
+                self.term(
+                    ctx.extend_with(binding.var.clone(), binding.ty.clone()),
+                    body,
+                )
+            }
+            Term::LetRec(binding, body) => {
+                self.bind(&binding.var)?;
+                if !matches!(binding.value, Comp::Abs(_)) {
+                    anyhow::bail!("`{}` is recursive but not a function", binding.var);
+                }
+                let (_, mono) = binding.ty.split();
+                let rec_ctx = ctx.extend_with(binding.var.clone(), PolyType::Mono(mono.clone()));
+                let ty = self.comp(rec_ctx, &binding.value)?;
+                self.check_binding(&ctx, binding, ty)?;
+                self.term(
+                    ctx.extend_with(binding.var.clone(), binding.ty.clone()),
+                    body,
+                )
+            }
+            Term::Comp(comp) => self.comp(ctx, comp),
+        }
+    }
+
+    fn comp(&mut self, ctx: Context, comp: &Comp) -> anyhow::Result<MonoType> {
+        match comp {
+            Comp::Atom(atom) => atom_type(&ctx, atom),
+            Comp::App(fun, arg) => {
+                let fun_ty = atom_type(&ctx, fun)?;
+                let arg_ty = atom_type(&ctx, arg)?;
+                match fun_ty {
+                    MonoType::TyApp(app) if app.func == "->" && app.monotypes[0] == arg_ty => {
+                        Ok(app.monotypes[1].clone())
+                    }
+                    _ => anyhow::bail!("Could not apply `{fun}: {fun_ty}` to `{arg}: {arg_ty}`"),
+                }
+            }
+            Comp::Abs(lambda) => {
+                self.bind(&lambda.arg)?;
+                let arg_ty = PolyType::Mono(lambda.arg_ty.clone());
+                let ty = self.term(ctx.extend_with(lambda.arg.clone(), arg_ty), &lambda.body)?;
+
+                Ok(MonoType::arrow(lambda.arg_ty.clone(), ty))
+            }
+        }
+    }
+
+    /// Checks that `ty`, the type of the value, generalises to the annotation.
+    fn check_binding(&self, ctx: &Context, binding: &Binding, ty: MonoType) -> anyhow::Result<()> {
+        let (bounded, mono) = binding.ty.split();
+        if *mono != ty {
+            anyhow::bail!(
+                "`{}` is annotated with `{}` but has type `{ty}`",
+                binding.var,
+                print_poly(&binding.ty)
+            );
+        }
+        let ctx_free_vars = ctx.free_vars();
+        if let Some(var) = bounded.iter().find(|var| ctx_free_vars.contains(*var)) {
+            anyhow::bail!("`{}` can't be generalised over `{var}`", binding.var);
+        }
+
+        Ok(())
+    }
+
+    fn bind(&mut self, var: &str) -> anyhow::Result<()> {
+        if !self.bound.insert(var.to_string()) {
+            anyhow::bail!("`{var}` is bound more than once");
+        }
+
+        Ok(())
+    }
+}
+
+fn atom_type(ctx: &Context, atom: &Atom) -> anyhow::Result<MonoType> {
+    let ident = match atom {
+        Atom::Lit(lit) => return Ok(lit.ty()),
+        Atom::Var(ident) | Atom::Con(ident) => ident,
+    };
+    let Some(poly) = ctx.constrains.get(&ident.name) else {
+        anyhow::bail!("Undefiend variable: {}", ident.name);
+    };
+    let (bounded, _) = poly.split();
+    if bounded.len() != ident.inst.len() {
+        anyhow::bail!(
+            "`{}` is instantiated with {} types but `{}` has {} quantifiers",
+            ident.name,
+            ident.inst.len(),
+            print_poly(poly),
+            bounded.len()
+        );
+    }
+
+    Ok(poly.instantiate_with(&ident.inst))
+}
+
+impl Term {
+    /// Erases the types, giving back an expression.
+    pub fn to_expr(&self) -> Expr {
+        match self {
+            Term::Let(binding, body) | Term::LetRec(binding, body) => {
+                let elet = Box::new(LetExpr {
+                    var: binding.var.clone(),
+                    vexpr: binding.value.to_expr(),
+                    aexpr: body.to_expr(),
+                });
+                match self {
+                    Term::LetRec(..) => Expr::LetRec(elet),
+                    _ => Expr::Let(elet),
+                }
+            }
+            Term::Comp(comp) => comp.to_expr(),
+        }
+    }
+
+    /// Whether `var` occurs in the term.
+    pub fn mentions(&self, var: &str) -> bool {
+        match self {
+            Term::Let(binding, body) | Term::LetRec(binding, body) => {
+                binding.value.mentions(var) || body.mentions(var)
+            }
+            Term::Comp(comp) => comp.mentions(var),
+        }
+    }
+}
+
+impl Comp {
+    pub fn to_expr(&self) -> Expr {
+        match self {
+            Comp::Atom(atom) => atom.to_expr(),
+            Comp::App(fun, arg) => Expr::App(Box::new(AppExpr {
+                fun: fun.to_expr(),
+                arg: arg.to_expr(),
+            })),
+            Comp::Abs(lambda) => Expr::Abs(Box::new(AbsExpr {
+                arg: lambda.arg.clone(),
+                body: lambda.body.to_expr(),
+            })),
+        }
+    }
+
+    fn mentions(&self, var: &str) -> bool {
+        match self {
+            Comp::Atom(atom) => atom.mentions(var),
+            Comp::App(fun, arg) => fun.mentions(var) || arg.mentions(var),
+            Comp::Abs(lambda) => lambda.body.mentions(var),
+        }
+    }
+}
+
+impl Atom {
+    pub fn to_expr(&self) -> Expr {
+        match self {
+            Atom::Lit(lit) => Expr::Lit(lit.clone()),
+            Atom::Var(ident) => Expr::Var(ident.name.clone()),
+            Atom::Con(ident) => Expr::Con(ident.name.clone()),
+        }
+    }
+
+    fn mentions(&self, var: &str) -> bool {
+        matches!(self, Atom::Var(ident) if ident.name == var)
+    }
+}
+
+impl Subst for Ident {
+    fn apply(self, s: &Substitution) -> Self {
+        let inst = self.inst.into_iter().map(|ty| ty.apply(s)).collect();
+        Ident { inst, ..self }
+    }
+}
+
+impl Subst for Atom {
+    fn apply(self, s: &Substitution) -> Self {
+        match self {
+            Atom::Lit(lit) => Atom::Lit(lit),
+            Atom::Var(ident) => Atom::Var(ident.apply(s)),
+            Atom::Con(ident) => Atom::Con(ident.apply(s)),
+        }
+    }
+}
+
+impl Subst for Comp {
+    fn apply(self, s: &Substitution) -> Self {
+        match self {
+            Comp::Atom(atom) => Comp::Atom(atom.apply(s)),
+            Comp::App(fun, arg) => Comp::App(fun.apply(s), arg.apply(s)),
+            Comp::Abs(lambda) => Comp::Abs(Box::new(Lambda {
+                arg: lambda.arg,
+                arg_ty: lambda.arg_ty.apply(s),
+                body: lambda.body.apply(s),
+            })),
+        }
+    }
+}
+
+impl Subst for Binding {
+    fn apply(self, s: &Substitution) -> Self {
+        Binding {
+            var: self.var,
+            ty: self.ty.apply(s),
+            value: self.value.apply(s),
+        }
+    }
+}
+
+impl Subst for Term {
+    fn apply(self, s: &Substitution) -> Self {
+        match self {
+            Term::Let(binding, body) => Term::Let(binding.apply(s), Box::new(body.apply(s))),
+            Term::LetRec(binding, body) => Term::LetRec(binding.apply(s), Box::new(body.apply(s))),
+            Term::Comp(comp) => Term::Comp(comp.apply(s)),
+        }
+    }
+}
+
+/// Optimises a term until it doesn't change anymore, the result has the same type.
+pub fn optimise(mut term: Term) -> Term {
+    loop {
+        let next = remove_dead_lets(inline(fold_constants(term.clone())));
+        if next == term {
+            return term;
+        }
+        term = next;
+    }
+}
+
+/// Replaces variables bound to atoms by those atoms, and inlines functions that are
+/// only used once, by a call.
+pub fn inline(term: Term) -> Term {
+    let mut inliner = Inliner::default();
+    inliner.count(&term);
+    inliner.term(term)
+}
+
+#[derive(Default)]
+struct Inliner {
+    uses: HashMap<String, usize>,
+    /// How many times a variable is called in a way that can be inlined: its result
+    /// is returned or bound monomorphically.
+    calls: HashMap<String, usize>,
+    atoms: HashMap<String, (PolyType, Atom)>,
+    lambdas: HashMap<String, (PolyType, Lambda)>,
+}
+
+impl Inliner {
+    fn count(&mut self, term: &Term) {
+        let mut call = |comp: &Comp| {
+            if let Comp::App(Atom::Var(fun), _) = comp {
+                *self.calls.entry(fun.name.clone()).or_default() += 1;
+            }
+        };
+        match term {
+            Term::Let(binding, body) | Term::LetRec(binding, body) => {
+                if matches!(binding.ty, PolyType::Mono(_)) {
+                    call(&binding.value);
+                }
+                self.count_comp(&binding.value);
+                self.count(body);
+            }
+            Term::Comp(comp) => {
+                call(comp);
+                self.count_comp(comp);
+            }
+        }
+    }
+
+    fn count_comp(&mut self, comp: &Comp) {
+        let atoms = match comp {
+            Comp::Atom(atom) => vec![atom],
+            Comp::App(fun, arg) => vec![fun, arg],
+            Comp::Abs(lambda) => return self.count(&lambda.body),
+        };
+        for atom in atoms {
+            if let Atom::Var(ident) = atom {
+                *self.uses.entry(ident.name.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    fn term(&mut self, term: Term) -> Term {
+        match term {
+            Term::Let(binding, body) => match binding.value {
+                Comp::Atom(atom) => {
+                    let atom = self.atom(atom);
+                    self.atoms.insert(binding.var, (binding.ty, atom));
+                    self.term(*body)
+                }
+                Comp::Abs(lambda) if self.inlinable(&binding.var) => {
+                    self.lambdas.insert(binding.var, (binding.ty, *lambda));
+                    self.term(*body)
+                }
+                value => match self.comp(value) {
+                    Comp::App(Atom::Var(fun), arg)
+                        if self.lambdas.contains_key(&fun.name)
+                            && matches!(binding.ty, PolyType::Mono(_)) =>
+                    {
+                        let value = self.beta(fun, arg);
+                        let body = self.term(*body);
+                        splice(value, binding.var, binding.ty, body)
+                    }
+                    value => {
+                        let binding = Binding { value, ..binding };
+                        Term::Let(binding, Box::new(self.term(*body)))
+                    }
+                },
+            },
+            Term::LetRec(binding, body) => {
+                let value = self.comp(binding.value);
+                let binding = Binding { value, ..binding };
+                Term::LetRec(binding, Box::new(self.term(*body)))
+            }
+            Term::Comp(comp) => match self.comp(comp) {
+                Comp::App(Atom::Var(fun), arg) if self.lambdas.contains_key(&fun.name) => {
+                    self.beta(fun, arg)
+                }
+                comp => Term::Comp(comp),
+            },
+        }
+    }
+
+    fn inlinable(&self, var: &str) -> bool {
+        self.uses.get(var) == Some(&1) && self.calls.get(var) == Some(&1)
+    }
+
+    /// The body of the lambda bound to `fun`, instantiated and applied to `arg`.
+    fn beta(&mut self, fun: Ident, arg: Atom) -> Term {
+        let (poly, lambda) = self.lambdas.remove(&fun.name).unwrap();
+        let (bounded, _) = poly.split();
+        let mapping = bounded.into_iter().map(str::to_string).zip(fun.inst);
+        let s = Substitution::from_map(mapping.collect());
+
+        let arg_ty = PolyType::Mono(lambda.arg_ty.apply(&s));
+        self.atoms.insert(lambda.arg, (arg_ty, arg));
+        self.term(lambda.body.apply(&s))
+    }
+
+    fn comp(&mut self, comp: Comp) -> Comp {
+        match comp {
+            Comp::Atom(atom) => Comp::Atom(self.atom(atom)),
+            Comp::App(fun, arg) => Comp::App(self.atom(fun), self.atom(arg)),
+            Comp::Abs(lambda) => Comp::Abs(Box::new(Lambda {
+                body: self.term(lambda.body),
+                ..*lambda
+            })),
+        }
+    }
+
+    fn atom(&self, atom: Atom) -> Atom {
+        let Atom::Var(ident) = &atom else {
+            return atom;
+        };
+        let Some((poly, replacement)) = self.atoms.get(&ident.name) else {
+            return atom;
+        };
+
+        let (bounded, _) = poly.split();
+        let mapping = bounded.into_iter().map(str::to_string);
+        let s = Substitution::from_map(mapping.zip(ident.inst.clone()).collect());
+        replacement.clone().apply(&s)
+    }
+}
+
+/// Binds the result of `term` to `var` around `body`.
+fn splice(term: Term, var: String, ty: PolyType, body: Term) -> Term {
+    match term {
+        Term::Let(binding, rest) => Term::Let(binding, Box::new(splice(*rest, var, ty, body))),
+        Term::LetRec(binding, rest) => {
+            Term::LetRec(binding, Box::new(splice(*rest, var, ty, body)))
+        }
+        Term::Comp(value) => Term::Let(Binding { var, ty, value }, Box::new(body)),
+    }
+}
+
+/// Evaluates applications of the integer builtins to literals.
+pub fn fold_constants(term: Term) -> Term {
+    let mut folder = Folder {
+        bound: binders(&term),
+        added: HashMap::new(),
+    };
+    folder.term(term)
+}
+
+struct Folder {
+    /// Variables bound in the term, which shadow the builtins.
+    bound: HashSet<String>,
+    /// Variables bound to `add n`.
+    added: HashMap<String, i64>,
+}
+
+impl Folder {
+    fn term(&mut self, term: Term) -> Term {
+        match term {
+            Term::Let(binding, body) => {
+                let value = self.comp(binding.value);
+                if let Comp::App(Atom::Var(fun), Atom::Lit(Literal::Int(n))) = &value {
+                    if self.is_builtin(fun, "add") {
+                        self.added.insert(binding.var.clone(), *n);
+                    }
+                }
+                let binding = Binding { value, ..binding };
+                Term::Let(binding, Box::new(self.term(*body)))
+            }
+            Term::LetRec(binding, body) => {
+                let binding = Binding {
+                    value: self.comp(binding.value),
+                    ..binding
+                };
+                Term::LetRec(binding, Box::new(self.term(*body)))
+            }
+            Term::Comp(comp) => Term::Comp(self.comp(comp)),
+        }
+    }
+
+    fn comp(&mut self, comp: Comp) -> Comp {
+        match comp {
+            Comp::App(Atom::Var(fun), Atom::Lit(Literal::Int(n))) => {
+                let result = if self.is_builtin(&fun, "inc") {
+                    Some(n.wrapping_add(1))
+                } else if self.is_builtin(&fun, "dec") {
+                    Some(n.wrapping_sub(1))
+                } else {
+                    self.added.get(&fun.name).map(|m| m.wrapping_add(n))
+                };
+                match result {
+                    Some(result) => Comp::Atom(Atom::Lit(Literal::Int(result))),
+                    None => Comp::App(Atom::Var(fun), Atom::Lit(Literal::Int(n))),
+                }
+            }
+            Comp::Abs(lambda) => Comp::Abs(Box::new(Lambda {
+                body: self.term(lambda.body),
+                ..*lambda
+            })),
+            comp => comp,
+        }
+    }
+
+    fn is_builtin(&self, ident: &Ident, name: &str) -> bool {
+        ident.name == name && !self.bound.contains(name)
+    }
+}
+
+/// Removes the bindings that are never used and whose evaluation can't fail or loop.
+pub fn remove_dead_lets(term: Term) -> Term {
+    DeadLets {
+        bound: binders(&term),
+    }
+    .term(term)
+}
+
+struct DeadLets {
+    bound: HashSet<String>,
+}
+
+impl DeadLets {
+    fn term(&self, term: Term) -> Term {
+        match term {
+            Term::Let(binding, body) | Term::LetRec(binding, body)
+                if self.is_value(&binding.value) && !body.mentions(&binding.var) =>
+            {
+                self.term(*body)
+            }
+            Term::Let(binding, body) => {
+                let binding = Binding {
+                    value: self.comp(binding.value),
+                    ..binding
+                };
+                Term::Let(binding, Box::new(self.term(*body)))
+            }
+            Term::LetRec(binding, body) => {
+                let binding = Binding {
+                    value: self.comp(binding.value),
+                    ..binding
+                };
+                Term::LetRec(binding, Box::new(self.term(*body)))
+            }
+            Term::Comp(comp) => Term::Comp(self.comp(comp)),
+        }
+    }
+
+    fn comp(&self, comp: Comp) -> Comp {
+        match comp {
+            Comp::Abs(lambda) => Comp::Abs(Box::new(Lambda {
+                body: self.term(lambda.body),
+                ..*lambda
+            })),
+            comp => comp,
+        }
+    }
+
+    /// Atoms, lambdas and partial applications of constructors are values. So are
+    /// partial applications of builtins, unless a variable shadows them.
+    fn is_value(&self, comp: &Comp) -> bool {
+        match comp {
+            Comp::Atom(_) | Comp::Abs(_) | Comp::App(Atom::Con(_), _) => true,
+            Comp::App(Atom::Var(fun), _) if !self.bound.contains(&fun.name) => {
+                crate::prelude::BUILTINS
+                    .iter()
+                    .any(|b| b.name == fun.name && b.arity > 1)
+            }
+            Comp::App(..) => false,
+        }
+    }
+}
+
+/// The variables bound anywhere in a term.
+fn binders(term: &Term) -> HashSet<String> {
+    fn collect(term: &Term, bound: &mut HashSet<String>) {
+        let comp = match term {
+            Term::Let(binding, body) | Term::LetRec(binding, body) => {
+                bound.insert(binding.var.clone());
+                collect(body, bound);
+                &binding.value
+            }
+            Term::Comp(comp) => comp,
+        };
+        if let Comp::Abs(lambda) = comp {
+            bound.insert(lambda.arg.clone());
+            collect(&lambda.body, bound);
+        }
+    }
+
+    let mut bound = HashSet::new();
+    collect(term, &mut bound);
+    bound
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_term(f, self, 0)
+    }
+}
+
+fn write_term(f: &mut std::fmt::Formatter<'_>, term: &Term, indent: usize) -> std::fmt::Result {
+    match term {
+        Term::Let(binding, body) | Term::LetRec(binding, body) => {
+            let keyword = if matches!(term, Term::LetRec(..)) {
+                "letrec"
+            } else {
+                "let"
+            };
+            write!(
+                f,
+                "{keyword} {} : {} = ",
+                binding.var,
+                print_poly(&binding.ty)
+            )?;
+            write_comp(f, &binding.value, indent)?;
+            write!(f, " in\n{:indent$}", "")?;
+            write_term(f, body, indent)
+        }
+        Term::Comp(comp) => write_comp(f, comp, indent),
+    }
+}
+
+fn write_comp(f: &mut std::fmt::Formatter<'_>, comp: &Comp, indent: usize) -> std::fmt::Result {
+    match comp {
+        Comp::Atom(atom) => write!(f, "{atom}"),
+        Comp::App(fun, arg) if is_simple(arg) => write!(f, "{fun} {arg}"),
+        Comp::App(fun, arg) => write!(f, "{fun} ({arg})"),
+        Comp::Abs(lambda) => {
+            write!(f, "\\({} : {}) ->", lambda.arg, lambda.arg_ty)?;
+            match &lambda.body {
+                Term::Comp(comp) => {
+                    write!(f, " ")?;
+                    write_comp(f, comp, indent)
+                }
+                body => {
+                    let indent = indent + 2;
+                    write!(f, "\n{:indent$}", "")?;
+                    write_term(f, body, indent)
+                }
+            }
+        }
+    }
+}
+
+/// Whether an atom can be printed as an argument without parentheses.
+fn is_simple(atom: &Atom) -> bool {
+    match atom {
+        Atom::Lit(Literal::Int(i)) => *i >= 0,
+        Atom::Lit(Literal::Float(x)) => x.is_sign_positive(),
+        Atom::Lit(_) => true,
+        Atom::Var(ident) | Atom::Con(ident) => ident.inst.is_empty(),
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Atom::Lit(lit) => write!(f, "{lit}"),
+            Atom::Var(ident) | Atom::Con(ident) => write!(f, "{ident}"),
+        }
+    }
+}
+
+impl Display for Ident {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for ty in &self.inst {
+            if ty.is_atom() {
+                write!(f, " @{ty}")?;
+            } else {
+                write!(f, " @({ty})")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn print_poly(poly: &PolyType) -> String {
+    TypePrinter::new(Charset::Unicode, false).print_poly(poly)
+}
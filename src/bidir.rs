@@ -0,0 +1,653 @@
+//! Bidirectional type checking of higher-rank types, after Dunfield and Krishnaswami's
+//! "Complete and Easy Bidirectional Typechecking for Higher-Rank Polymorphism".
+//!
+//! Quantifiers may appear anywhere in a type but are only ever instantiated with
+//! monotypes. A lambda is checked against the type it is expected to have, so the
+//! argument of a function of type `(∀s. ST s a) -> a` can be used polymorphically
+//! without more ado. Annotations `(e : σ)` are only needed where no such type is known,
+//! typically for a lambda that uses its own argument polymorphically.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::models::*;
+use crate::system_f::FType;
+
+/// The types of the variables in scope, of any rank.
+pub type TypeEnv = HashMap<String, FType>;
+
+/// The schemes of `ctx` as types, they are all of rank 1.
+pub fn type_env(ctx: &Context) -> TypeEnv {
+    ctx.constrains
+        .iter()
+        .map(|(name, poly)| (name.clone(), poly.into()))
+        .collect()
+}
+
+/// Infers the type of `expr`, quantified over what is left unknown.
+pub fn infer(env: &TypeEnv, expr: &Expr) -> anyhow::Result<FType> {
+    let mut checker = Checker::new(env);
+    let ty = checker.synth(expr)?;
+    let ty = checker.apply(&ty);
+    let exists = ty.exists();
+
+    Ok((&quantify(ty, &exists)).into())
+}
+
+/// Checks that `expr` has the type `ty`, whose free variables are quantified.
+pub fn check(env: &TypeEnv, expr: &Expr, ty: &FType) -> anyhow::Result<()> {
+    let mut checker = Checker::new(env);
+    checker.check(expr, &close(ty)?)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    /// A universal variable, rigid where it is in scope.
+    Var(String),
+    /// An existential variable `^n`, that stands for a monotype yet to be found.
+    Exist(usize),
+    Con(String, Vec<Type>),
+    Forall(String, Box<Type>),
+}
+
+/// Type variables of higher kinds are left to Algorithm W: solving `^f a` would need
+/// higher-order unification.
+impl TryFrom<&FType> for Type {
+    type Error = anyhow::Error;
+
+    fn try_from(ty: &FType) -> Result<Self, Self::Error> {
+        match ty {
+            FType::Var(var) => Ok(Type::Var(var.clone())),
+            FType::Con(con, args) => Ok(Type::Con(
+                con.clone(),
+                args.iter().map(Type::try_from).collect::<Result<_, _>>()?,
+            )),
+            FType::VarApp(..) => anyhow::bail!(
+                "`{ty}` applies a type variable, only Algorithm W handles higher kinds"
+            ),
+            FType::Forall(var, body) => Ok(Type::Forall(
+                var.clone(),
+                Box::new(body.as_ref().try_into()?),
+            )),
+        }
+    }
+}
+
+impl From<&Type> for FType {
+    fn from(ty: &Type) -> Self {
+        match ty {
+            Type::Var(var) => FType::Var(var.clone()),
+            Type::Exist(n) => FType::Var(format!("^{n}")),
+            Type::Con(con, args) => FType::Con(con.clone(), args.iter().map(FType::from).collect()),
+            Type::Forall(var, body) => FType::Forall(var.clone(), Box::new(body.as_ref().into())),
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", FType::from(self))
+    }
+}
+
+impl Type {
+    fn arrow(from: Type, to: Type) -> Type {
+        Type::Con("->".to_string(), vec![from, to])
+    }
+
+    fn is_mono(&self) -> bool {
+        match self {
+            Type::Var(_) | Type::Exist(_) => true,
+            Type::Con(_, args) => args.iter().all(Type::is_mono),
+            Type::Forall(..) => false,
+        }
+    }
+
+    fn occurs(&self, n: usize) -> bool {
+        match self {
+            Type::Var(_) => false,
+            Type::Exist(m) => *m == n,
+            Type::Con(_, args) => args.iter().any(|arg| arg.occurs(n)),
+            Type::Forall(_, body) => body.occurs(n),
+        }
+    }
+
+    /// The existential variables, in order of first appearance.
+    fn exists(&self) -> Vec<usize> {
+        fn collect(ty: &Type, acc: &mut Vec<usize>) {
+            match ty {
+                Type::Var(_) => {}
+                Type::Exist(n) => {
+                    if !acc.contains(n) {
+                        acc.push(*n);
+                    }
+                }
+                Type::Con(_, args) => args.iter().for_each(|arg| collect(arg, acc)),
+                Type::Forall(_, body) => collect(body, acc),
+            }
+        }
+
+        let mut acc = vec![];
+        collect(self, &mut acc);
+        acc
+    }
+
+    /// Every name of a universal variable, bound or not.
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Type::Var(var) => vec![var],
+            Type::Exist(_) => vec![],
+            Type::Con(_, args) => args.iter().flat_map(Type::names).collect(),
+            Type::Forall(var, body) => {
+                let mut names = body.names();
+                names.push(var);
+                names
+            }
+        }
+    }
+
+    /// Substitutes `ty` for the universal `var`. Quantifiers are opened with fresh
+    /// names, so `ty` can't be captured.
+    fn subst(&self, var: &str, ty: &Type) -> Type {
+        match self {
+            Type::Var(v) if v == var => ty.clone(),
+            Type::Var(_) | Type::Exist(_) => self.clone(),
+            Type::Con(con, args) => {
+                Type::Con(con.clone(), args.iter().map(|a| a.subst(var, ty)).collect())
+            }
+            Type::Forall(bound, _) if bound == var => self.clone(),
+            Type::Forall(bound, body) => Type::Forall(bound.clone(), Box::new(body.subst(var, ty))),
+        }
+    }
+
+    /// Replaces existentials by the types `f` gives for them.
+    fn map_exists(&self, f: &impl Fn(usize) -> Option<Type>) -> Type {
+        match self {
+            Type::Exist(n) => f(*n).unwrap_or_else(|| self.clone()),
+            Type::Var(_) => self.clone(),
+            Type::Con(con, args) => {
+                Type::Con(con.clone(), args.iter().map(|a| a.map_exists(f)).collect())
+            }
+            Type::Forall(var, body) => Type::Forall(var.clone(), Box::new(body.map_exists(f))),
+        }
+    }
+
+    /// The existentials with an occurrence left of an odd number of arrows, or under a
+    /// type constructor other than `->` and `List`, see
+    /// [`MonoType::generalise_relaxed`].
+    fn non_covariant_exists(&self) -> Vec<usize> {
+        fn collect(ty: &Type, covariant: Option<bool>, acc: &mut Vec<usize>) {
+            match ty {
+                Type::Var(_) => {}
+                Type::Exist(n) => {
+                    if covariant != Some(true) {
+                        acc.push(*n);
+                    }
+                }
+                Type::Con(con, args) => {
+                    for (i, arg) in args.iter().enumerate() {
+                        let variance = match con.as_str() {
+                            "->" if i == 0 => covariant.map(|c| !c),
+                            "->" | "List" => covariant,
+                            _ => None,
+                        };
+                        collect(arg, variance, acc);
+                    }
+                }
+                Type::Forall(_, body) => collect(body, covariant, acc),
+            }
+        }
+
+        let mut acc = vec![];
+        collect(self, Some(true), &mut acc);
+        acc
+    }
+}
+
+/// Quantifies the type of an annotation over its free variables.
+fn close(ty: &FType) -> anyhow::Result<Type> {
+    fn collect<'a>(ty: &'a FType, bound: &mut Vec<&'a str>, acc: &mut Vec<&'a str>) {
+        match ty {
+            FType::Var(var) => {
+                if !bound.contains(&var.as_str()) && !acc.contains(&var.as_str()) {
+                    acc.push(var);
+                }
+            }
+            FType::Con(_, args) => args.iter().for_each(|arg| collect(arg, bound, acc)),
+            // rejected by the conversion below
+            FType::VarApp(..) => {}
+            FType::Forall(var, body) => {
+                bound.push(var);
+                collect(body, bound, acc);
+                bound.pop();
+            }
+        }
+    }
+
+    let mut free = vec![];
+    collect(ty, &mut vec![], &mut free);
+    let body = ty.try_into()?;
+    Ok(free.into_iter().rev().fold(body, |acc, var| {
+        Type::Forall(var.to_string(), Box::new(acc))
+    }))
+}
+
+/// Quantifies `ty` over the existentials `exists`, naming them apart from every
+/// variable of `ty`.
+fn quantify(ty: Type, exists: &[usize]) -> Type {
+    let taken = ty.names();
+    let mut names = (0..)
+        .map(|i| {
+            let letter = (b'a' + (i % 26) as u8) as char;
+            match i / 26 {
+                0 => letter.to_string(),
+                n => format!("{letter}{n}"),
+            }
+        })
+        .filter(|name| !taken.contains(&name.as_str()));
+    let named: Vec<_> = exists.iter().map(|n| (*n, names.next().unwrap())).collect();
+
+    let body = ty.map_exists(&|n| {
+        named
+            .iter()
+            .find(|(m, _)| *m == n)
+            .map(|(_, name)| Type::Var(name.clone()))
+    });
+    named
+        .into_iter()
+        .rev()
+        .fold(body, |acc, (_, name)| Type::Forall(name, Box::new(acc)))
+}
+
+/// An entry of the ordered context, which is where type variables are in scope and
+/// where existentials get solved.
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Var(String),
+    Exist(usize),
+    Solved(usize, Type),
+    /// `▶^n`, where the part of the context of a subderivation starts.
+    Marker(usize),
+    Ann(String, Type),
+}
+
+struct Checker<'a> {
+    env: &'a TypeEnv,
+    ctx: Vec<Entry>,
+    next: usize,
+}
+
+impl<'a> Checker<'a> {
+    fn new(env: &'a TypeEnv) -> Self {
+        Self {
+            env,
+            ctx: vec![],
+            next: 0,
+        }
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next
+    }
+
+    /// A name for a universal that no other can have, the lexer never reads `#`.
+    fn fresh_var(&mut self, var: &str) -> String {
+        format!("{var}#{}", self.fresh())
+    }
+
+    fn position(&self, entry: &Entry) -> usize {
+        self.ctx.iter().position(|e| e == entry).unwrap()
+    }
+
+    fn exist_position(&self, n: usize) -> usize {
+        self.ctx
+            .iter()
+            .position(|e| matches!(e, Entry::Exist(m) | Entry::Solved(m, _) if *m == n))
+            .unwrap()
+    }
+
+    /// Removes `entry` and everything after it, which is returned.
+    fn drop_from(&mut self, entry: &Entry) -> Vec<Entry> {
+        let position = self.position(entry);
+        let mut tail = self.ctx.split_off(position);
+        tail.remove(0);
+        tail
+    }
+
+    /// Substitutes the solved existentials.
+    fn apply(&self, ty: &Type) -> Type {
+        ty.map_exists(&|n| {
+            self.ctx.iter().find_map(|e| match e {
+                Entry::Solved(m, solution) if *m == n => Some(self.apply(solution)),
+                _ => None,
+            })
+        })
+    }
+
+    /// Whether all the variables of `ty` are in scope before `position`.
+    fn is_well_formed(&self, ty: &Type, position: usize) -> bool {
+        let prefix = &self.ctx[..position];
+        match ty {
+            Type::Var(var) => prefix.contains(&Entry::Var(var.clone())),
+            Type::Exist(n) => prefix
+                .iter()
+                .any(|e| matches!(e, Entry::Exist(m) | Entry::Solved(m, _) if m == n)),
+            Type::Con(_, args) => args.iter().all(|arg| self.is_well_formed(arg, position)),
+            Type::Forall(..) => false,
+        }
+    }
+
+    fn lookup(&self, var: &str) -> anyhow::Result<Type> {
+        let ann = self.ctx.iter().rev().find_map(|e| match e {
+            Entry::Ann(name, ty) if name == var => Some(ty.clone()),
+            _ => None,
+        });
+        match (ann, self.env.get(var)) {
+            (Some(ty), _) => Ok(ty),
+            (None, Some(ty)) => ty.try_into(),
+            (None, None) => anyhow::bail!("Undefiend variable: {var}"),
+        }
+    }
+
+    /// Solves the unsolved `^n` with `con ^n1 .. ^nk`, declaring the new existentials
+    /// right before it.
+    fn articulate(&mut self, n: usize, con: &str, arity: usize) -> Vec<usize> {
+        let exists: Vec<_> = (0..arity).map(|_| self.fresh()).collect();
+        let position = self.exist_position(n);
+        let solution = Type::Con(
+            con.to_string(),
+            exists.iter().map(|m| Type::Exist(*m)).collect(),
+        );
+        self.ctx[position] = Entry::Solved(n, solution);
+        for m in exists.iter().rev() {
+            self.ctx.insert(position, Entry::Exist(*m));
+        }
+
+        exists
+    }
+
+    /// `Γ ⊢ e ⇐ A ⊣ Δ`
+    fn check(&mut self, expr: &Expr, ty: &Type) -> anyhow::Result<()> {
+        match (expr, ty) {
+            (_, Type::Forall(var, body)) => {
+                let fresh = self.fresh_var(var);
+                let entry = Entry::Var(fresh.clone());
+                self.ctx.push(entry.clone());
+                self.check(expr, &body.subst(var, &Type::Var(fresh)))?;
+                self.drop_from(&entry);
+                Ok(())
+            }
+            (Expr::Abs(abs), Type::Con(con, args)) if con == "->" => {
+                let entry = Entry::Ann(abs.arg.clone(), args[0].clone());
+                self.ctx.push(entry.clone());
+                self.check(&abs.body, &args[1])?;
+                self.drop_from(&entry);
+                Ok(())
+            }
+            (Expr::Let(elet), _) | (Expr::LetRec(elet), _) => {
+                let entry = self.bind(elet, matches!(expr, Expr::LetRec(_)))?;
+                self.check(&elet.aexpr, ty)?;
+                self.unbind(&entry);
+                Ok(())
+            }
+            _ => {
+                let synthesised = self.synth(expr)?;
+                let (a, b) = (self.apply(&synthesised), self.apply(ty));
+                self.subtype(&a, &b)
+            }
+        }
+    }
+
+    /// `Γ ⊢ e ⇒ A ⊣ Δ`
+    fn synth(&mut self, expr: &Expr) -> anyhow::Result<Type> {
+        match expr {
+            Expr::Lit(lit) => (&FType::from(&lit.ty())).try_into(),
+            Expr::Var(var) => self.lookup(var),
+            Expr::Con(con) => match self.env.get(con) {
+                Some(ty) => ty.try_into(),
+                None => anyhow::bail!("Undefined constructor: {con}"),
+            },
+            Expr::Case(case) => {
+                anyhow::bail!("`{case}` matches a constructor, only Algorithm W handles `case`")
+            }
+            Expr::Ann(ann) => {
+                let ty = close(&ann.ty)?;
+                self.check(&ann.expr, &ty)?;
+                Ok(ty)
+            }
+            Expr::Abs(abs) => {
+                let (a, b) = (self.fresh(), self.fresh());
+                self.ctx.push(Entry::Exist(a));
+                self.ctx.push(Entry::Exist(b));
+                let entry = Entry::Ann(abs.arg.clone(), Type::Exist(a));
+                self.ctx.push(entry.clone());
+                self.check(&abs.body, &Type::Exist(b))?;
+                self.drop_from(&entry);
+                Ok(Type::arrow(Type::Exist(a), Type::Exist(b)))
+            }
+            Expr::App(app) => {
+                let fun = self.synth(&app.fun)?;
+                let fun = self.apply(&fun);
+                self.synth_app(&fun, &app.arg)
+            }
+            Expr::Let(elet) | Expr::LetRec(elet) => {
+                let entry = self.bind(elet, matches!(expr, Expr::LetRec(_)))?;
+                let ty = self.synth(&elet.aexpr)?;
+                self.unbind(&entry);
+                Ok(ty)
+            }
+        }
+    }
+
+    /// `Γ ⊢ A • e ⇒⇒ C ⊣ Δ`, the type of applying a function of type `A` to `arg`.
+    fn synth_app(&mut self, ty: &Type, arg: &Expr) -> anyhow::Result<Type> {
+        match ty {
+            Type::Forall(var, body) => {
+                let n = self.fresh();
+                self.ctx.push(Entry::Exist(n));
+                self.synth_app(&body.subst(var, &Type::Exist(n)), arg)
+            }
+            Type::Exist(n) => {
+                let exists = self.articulate(*n, "->", 2);
+                self.check(arg, &Type::Exist(exists[0]))?;
+                Ok(Type::Exist(exists[1]))
+            }
+            Type::Con(con, args) if con == "->" => {
+                self.check(arg, &args[0])?;
+                Ok(args[1].clone())
+            }
+            _ => anyhow::bail!("Could not apply `{ty}`, it is not a function"),
+        }
+    }
+
+    /// Binds the variable of a `let` to the type of its expression, generalised over
+    /// the existentials the expression left unsolved. Like in inference, only those
+    /// that occur in covariant positions alone are unless the expression is a value.
+    /// An annotated expression gets its annotation, which may be polymorphically
+    /// recursive.
+    fn bind(&mut self, elet: &LetExpr, recursive: bool) -> anyhow::Result<Entry> {
+        if let Expr::Ann(ann) = &elet.vexpr {
+            let ty = close(&ann.ty)?;
+            let entry = Entry::Ann(elet.var.clone(), ty.clone());
+            if recursive {
+                self.ctx.push(entry.clone());
+                self.check(&ann.expr, &ty)?;
+            } else {
+                self.check(&ann.expr, &ty)?;
+                self.ctx.push(entry.clone());
+            }
+            return Ok(entry);
+        }
+
+        let marker = Entry::Marker(self.fresh());
+        self.ctx.push(marker.clone());
+        let ty = if recursive {
+            let n = self.fresh();
+            self.ctx.push(Entry::Exist(n));
+            let entry = Entry::Ann(elet.var.clone(), Type::Exist(n));
+            self.ctx.push(entry.clone());
+            self.check(&elet.vexpr, &Type::Exist(n))?;
+            self.unbind(&entry);
+            Type::Exist(n)
+        } else {
+            self.synth(&elet.vexpr)?
+        };
+        let ty = self.apply(&ty);
+
+        let tail = self.drop_from(&marker);
+        let value = elet.vexpr.is_value();
+        let restricted = ty.non_covariant_exists();
+        let exists: Vec<_> = ty
+            .exists()
+            .into_iter()
+            .filter(|n| tail.contains(&Entry::Exist(*n)))
+            .filter(|n| value || !restricted.contains(n))
+            .collect();
+        if !value {
+            // the others are still to be solved by what uses the variable
+            self.ctx.extend(tail);
+        }
+
+        let entry = Entry::Ann(elet.var.clone(), quantify(ty, &exists));
+        self.ctx.push(entry.clone());
+        Ok(entry)
+    }
+
+    /// Takes a variable out of scope, leaving the existentials declared since.
+    fn unbind(&mut self, entry: &Entry) {
+        let position = self.position(entry);
+        self.ctx.remove(position);
+    }
+
+    /// `Γ ⊢ A <: B ⊣ Δ`, `A` is at least as polymorphic as `B`.
+    fn subtype(&mut self, a: &Type, b: &Type) -> anyhow::Result<()> {
+        match (a, b) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Exist(x), Type::Exist(y)) if x == y => Ok(()),
+            (Type::Con(c1, args1), Type::Con(c2, args2))
+                if c1 == c2 && args1.len() == args2.len() =>
+            {
+                if c1 == "->" {
+                    self.subtype(&args2[0], &args1[0])?;
+                    let (a2, b2) = (self.apply(&args1[1]), self.apply(&args2[1]));
+                    return self.subtype(&a2, &b2);
+                }
+                for (arg1, arg2) in args1.iter().zip(args2) {
+                    self.equate(arg1, arg2)?;
+                }
+                Ok(())
+            }
+            (_, Type::Forall(var, body)) => {
+                let fresh = self.fresh_var(var);
+                let entry = Entry::Var(fresh.clone());
+                self.ctx.push(entry.clone());
+                self.subtype(a, &body.subst(var, &Type::Var(fresh)))?;
+                self.drop_from(&entry);
+                Ok(())
+            }
+            (Type::Forall(var, body), _) => {
+                let n = self.fresh();
+                let marker = Entry::Marker(n);
+                self.ctx.push(marker.clone());
+                self.ctx.push(Entry::Exist(n));
+                self.subtype(&body.subst(var, &Type::Exist(n)), b)?;
+                self.drop_from(&marker);
+                Ok(())
+            }
+            (Type::Exist(n), _) if !b.occurs(*n) => self.instantiate_l(*n, b),
+            (_, Type::Exist(n)) if !a.occurs(*n) => self.instantiate_r(a, *n),
+            (Type::Exist(_), _) | (_, Type::Exist(_)) => {
+                anyhow::bail!("Infinite type detected: `{a}` and `{b}`")
+            }
+            _ => anyhow::bail!("Could not match `{a}` with `{b}`"),
+        }
+    }
+
+    /// Type constructors other than `->` are invariant in their arguments.
+    fn equate(&mut self, a: &Type, b: &Type) -> anyhow::Result<()> {
+        let (a1, b1) = (self.apply(a), self.apply(b));
+        self.subtype(&a1, &b1)?;
+        let (a2, b2) = (self.apply(a), self.apply(b));
+        self.subtype(&b2, &a2)
+    }
+
+    /// `Γ ⊢ ^n :≦ A ⊣ Δ`, solves `^n` so that it is a subtype of `ty`.
+    fn instantiate_l(&mut self, n: usize, ty: &Type) -> anyhow::Result<()> {
+        let position = self.exist_position(n);
+        if ty.is_mono() && self.is_well_formed(ty, position) {
+            self.ctx[position] = Entry::Solved(n, ty.clone());
+            return Ok(());
+        }
+
+        match ty {
+            Type::Exist(m) => {
+                let position = self.exist_position(*m);
+                self.ctx[position] = Entry::Solved(*m, Type::Exist(n));
+                Ok(())
+            }
+            Type::Con(con, args) if con == "->" => {
+                let exists = self.articulate(n, con, 2);
+                self.instantiate_r(&args[0], exists[0])?;
+                let result = self.apply(&args[1]);
+                self.instantiate_l(exists[1], &result)
+            }
+            Type::Con(con, args) => {
+                let exists = self.articulate(n, con, args.len());
+                for (m, arg) in exists.into_iter().zip(args) {
+                    self.equate(&Type::Exist(m), arg)?;
+                }
+                Ok(())
+            }
+            Type::Forall(var, body) => {
+                let fresh = self.fresh_var(var);
+                let entry = Entry::Var(fresh.clone());
+                self.ctx.push(entry.clone());
+                self.instantiate_l(n, &body.subst(var, &Type::Var(fresh)))?;
+                self.drop_from(&entry);
+                Ok(())
+            }
+            Type::Var(_) => anyhow::bail!("The type variable `{ty}` would escape its scope"),
+        }
+    }
+
+    /// `Γ ⊢ A ≦: ^n ⊣ Δ`, solves `^n` so that `ty` is a subtype of it.
+    fn instantiate_r(&mut self, ty: &Type, n: usize) -> anyhow::Result<()> {
+        let position = self.exist_position(n);
+        if ty.is_mono() && self.is_well_formed(ty, position) {
+            self.ctx[position] = Entry::Solved(n, ty.clone());
+            return Ok(());
+        }
+
+        match ty {
+            Type::Exist(m) => {
+                let position = self.exist_position(*m);
+                self.ctx[position] = Entry::Solved(*m, Type::Exist(n));
+                Ok(())
+            }
+            Type::Con(con, args) if con == "->" => {
+                let exists = self.articulate(n, con, 2);
+                self.instantiate_l(exists[0], &args[0])?;
+                let result = self.apply(&args[1]);
+                self.instantiate_r(&result, exists[1])
+            }
+            Type::Con(con, args) => {
+                let exists = self.articulate(n, con, args.len());
+                for (m, arg) in exists.into_iter().zip(args) {
+                    self.equate(arg, &Type::Exist(m))?;
+                }
+                Ok(())
+            }
+            Type::Forall(var, body) => {
+                let m = self.fresh();
+                let marker = Entry::Marker(m);
+                self.ctx.push(marker.clone());
+                self.ctx.push(Entry::Exist(m));
+                self.instantiate_r(&body.subst(var, &Type::Exist(m)), n)?;
+                self.drop_from(&marker);
+                Ok(())
+            }
+            Type::Var(_) => anyhow::bail!("The type variable `{ty}` would escape its scope"),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::Read;
+
+use anyhow::Context;
+
+use hm_type_sys::codegen::expr_to_c;
+use hm_type_sys::lex::*;
+use hm_type_sys::parser::*;
+use hm_type_sys::prelude::default_ctx;
+
+const USAGE: &str = "usage: hmc [-o <output>] [<file>]
+
+Compiles a program, or stdin when no file is given, to a C program printing its value.
+
+    -o <output>    write the C program to <output> instead of stdout";
+
+fn main() -> anyhow::Result<()> {
+    let mut output = None;
+    let mut input = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().context("expected a file after -o")?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => anyhow::bail!("unknown option `{arg}`\n\n{USAGE}"),
+            _ if input.is_none() => input = Some(arg),
+            _ => anyhow::bail!("expected a single file\n\n{USAGE}"),
+        }
+    }
+
+    let source = match &input {
+        Some(file) => {
+            std::fs::read_to_string(file).with_context(|| format!("failed to read {file}"))?
+        }
+        None => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            source
+        }
+    };
+    let expr = Parser::new(Lexer::new(&source)).parse()?;
+    let program = expr_to_c(&default_ctx(), &expr)?;
+
+    match output {
+        Some(file) => {
+            std::fs::write(&file, program).with_context(|| format!("failed to write {file}"))?
+        }
+        None => print!("{program}"),
+    }
+
+    Ok(())
+}
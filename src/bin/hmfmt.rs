@@ -0,0 +1,45 @@
+use std::io::{Read, Write};
+use std::process::ExitCode;
+
+use anyhow::Context;
+
+use hm_type_sys::format::{format_files, format_source, Options, USAGE};
+
+fn main() -> anyhow::Result<ExitCode> {
+    let options = Options::parse(std::env::args().skip(1))?;
+    if options.help {
+        println!("{USAGE}");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if options.files.is_empty() {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        let formatted = format_source(&source, &options.printer).context("<stdin>")?;
+        if options.check {
+            return Ok(if formatted == source {
+                ExitCode::SUCCESS
+            } else {
+                eprintln!("<stdin> is not formatted");
+                ExitCode::FAILURE
+            });
+        }
+        std::io::stdout().write_all(formatted.as_bytes())?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let unformatted = format_files(&options)?;
+    if !options.check {
+        return Ok(ExitCode::SUCCESS);
+    }
+    for file in unformatted.iter() {
+        eprintln!("{file} is not formatted");
+    }
+
+    Ok(if unformatted.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,928 @@
+//! Type classes: checking class and instance declarations, simplifying predicates and
+//! translating programs to dictionary passing.
+//!
+//! A dictionary is a function `\k -> k s1 .. m1 ..` passing the dictionaries of its
+//! superclasses and then its methods to `k`, so that the translation is a plain `Expr`
+//! that any evaluator runs. Each method is bound to a selector taking a dictionary.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::alg_w::infer_qualified;
+use crate::bidir;
+use crate::lift::NameSupply;
+use crate::models::*;
+use crate::prelude::default_kinds;
+use crate::system_f::FType;
+use crate::typed::*;
+use crate::utils::*;
+
+/// The classes and instances in scope, with the type synonyms that predicates may
+/// have to be expanded through to find an instance.
+#[derive(Debug, Clone, Default)]
+pub struct ClassEnv {
+    classes: HashMap<String, ClassDecl>,
+    instances: Vec<InstanceDecl>,
+    /// The types to try for ambiguous variables, in order, with their class.
+    defaults: Vec<Pred>,
+    synonyms: Synonyms,
+}
+
+impl ClassEnv {
+    pub fn class(&self, name: &str) -> Option<&ClassDecl> {
+        self.classes.get(name)
+    }
+
+    pub fn synonyms(&self) -> &Synonyms {
+        &self.synonyms
+    }
+
+    /// The superclasses of `pred`, transitively, each with the path of superclass
+    /// indices that leads to it from `pred`.
+    fn supers(&self, pred: &Pred) -> Vec<(Pred, Vec<usize>)> {
+        let mut supers = vec![];
+        if let Some(class) = self.classes.get(&pred.class) {
+            for (i, name) in class.supers.iter().enumerate() {
+                let sup = Pred {
+                    class: name.clone(),
+                    ty: pred.ty.clone(),
+                };
+                supers.push((sup.clone(), vec![i]));
+                for (sup, mut path) in self.supers(&sup) {
+                    path.insert(0, i);
+                    supers.push((sup, path));
+                }
+            }
+        }
+
+        supers
+    }
+
+    /// The index of the instance for `pred` and the predicates it needs.
+    fn instance_for(&self, pred: &Pred) -> Option<(usize, Vec<Pred>)> {
+        let found = self.instances.iter().enumerate().find_map(|(i, instance)| {
+            if instance.head.class != pred.class {
+                return None;
+            }
+            let s = match_head(&instance.head.ty, &pred.ty)?;
+            let context = instance.context.iter().map(|p| p.clone().apply(&s));
+            Some((i, context.collect()))
+        });
+        // instances are never for synonyms, only for the types they stand for
+        found.or_else(|| {
+            let pred = Pred {
+                class: pred.class.clone(),
+                ty: self.synonyms.expand(&pred.ty)?,
+            };
+            self.instance_for(&pred)
+        })
+    }
+
+    /// Whether `pred` follows from `given`, through superclasses and instances.
+    pub fn entails(&self, given: &[Pred], pred: &Pred) -> bool {
+        let by_super = given
+            .iter()
+            .any(|g| g == pred || self.supers(g).iter().any(|(sup, _)| sup == pred));
+        by_super
+            || self
+                .instance_for(pred)
+                .is_some_and(|(_, context)| context.iter().all(|p| self.entails(given, p)))
+    }
+
+    /// Reduces predicates to ones on type variables, possibly applied to types, with
+    /// the instances, then drops duplicates and those implied by the superclasses of
+    /// others.
+    pub fn reduce(&self, preds: Vec<Pred>) -> anyhow::Result<Vec<Pred>> {
+        let mut hnf: Vec<Pred> = vec![];
+        let mut todo = preds;
+        todo.reverse();
+        while let Some(pred) = todo.pop() {
+            if matches!(pred.ty, MonoType::TyVar(_) | MonoType::TyVarApp(_)) {
+                if !hnf.contains(&pred) {
+                    hnf.push(pred);
+                }
+                continue;
+            }
+            match self.instance_for(&pred) {
+                Some((_, context)) => todo.extend(context.into_iter().rev()),
+                None => anyhow::bail!("No instance for `{pred}`"),
+            }
+        }
+
+        let implied = |pred: &Pred| {
+            hnf.iter()
+                .any(|other| self.supers(other).iter().any(|(sup, _)| sup == pred))
+        };
+        Ok(hnf.iter().filter(|p| !implied(p)).cloned().collect())
+    }
+}
+
+impl ClassEnv {
+    /// Picks a type for each variable of `preds` that is not in `keep`: the first
+    /// default of one of its classes that has instances for all of them. Gives a
+    /// warning for each choice.
+    pub fn default_ambiguous(
+        &self,
+        preds: &[Pred],
+        keep: &HashSet<&str>,
+    ) -> anyhow::Result<(Substitution, Vec<String>)> {
+        let mut mapping = HashMap::new();
+        let mut warnings = vec![];
+        for pred in preds.iter() {
+            let MonoType::TyVar(var) = &pred.ty else {
+                continue;
+            };
+            if keep.contains(var.as_str()) || mapping.contains_key(var) {
+                continue;
+            }
+
+            let constrained: Vec<_> = preds.iter().filter(|p| p.ty == pred.ty).collect();
+            let default = self
+                .defaults
+                .iter()
+                .filter(|default| constrained.iter().any(|p| p.class == default.class))
+                .map(|default| &default.ty)
+                .find(|ty| {
+                    constrained.iter().all(|p| {
+                        let pred = Pred {
+                            class: p.class.clone(),
+                            ty: (*ty).clone(),
+                        };
+                        self.entails(&[], &pred)
+                    })
+                });
+            let constrained: Vec<_> = constrained.iter().map(|p| p.to_string()).collect();
+            match default {
+                Some(ty) => {
+                    warnings.push(format!(
+                        "Defaulting `{var}` to `{ty}` in `{}`",
+                        constrained.join(", ")
+                    ));
+                    mapping.insert(var.clone(), ty.clone());
+                }
+                None => anyhow::bail!(
+                    "Ambiguous type variable `{var}` in `{}`",
+                    constrained.join(", ")
+                ),
+            }
+        }
+
+        Ok((Substitution::from_map(mapping), warnings))
+    }
+}
+
+/// Matches an instance head `T a b ..` against a type, binding its variables.
+fn match_head(head: &MonoType, ty: &MonoType) -> Option<Substitution> {
+    match (head, ty) {
+        (MonoType::TyApp(head), MonoType::TyApp(app))
+            if head.func == app.func && head.monotypes.len() == app.monotypes.len() =>
+        {
+            let mapping =
+                head.monotypes
+                    .iter()
+                    .zip(app.monotypes.iter())
+                    .map(|(var, ty)| match var {
+                        MonoType::TyVar(var) => (var.clone(), ty.clone()),
+                        _ => unreachable!("instance heads only have variables as arguments"),
+                    });
+            Some(Substitution::from_map(mapping.collect()))
+        }
+        _ => None,
+    }
+}
+
+/// A program checked against its classes and translated to dictionary passing.
+#[derive(Debug, Clone)]
+pub struct Translated {
+    pub ty: PolyType,
+    /// The program without classes and newtype constructors, only needing the globals
+    /// of the context.
+    pub expr: Expr,
+    pub classes: ClassEnv,
+    /// The defaults that were chosen for ambiguous variables.
+    pub warnings: Vec<String>,
+}
+
+/// Checks the declarations of `program` in order, each one seeing those before it,
+/// then infers the type of its body and translates the whole program.
+pub fn translate_program(ctx: &Context, program: &Program) -> anyhow::Result<Translated> {
+    let mut translator = Translator {
+        classes: ClassEnv::default(),
+        names: NameSupply::new(&program_expr(program)),
+        selectors: HashMap::new(),
+        dicts: vec![],
+        newtypes: HashSet::new(),
+        warnings: vec![],
+    };
+    default_kinds().check_program(ctx, program)?;
+    let mut ctx = ctx.clone();
+    let mut bindings = vec![];
+
+    for decl in program.decls.iter() {
+        match decl {
+            Decl::Data(data) => {
+                for (con, scheme) in data.constructor_schemes() {
+                    if data.newtype {
+                        translator.newtypes.insert(con.clone());
+                    }
+                    ctx = ctx.extend_with(con, scheme);
+                }
+            }
+            Decl::Type(synonym) => translator.classes.synonyms.declare(synonym.clone()),
+            Decl::Class(class) => {
+                translator.check_class(&ctx, class)?;
+                for (name, ty) in class.methods.iter() {
+                    ctx = ctx.extend_with(name.clone(), method_scheme(class, ty));
+                }
+                bindings.extend(translator.selectors(class));
+            }
+            Decl::Instance(instance) => {
+                translator.check_instance(instance)?;
+                let dict = translator.instance_dict(&ctx, instance)?;
+                bindings.push(dict);
+            }
+            Decl::Default(default) => translator.check_default(default)?,
+        }
+    }
+
+    if has_higher_rank(&program.body) {
+        return translator.higher_rank_program(&ctx, program, bindings);
+    }
+
+    let (preds, typed) = infer_qualified(&translator.classes, ctx.clone(), program.body.clone())?;
+    // the value restriction applies to the body as to a `let`, the variables it keeps
+    // from being quantified are defaulted like ambiguous ones
+    let generalise = match program.body.is_value() {
+        true => MonoType::generalise_with,
+        false => MonoType::generalise_relaxed,
+    };
+    let scheme = generalise(&typed.ty, vec![], &ctx);
+    let (quantified, _) = scheme.split();
+    let (s, warnings) = translator
+        .classes
+        .default_ambiguous(&preds, &quantified.into_iter().collect())?;
+    translator.warnings.extend(warnings);
+    let typed = typed.apply(&s);
+    let preds = preds.into_iter().map(|p| p.apply(&s)).collect();
+    let preds = translator.classes.reduce(preds)?;
+    let ty = generalise(&typed.ty, preds.clone(), &ctx);
+    if let Some(var) = ty.ambiguous_vars().first() {
+        anyhow::bail!("Ambiguous type variable `{var}` in `{ty}`");
+    }
+    let body = translator.abstract_dicts(&preds, &typed, &[], &HashMap::new())?;
+
+    Ok(Translated {
+        ty,
+        expr: bind_all(bindings, body),
+        classes: translator.classes,
+        warnings: translator.warnings,
+    })
+}
+
+/// Binds the dictionaries and selectors of a program around its body.
+fn bind_all(bindings: Vec<(String, Expr, bool)>, body: Expr) -> Expr {
+    bindings
+        .into_iter()
+        .rev()
+        .fold(body, |aexpr, (var, vexpr, recursive)| {
+            let elet = Box::new(LetExpr { var, vexpr, aexpr });
+            if recursive {
+                Expr::LetRec(elet)
+            } else {
+                Expr::Let(elet)
+            }
+        })
+}
+
+/// Rebuilds `expr` from the bottom up, passing every node to `f` once its children
+/// are rebuilt.
+fn map_expr(expr: &Expr, f: &mut impl FnMut(Expr) -> Expr) -> Expr {
+    let expr = match expr {
+        Expr::Lit(_) | Expr::Var(_) | Expr::Con(_) => expr.clone(),
+        Expr::App(app) => Expr::App(Box::new(AppExpr {
+            fun: map_expr(&app.fun, f),
+            arg: map_expr(&app.arg, f),
+        })),
+        Expr::Abs(abs) => Expr::Abs(Box::new(AbsExpr {
+            arg: abs.arg.clone(),
+            body: map_expr(&abs.body, f),
+        })),
+        Expr::Let(elet) | Expr::LetRec(elet) => {
+            let elet = Box::new(LetExpr {
+                var: elet.var.clone(),
+                vexpr: map_expr(&elet.vexpr, f),
+                aexpr: map_expr(&elet.aexpr, f),
+            });
+            match expr {
+                Expr::Let(_) => Expr::Let(elet),
+                _ => Expr::LetRec(elet),
+            }
+        }
+        Expr::Ann(ann) => Expr::Ann(Box::new(AnnExpr {
+            expr: map_expr(&ann.expr, f),
+            ty: ann.ty.clone(),
+        })),
+        Expr::Case(case) => Expr::Case(Box::new(CaseExpr {
+            expr: map_expr(&case.expr, f),
+            arms: case
+                .arms
+                .iter()
+                .map(|arm| CaseArm {
+                    con: arm.con.clone(),
+                    vars: arm.vars.clone(),
+                    body: map_expr(&arm.body, f),
+                })
+                .collect(),
+        })),
+    };
+
+    f(expr)
+}
+
+/// Whether `expr` has an annotation with quantifiers other than in front, which only
+/// the bidirectional checker takes.
+fn has_higher_rank(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(_) | Expr::Var(_) | Expr::Con(_) => false,
+        Expr::App(app) => has_higher_rank(&app.fun) || has_higher_rank(&app.arg),
+        Expr::Abs(abs) => has_higher_rank(&abs.body),
+        Expr::Let(elet) | Expr::LetRec(elet) => {
+            has_higher_rank(&elet.vexpr) || has_higher_rank(&elet.aexpr)
+        }
+        Expr::Ann(ann) => {
+            let mut body = &ann.ty;
+            while let FType::Forall(_, inner) = body {
+                body = inner;
+            }
+            MonoType::try_from(body).is_err() || has_higher_rank(&ann.expr)
+        }
+        Expr::Case(case) => {
+            has_higher_rank(&case.expr) || case.arms.iter().any(|arm| has_higher_rank(&arm.body))
+        }
+    }
+}
+
+/// An expression mentioning every name of `program`, to pick fresh names from.
+fn program_expr(program: &Program) -> Expr {
+    let mut expr = program.body.clone();
+    for decl in program.decls.iter() {
+        if let Decl::Instance(instance) = decl {
+            for (name, method) in instance.methods.iter() {
+                expr = Expr::Let(Box::new(LetExpr {
+                    var: name.clone(),
+                    vexpr: method.clone(),
+                    aexpr: expr,
+                }));
+            }
+        }
+    }
+
+    expr
+}
+
+/// `∀a b... C a => τ`, the class variable first.
+fn method_scheme(class: &ClassDecl, ty: &MonoType) -> PolyType {
+    let qual = PolyType::Qualified(QualType {
+        preds: vec![Pred {
+            class: class.name.clone(),
+            ty: MonoType::TyVar(class.var.clone()),
+        }],
+        ty: ty.clone(),
+    });
+    let mut vars = vec![class.var.as_str()];
+    vars.extend(ty.type_vars().into_iter().filter(|v| *v != class.var));
+
+    vars.into_iter().rev().fold(qual, |acc, var| {
+        PolyType::TyQuantifier(TypeQuantifier {
+            bounded: var.to_string(),
+            ty: Box::new(acc),
+        })
+    })
+}
+
+/// A binding of the translated program: its name, its value and whether it is
+/// recursive.
+type Binding = (String, Expr, bool);
+
+struct Translator {
+    classes: ClassEnv,
+    names: NameSupply,
+    /// The selectors of the superclasses of each class, in order.
+    selectors: HashMap<String, Vec<String>>,
+    /// The name of the dictionary of each instance, in order.
+    dicts: Vec<String>,
+    /// The constructors of newtypes, which are translated to the identity.
+    newtypes: HashSet<String>,
+    warnings: Vec<String>,
+}
+
+impl Translator {
+    /// Checks a body with higher-rank annotations with the bidirectional checker, which
+    /// knows nothing of classes or `case`, so it can't use either. The program itself
+    /// must be of rank 1.
+    fn higher_rank_program(
+        mut self,
+        ctx: &Context,
+        program: &Program,
+        bindings: Vec<(String, Expr, bool)>,
+    ) -> anyhow::Result<Translated> {
+        let free = program.body.free_vars();
+        let methods = self.classes.classes.values().flat_map(|c| c.methods.iter());
+        if let Some((name, _)) = methods
+            .into_iter()
+            .find(|(name, _)| free.contains(name.as_str()))
+        {
+            anyhow::bail!("The method `{name}` can't be used along with higher-rank annotations");
+        }
+
+        if program.body.has_case() {
+            anyhow::bail!("`case` can't be used along with higher-rank annotations");
+        }
+
+        // the bidirectional checker knows nothing of synonyms either
+        let synonyms = &self.classes.synonyms;
+        let mut env = bidir::type_env(ctx);
+        for ty in env.values_mut() {
+            *ty = synonyms.expand_ftype(ty);
+        }
+        let expanded = map_expr(&program.body, &mut |expr| match expr {
+            Expr::Ann(ann) => Expr::Ann(Box::new(AnnExpr {
+                ty: synonyms.expand_ftype(&ann.ty),
+                expr: ann.expr,
+            })),
+            expr => expr,
+        });
+        let ty = bidir::infer(&env, &expanded)?;
+        let mut body = &ty;
+        while let FType::Forall(_, inner) = body {
+            body = inner;
+        }
+        let Ok(mono) = MonoType::try_from(body) else {
+            anyhow::bail!("The type of the program `{ty}` is of higher rank");
+        };
+        let ty = match program.body.is_value() {
+            true => mono.generalise(ctx),
+            false => mono.generalise_relaxed(vec![], ctx),
+        };
+
+        // a newtype has the representation of its field
+        let (names, newtypes) = (&mut self.names, &self.newtypes);
+        let body = map_expr(&program.body, &mut |expr| match expr {
+            Expr::Con(con) if newtypes.contains(&con) => {
+                let arg = names.name("x");
+                Expr::Abs(Box::new(AbsExpr {
+                    body: Expr::Var(arg.clone()),
+                    arg,
+                }))
+            }
+            expr => expr,
+        });
+        Ok(Translated {
+            ty,
+            expr: bind_all(bindings, body),
+            classes: self.classes,
+            warnings: self.warnings,
+        })
+    }
+
+    fn check_class(&mut self, ctx: &Context, class: &ClassDecl) -> anyhow::Result<()> {
+        if self.classes.classes.contains_key(&class.name) {
+            anyhow::bail!("Class `{}` is declared twice", class.name);
+        }
+        for sup in class.supers.iter() {
+            if !self.classes.classes.contains_key(sup) {
+                anyhow::bail!(
+                    "Undefined class `{sup}` in the superclasses of `{}`",
+                    class.name
+                );
+            }
+        }
+        for (i, (name, ty)) in class.methods.iter().enumerate() {
+            let declared = class.methods[..i].iter().any(|(other, _)| other == name);
+            if declared || ctx.constrains.contains_key(name) {
+                anyhow::bail!("Method `{name}` of `{}` is already defined", class.name);
+            }
+            if !ty.free_vars().contains(class.var.as_str()) {
+                anyhow::bail!(
+                    "The type of method `{name}` doesn't mention `{}`",
+                    class.var
+                );
+            }
+        }
+
+        self.classes
+            .classes
+            .insert(class.name.clone(), class.clone());
+        Ok(())
+    }
+
+    fn check_instance(&mut self, instance: &InstanceDecl) -> anyhow::Result<()> {
+        let head = &instance.head;
+        let class = match self.classes.class(&head.class) {
+            Some(class) => class,
+            None => anyhow::bail!("Undefined class `{}` in instance `{head}`", head.class),
+        };
+        let vars = match &head.ty {
+            MonoType::TyApp(app) if self.classes.synonyms.contains(&app.func) => {
+                anyhow::bail!(
+                    "Instance `{head}` is for the type synonym `{}`, not a type constructor",
+                    app.func
+                )
+            }
+            MonoType::TyApp(app) => {
+                let vars: Vec<_> = app.monotypes.iter().flat_map(|m| m.type_vars()).collect();
+                let distinct = app
+                    .monotypes
+                    .iter()
+                    .all(|m| matches!(m, MonoType::TyVar(_)))
+                    && vars.iter().enumerate().all(|(i, v)| !vars[..i].contains(v));
+                if !distinct {
+                    anyhow::bail!(
+                        "Instance `{head}` must be for a type constructor applied to distinct variables"
+                    );
+                }
+                vars
+            }
+            MonoType::TyVar(_) | MonoType::TyVarApp(_) => {
+                anyhow::bail!("Instance `{head}` must be for a type constructor")
+            }
+        };
+        if self.classes.instance_for(head).is_some() {
+            anyhow::bail!("Instance `{head}` overlaps with another one");
+        }
+        for pred in instance.context.iter() {
+            if !self.classes.classes.contains_key(&pred.class) {
+                anyhow::bail!(
+                    "Undefined class `{}` in the context of `{head}`",
+                    pred.class
+                );
+            }
+            if !matches!(&pred.ty, MonoType::TyVar(v) if vars.contains(&v.as_str())) {
+                anyhow::bail!("The context of `{head}` must constrain its variables, not `{pred}`");
+            }
+        }
+        for sup in class.supers.iter() {
+            let sup = Pred {
+                class: sup.clone(),
+                ty: head.ty.clone(),
+            };
+            if !self.classes.entails(&instance.context, &sup) {
+                anyhow::bail!("Instance `{head}` needs an instance for its superclass `{sup}`");
+            }
+        }
+        for (name, _) in class.methods.iter() {
+            let count = instance.methods.iter().filter(|(m, _)| m == name).count();
+            if count != 1 {
+                anyhow::bail!("Instance `{head}` must define `{name}` once, not {count} times");
+            }
+        }
+        if let Some((name, _)) = instance
+            .methods
+            .iter()
+            .find(|(m, _)| !class.methods.iter().any(|(c, _)| c == m))
+        {
+            anyhow::bail!("`{name}` is not a method of `{}`", class.name);
+        }
+
+        self.classes.instances.push(instance.clone());
+        Ok(())
+    }
+
+    fn check_default(&mut self, default: &Pred) -> anyhow::Result<()> {
+        if self.classes.class(&default.class).is_none() {
+            anyhow::bail!("Undefined class `{}` in `default {default}`", default.class);
+        }
+        if !default.ty.free_vars().is_empty() || !self.classes.entails(&[], default) {
+            anyhow::bail!("`default {default}` needs a type with an instance");
+        }
+
+        self.classes.defaults.push(default.clone());
+        Ok(())
+    }
+
+    /// The selectors of the superclasses, then those of the methods, named after them.
+    fn selectors(&mut self, class: &ClassDecl) -> Vec<Binding> {
+        let fields = class.supers.len() + class.methods.len();
+        let supers: Vec<_> = class
+            .supers
+            .iter()
+            .map(|sup| self.names.name(&format!("super_{}_{sup}", class.name)))
+            .collect();
+        self.selectors.insert(class.name.clone(), supers.clone());
+
+        let methods = class.methods.iter().map(|(name, _)| name.clone());
+        supers
+            .into_iter()
+            .chain(methods)
+            .enumerate()
+            .map(|(i, name)| (name, selector(i, fields), false))
+            .collect()
+    }
+
+    /// Checks the methods of an instance and builds its dictionary, a function of the
+    /// dictionaries of its context.
+    fn instance_dict(&mut self, ctx: &Context, instance: &InstanceDecl) -> anyhow::Result<Binding> {
+        let head = &instance.head;
+        let class = self.classes.classes[&head.class].clone();
+        let mut name = format!("dict_{}", head.class);
+        if let MonoType::TyApp(app) = &head.ty {
+            name.push('_');
+            name.push_str(&app.func.replace("->", "Fn").replace('.', "_"));
+        }
+        let name = self.names.name(&name);
+        self.dicts.push(name.clone());
+
+        let params: Vec<_> = instance
+            .context
+            .iter()
+            .map(|pred| (pred.clone(), Expr::Var(self.dict_name(pred))))
+            .collect();
+        let mut fields = vec![];
+        for sup in class.supers.iter() {
+            let sup = Pred {
+                class: sup.clone(),
+                ty: head.ty.clone(),
+            };
+            fields.push(self.resolve(&sup, &params)?);
+        }
+        for (method, ty) in class.methods.iter() {
+            let expected = ty.clone().apply(&Substitution::from_map(HashMap::from([(
+                class.var.clone(),
+                head.ty.clone(),
+            )])));
+            let (_, expr) = instance.methods.iter().find(|(m, _)| m == method).unwrap();
+            let typed = self
+                .check_method(ctx, instance, &expected, expr)
+                .map_err(|err| err.context(format!("in method `{method}` of `{head}`")))?;
+            fields.push(self.expr(&typed, &params, &HashMap::new())?);
+        }
+
+        let k = self.names.fresh("k");
+        let dict = fields.into_iter().fold(Expr::Var(k.clone()), |fun, arg| {
+            Expr::App(Box::new(AppExpr { fun, arg }))
+        });
+        let dict = Expr::Abs(Box::new(AbsExpr { arg: k, body: dict }));
+        let dict = params.into_iter().rev().fold(dict, |body, (_, param)| {
+            let Expr::Var(arg) = param else {
+                unreachable!("dictionary parameters are variables")
+            };
+            Expr::Abs(Box::new(AbsExpr { arg, body }))
+        });
+
+        Ok((name, dict, true))
+    }
+
+    /// Infers the type of a method, which must be as general as `expected` with
+    /// predicates that follow from the context of the instance. The result is typed
+    /// with the variables of `expected`.
+    fn check_method(
+        &mut self,
+        ctx: &Context,
+        instance: &InstanceDecl,
+        expected: &MonoType,
+        expr: &Expr,
+    ) -> anyhow::Result<TypedExpr> {
+        let (preds, typed) = infer_qualified(&self.classes, ctx.clone(), expr.clone())?;
+        let s = unify_with(&self.classes.synonyms, typed.ty.clone(), expected.clone())?;
+
+        // the variables of `expected` may only be renamed, not specialised or merged
+        let mut renaming = HashMap::new();
+        for var in expected.type_vars() {
+            match MonoType::TyVar(var.to_string()).apply(&s) {
+                MonoType::TyVar(image) if !renaming.contains_key(&image) => {
+                    renaming.insert(image, MonoType::TyVar(var.to_string()));
+                }
+                _ => anyhow::bail!("`{}` is less general than `{expected}`", typed.ty.apply(&s)),
+            }
+        }
+        let renaming = Substitution::from_map(renaming);
+
+        let preds: Vec<_> = preds
+            .into_iter()
+            .map(|p| p.apply(&s).apply(&renaming))
+            .collect();
+        let (defaults, warnings) = self
+            .classes
+            .default_ambiguous(&preds, &expected.free_vars())?;
+        self.warnings.extend(warnings);
+        for pred in preds {
+            let pred = pred.apply(&defaults);
+            if !self.classes.entails(&instance.context, &pred) {
+                anyhow::bail!(
+                    "Could not deduce `{pred}` from the context of `{}`",
+                    instance.head
+                );
+            }
+        }
+
+        Ok(typed.apply(&s).apply(&renaming).apply(&defaults))
+    }
+
+    fn dict_name(&mut self, pred: &Pred) -> String {
+        let mut name = format!("dict_{}", pred.class);
+        for var in pred.ty.type_vars() {
+            name.push('_');
+            name.push_str(var);
+        }
+        self.names.name(&name)
+    }
+
+    /// The dictionary for `pred`, from those in scope or from an instance.
+    fn resolve(&self, pred: &Pred, dicts: &[(Pred, Expr)]) -> anyhow::Result<Expr> {
+        for (given, dict) in dicts.iter() {
+            if given == pred {
+                return Ok(dict.clone());
+            }
+            let Some((_, path)) = self
+                .classes
+                .supers(given)
+                .into_iter()
+                .find(|(s, _)| s == pred)
+            else {
+                continue;
+            };
+
+            // select the superclass dictionaries along the path
+            let mut class = given.class.clone();
+            let mut dict = dict.clone();
+            for i in path {
+                let selector = self.selectors[&class][i].clone();
+                class = self.classes.classes[&class].supers[i].clone();
+                dict = Expr::App(Box::new(AppExpr {
+                    fun: Expr::Var(selector),
+                    arg: dict,
+                }));
+            }
+            return Ok(dict);
+        }
+
+        match self.classes.instance_for(pred) {
+            Some((i, context)) => {
+                let mut dict = Expr::Var(self.dicts[i].clone());
+                for pred in context {
+                    let arg = self.resolve(&pred, dicts)?;
+                    dict = Expr::App(Box::new(AppExpr { fun: dict, arg }));
+                }
+                Ok(dict)
+            }
+            None => anyhow::bail!("No instance for `{pred}`"),
+        }
+    }
+
+    /// Translates `typed` under lambdas taking the dictionaries for `preds`.
+    fn abstract_dicts(
+        &mut self,
+        preds: &[Pred],
+        typed: &TypedExpr,
+        dicts: &[(Pred, Expr)],
+        recursive: &HashMap<String, Vec<Expr>>,
+    ) -> anyhow::Result<Expr> {
+        let params: Vec<_> = preds.iter().map(|p| self.dict_name(p)).collect();
+        let mut dicts = dicts.to_vec();
+        for (pred, param) in preds.iter().zip(params.iter()) {
+            dicts.push((pred.clone(), Expr::Var(param.clone())));
+        }
+        let body = self.expr(typed, &dicts, recursive)?;
+
+        Ok(params
+            .into_iter()
+            .rev()
+            .fold(body, |body, arg| Expr::Abs(Box::new(AbsExpr { arg, body }))))
+    }
+
+    /// Passes dictionaries to the variables with qualified types. `recursive` maps the
+    /// `letrec`s being defined to the dictionaries they were abstracted over.
+    fn expr(
+        &mut self,
+        typed: &TypedExpr,
+        dicts: &[(Pred, Expr)],
+        recursive: &HashMap<String, Vec<Expr>>,
+    ) -> anyhow::Result<Expr> {
+        let apply = |fun: Expr, args: Vec<Expr>| {
+            args.into_iter()
+                .fold(fun, |fun, arg| Expr::App(Box::new(AppExpr { fun, arg })))
+        };
+
+        match &typed.node {
+            TypedNode::Lit(lit) => Ok(Expr::Lit(lit.clone())),
+            TypedNode::Con(con) if self.newtypes.contains(&con.name) => {
+                // a newtype has the representation of its field
+                let arg = self.names.name("x");
+                Ok(Expr::Abs(Box::new(AbsExpr {
+                    body: Expr::Var(arg.clone()),
+                    arg,
+                })))
+            }
+            TypedNode::Var(var) | TypedNode::Con(var) => {
+                let fun = match typed.node {
+                    TypedNode::Con(_) => Expr::Con(var.name.clone()),
+                    _ => Expr::Var(var.name.clone()),
+                };
+                if let Some(args) = recursive.get(&var.name) {
+                    return Ok(apply(fun, args.clone()));
+                }
+                let preds = var.scheme.instantiate_preds(&var.inst);
+                let args = preds
+                    .iter()
+                    .map(|pred| self.resolve(pred, dicts))
+                    .collect::<anyhow::Result<_>>()?;
+                Ok(apply(fun, args))
+            }
+            TypedNode::App(app) => Ok(Expr::App(Box::new(AppExpr {
+                fun: self.expr(&app.fun, dicts, recursive)?,
+                arg: self.expr(&app.arg, dicts, recursive)?,
+            }))),
+            TypedNode::Abs(abs) => {
+                let mut recursive = recursive.clone();
+                recursive.remove(&abs.arg);
+                Ok(Expr::Abs(Box::new(AbsExpr {
+                    arg: abs.arg.clone(),
+                    body: self.expr(&abs.body, dicts, &recursive)?,
+                })))
+            }
+            TypedNode::Let(elet) | TypedNode::LetRec(elet) => {
+                let is_rec = matches!(typed.node, TypedNode::LetRec(_));
+                let preds = elet.scheme.preds();
+                let mut outer = recursive.clone();
+                outer.remove(&elet.var);
+
+                let vexpr = if is_rec {
+                    // the recursive uses pass on the dictionaries they are given
+                    let params: Vec<_> = preds.iter().map(|p| self.dict_name(p)).collect();
+                    let mut inner = recursive.clone();
+                    let args = params.iter().map(|p| Expr::Var(p.clone())).collect();
+                    inner.insert(elet.var.clone(), args);
+                    let mut dicts = dicts.to_vec();
+                    for (pred, param) in preds.iter().zip(params.iter()) {
+                        dicts.push((pred.clone(), Expr::Var(param.clone())));
+                    }
+                    let body = self.expr(&elet.vexpr, &dicts, &inner)?;
+                    params
+                        .into_iter()
+                        .rev()
+                        .fold(body, |body, arg| Expr::Abs(Box::new(AbsExpr { arg, body })))
+                } else {
+                    self.abstract_dicts(preds, &elet.vexpr, dicts, recursive)?
+                };
+                let aexpr = self.expr(&elet.aexpr, dicts, &outer)?;
+
+                let elet = Box::new(LetExpr {
+                    var: elet.var.clone(),
+                    vexpr,
+                    aexpr,
+                });
+                Ok(if is_rec {
+                    Expr::LetRec(elet)
+                } else {
+                    Expr::Let(elet)
+                })
+            }
+            TypedNode::Case(case) => {
+                let expr = self.expr(&case.expr, dicts, recursive)?;
+                let mut arms = vec![];
+                for arm in case.arms.iter() {
+                    let mut recursive = recursive.clone();
+                    for (var, _) in arm.vars.iter() {
+                        recursive.remove(var);
+                    }
+                    let body = self.expr(&arm.body, dicts, &recursive)?;
+                    if self.newtypes.contains(&arm.con.name) {
+                        // the scrutinee is already the field
+                        let (var, _) = &arm.vars[0];
+                        return Ok(Expr::Let(Box::new(LetExpr {
+                            var: var.clone(),
+                            vexpr: expr,
+                            aexpr: body,
+                        })));
+                    }
+                    arms.push(CaseArm {
+                        con: arm.con.name.clone(),
+                        vars: arm.vars.iter().map(|(var, _)| var.clone()).collect(),
+                        body,
+                    });
+                }
+                Ok(Expr::Case(Box::new(CaseExpr { expr, arms })))
+            }
+        }
+    }
+}
+
+/// `\d -> d (\x0 .. -> xi)`, selecting the `i`-th of `fields` from a dictionary.
+fn selector(i: usize, fields: usize) -> Expr {
+    let field = (0..fields)
+        .rev()
+        .fold(Expr::Var(format!("x{i}")), |body, j| {
+            Expr::Abs(Box::new(AbsExpr {
+                arg: format!("x{j}"),
+                body,
+            }))
+        });
+    let body = Expr::App(Box::new(AppExpr {
+        fun: Expr::Var("d".to_string()),
+        arg: field,
+    }));
+
+    Expr::Abs(Box::new(AbsExpr {
+        arg: "d".to_string(),
+        body,
+    }))
+}
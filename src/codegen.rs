@@ -0,0 +1,348 @@
+//! Emits a C program from a typed ANF term.
+//!
+//! Every value is a `value *` except for variables whose type is known to be `Int` or
+//! `Bool`, which are unboxed `int64_t`s and `bool`s. Closures take and return boxed
+//! values, so that polymorphic functions work on anything; values are boxed and
+//! unboxed where the two meet.
+
+use std::fmt::Write;
+
+use crate::anf::{self, Atom, Comp, Ident, Lambda, Term};
+use crate::models::*;
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// The builtins the runtime implements, as `builtin_<name>`.
+const BUILTINS: &[&str] = &[
+    "list", "inc", "dec", "isNull", "add", "head", "tail", "take",
+];
+
+/// Translates a type-checked `expr` to a C program printing its value.
+pub fn expr_to_c(ctx: &Context, expr: &Expr) -> anyhow::Result<String> {
+    if expr.has_case() {
+        anyhow::bail!("`case` can't be compiled to C yet, only the interpreters run it");
+    }
+    let term = anf::optimise(anf::from_expr(ctx, expr)?);
+    anf::check(ctx, &term)?;
+
+    term_to_c(ctx, &term)
+}
+
+/// Translates a term to a C program printing its value, `ctx` gives the types of the
+/// builtins and constructors it uses.
+pub fn term_to_c(ctx: &Context, term: &Term) -> anyhow::Result<String> {
+    let mut codegen = CodeGen {
+        ctx,
+        functions: vec![],
+        temps: 0,
+        partial_adds: vec![],
+    };
+    let mut body = String::new();
+    let (result, repr) = codegen.term(&mut vec![], term, &mut body)?;
+
+    let mut out = RUNTIME.to_string();
+    out.push_str("\n/* the program */\n\n");
+    for i in 0..codegen.functions.len() {
+        writeln!(out, "static value *lambda_{i}(value *self, value *arg);")?;
+    }
+    for function in codegen.functions.iter() {
+        write!(out, "\n{function}")?;
+    }
+    writeln!(out, "\nint main(void) {{")?;
+    out.push_str(&body);
+    writeln!(
+        out,
+        "    print_value({});",
+        repr.coerce(&result, Repr::Boxed)?
+    )?;
+    writeln!(out, "    putchar('\\n');")?;
+    writeln!(out, "    return 0;")?;
+    writeln!(out, "}}")?;
+
+    Ok(out)
+}
+
+/// How a value is represented in C.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repr {
+    Int,
+    Bool,
+    Boxed,
+}
+
+impl Repr {
+    fn of(ty: &MonoType) -> Repr {
+        match ty {
+            MonoType::TyApp(app) if app.func == "Int" => Repr::Int,
+            MonoType::TyApp(app) if app.func == "Bool" => Repr::Bool,
+            _ => Repr::Boxed,
+        }
+    }
+
+    fn of_poly(ty: &PolyType) -> Repr {
+        Repr::of(ty.split().1)
+    }
+
+    /// A C declaration of `name` with this representation.
+    fn declare(self, name: &str) -> String {
+        match self {
+            Repr::Int => format!("int64_t {name}"),
+            Repr::Bool => format!("bool {name}"),
+            Repr::Boxed => format!("value *{name}"),
+        }
+    }
+
+    /// Converts the C expression `expr` from this representation to `to`.
+    fn coerce(self, expr: &str, to: Repr) -> anyhow::Result<String> {
+        Ok(match (self, to) {
+            _ if self == to => expr.to_string(),
+            (Repr::Int, Repr::Boxed) => format!("mk_int({expr})"),
+            (Repr::Bool, Repr::Boxed) => format!("mk_bool({expr})"),
+            (Repr::Boxed, Repr::Int) => format!("{expr}->i"),
+            (Repr::Boxed, Repr::Bool) => format!("{expr}->b"),
+            _ => anyhow::bail!("Can't convert `{expr}` from {self:?} to {to:?}"),
+        })
+    }
+}
+
+/// The variables in C scope with their types, innermost last.
+type Scope = Vec<(String, PolyType)>;
+
+struct CodeGen<'a> {
+    ctx: &'a Context,
+    /// The C functions of the lambdas, `lambda_<i>`.
+    functions: Vec<String>,
+    temps: usize,
+    /// Variables bound to the builtin `add` applied to a C expression, so that applying
+    /// them again can be an unboxed addition.
+    partial_adds: Vec<(String, String)>,
+}
+
+impl CodeGen<'_> {
+    /// Writes the statements of `term` to `out` and returns the C expression of its
+    /// value.
+    fn term(
+        &mut self,
+        scope: &mut Scope,
+        term: &Term,
+        out: &mut String,
+    ) -> anyhow::Result<(String, Repr)> {
+        match term {
+            Term::Let(binding, body) | Term::LetRec(binding, body) => {
+                let name = c_name(&binding.var);
+                if matches!(term, Term::LetRec(..)) {
+                    scope.push((binding.var.clone(), binding.ty.clone()));
+                }
+                match &binding.value {
+                    Comp::Abs(lambda) => self.closure(&name, lambda, scope, out)?,
+                    value => {
+                        if let Some(arg) = self.partial_add(value, scope)? {
+                            self.partial_adds.push((binding.var.clone(), arg));
+                        }
+                        let (expr, repr) = self.comp(value, scope, out)?;
+                        let to = Repr::of_poly(&binding.ty);
+                        let expr = repr.coerce(&expr, to)?;
+                        writeln!(out, "    {} = {expr};", to.declare(&name))?;
+                    }
+                }
+                if matches!(term, Term::Let(..)) {
+                    scope.push((binding.var.clone(), binding.ty.clone()));
+                }
+
+                self.term(scope, body, out)
+            }
+            Term::Comp(comp) => self.comp(comp, scope, out),
+        }
+    }
+
+    fn comp(
+        &mut self,
+        comp: &Comp,
+        scope: &Scope,
+        out: &mut String,
+    ) -> anyhow::Result<(String, Repr)> {
+        match comp {
+            Comp::Atom(atom) => self.atom(atom, scope),
+            Comp::App(Atom::Var(fun), arg) if self.is_builtin(fun, scope, "inc") => {
+                let (arg, repr) = self.atom(arg, scope)?;
+                let arg = repr.coerce(&arg, Repr::Int)?;
+                Ok((format!("wrapping_add({arg}, 1)"), Repr::Int))
+            }
+            Comp::App(Atom::Var(fun), arg) if self.is_builtin(fun, scope, "dec") => {
+                let (arg, repr) = self.atom(arg, scope)?;
+                let arg = repr.coerce(&arg, Repr::Int)?;
+                Ok((format!("wrapping_add({arg}, -1)"), Repr::Int))
+            }
+            Comp::App(Atom::Var(fun), arg) if self.partial_added(&fun.name).is_some() => {
+                let lhs = self.partial_added(&fun.name).unwrap().to_string();
+                let (arg, repr) = self.atom(arg, scope)?;
+                let arg = repr.coerce(&arg, Repr::Int)?;
+                Ok((format!("wrapping_add({lhs}, {arg})"), Repr::Int))
+            }
+            Comp::App(fun, arg) => {
+                let (fun, fun_repr) = self.atom(fun, scope)?;
+                let (arg, arg_repr) = self.atom(arg, scope)?;
+                let fun = fun_repr.coerce(&fun, Repr::Boxed)?;
+                let arg = arg_repr.coerce(&arg, Repr::Boxed)?;
+                Ok((format!("apply({fun}, {arg})"), Repr::Boxed))
+            }
+            Comp::Abs(lambda) => {
+                self.temps += 1;
+                let name = format!("clo_{}", self.temps);
+                self.closure(&name, lambda, scope, out)?;
+                Ok((name, Repr::Boxed))
+            }
+        }
+    }
+
+    fn atom(&self, atom: &Atom, scope: &Scope) -> anyhow::Result<(String, Repr)> {
+        Ok(match atom {
+            Atom::Lit(Literal::Int(i)) if *i < 0 => {
+                (format!("(int64_t)UINT64_C({})", *i as u64), Repr::Int)
+            }
+            Atom::Lit(Literal::Int(i)) => (format!("INT64_C({i})"), Repr::Int),
+            Atom::Lit(Literal::Bool(b)) => (b.to_string(), Repr::Bool),
+            Atom::Lit(Literal::Float(x)) => (format!("mk_float({x:?})"), Repr::Boxed),
+            Atom::Lit(Literal::Char(ch)) => (format!("mk_char({})", *ch as u32), Repr::Boxed),
+            Atom::Lit(Literal::Str(s)) => {
+                (format!("mk_str({}, {})", c_string(s), s.len()), Repr::Boxed)
+            }
+            Atom::Lit(Literal::Unit) => ("&unit_value".to_string(), Repr::Boxed),
+            Atom::Var(ident) => match scope.iter().rev().find(|(var, _)| *var == ident.name) {
+                Some((var, ty)) => (c_name(var), Repr::of_poly(ty)),
+                None if BUILTINS.contains(&ident.name.as_str()) => {
+                    let name = &ident.name;
+                    let closure = format!("mk_closure(builtin_{name}, \"{name}\", 0)");
+                    (closure, Repr::Boxed)
+                }
+                None => anyhow::bail!("`{}` has no C implementation", ident.name),
+            },
+            Atom::Con(ident) => match self.ctx.constrains.get(&ident.name) {
+                Some(ty) => {
+                    let arity = arity(ty.split().1);
+                    let con = format!("mk_con({}, {arity})", c_string(&ident.name));
+                    (con, Repr::Boxed)
+                }
+                None => anyhow::bail!("Undefined constructor: {}", ident.name),
+            },
+        })
+    }
+
+    /// Declares `name` as a closure of `lambda`, capturing the variables it uses.
+    fn closure(
+        &mut self,
+        name: &str,
+        lambda: &Lambda,
+        scope: &Scope,
+        out: &mut String,
+    ) -> anyhow::Result<()> {
+        let captured: Scope = scope
+            .iter()
+            .filter(|(var, _)| lambda.body.mentions(var))
+            .cloned()
+            .collect();
+        let function = self.function(lambda, &captured)?;
+
+        writeln!(
+            out,
+            "    value *{name} = mk_closure(lambda_{function}, NULL, {});",
+            captured.len()
+        )?;
+        for (i, (var, ty)) in captured.iter().enumerate() {
+            let value = Repr::of_poly(ty).coerce(&c_name(var), Repr::Boxed)?;
+            writeln!(out, "    {name}->clo.env[{i}] = {value};")?;
+        }
+
+        Ok(())
+    }
+
+    /// Generates the C function of a lambda and returns its number.
+    fn function(&mut self, lambda: &Lambda, captured: &Scope) -> anyhow::Result<usize> {
+        let id = self.functions.len();
+        self.functions.push(String::new());
+        let partial_adds = std::mem::take(&mut self.partial_adds);
+
+        let mut body = String::new();
+        let mut scope = captured.clone();
+        for (i, (var, ty)) in captured.iter().enumerate() {
+            let repr = Repr::of_poly(ty);
+            let value = Repr::Boxed.coerce(&format!("self->clo.env[{i}]"), repr)?;
+            writeln!(body, "    {} = {value};", repr.declare(&c_name(var)))?;
+        }
+        let repr = Repr::of(&lambda.arg_ty);
+        let value = Repr::Boxed.coerce("arg", repr)?;
+        writeln!(
+            body,
+            "    {} = {value};",
+            repr.declare(&c_name(&lambda.arg))
+        )?;
+        scope.push((lambda.arg.clone(), PolyType::Mono(lambda.arg_ty.clone())));
+
+        let (result, repr) = self.term(&mut scope, &lambda.body, &mut body)?;
+        writeln!(body, "    return {};", repr.coerce(&result, Repr::Boxed)?)?;
+        self.functions[id] =
+            format!("static value *lambda_{id}(value *self, value *arg) {{\n{body}}}\n");
+        self.partial_adds = partial_adds;
+
+        Ok(id)
+    }
+
+    /// The unboxed argument of `add` if `comp` applies the builtin `add` to an `Int`.
+    fn partial_add(&self, comp: &Comp, scope: &Scope) -> anyhow::Result<Option<String>> {
+        match comp {
+            Comp::App(Atom::Var(fun), arg) if self.is_builtin(fun, scope, "add") => {
+                let (arg, repr) = self.atom(arg, scope)?;
+                Ok(Some(repr.coerce(&arg, Repr::Int)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn partial_added(&self, var: &str) -> Option<&str> {
+        let (_, arg) = self.partial_adds.iter().find(|(v, _)| v == var)?;
+        Some(arg)
+    }
+
+    fn is_builtin(&self, ident: &Ident, scope: &Scope, name: &str) -> bool {
+        ident.name == name && !scope.iter().any(|(var, _)| *var == ident.name)
+    }
+}
+
+/// The number of arguments a constructor of type `ty` takes.
+fn arity(ty: &MonoType) -> usize {
+    match ty {
+        MonoType::TyApp(app) if app.func == "->" => 1 + arity(&app.monotypes[1]),
+        _ => 0,
+    }
+}
+
+/// Mangles a variable into a C identifier that can't clash with the runtime's.
+fn c_name(var: &str) -> String {
+    let mut name = "v_".to_string();
+    for ch in var.chars() {
+        match ch {
+            '_' => name.push_str("__"),
+            '\'' => name.push_str("_q"),
+            _ if ch.is_ascii_alphanumeric() => name.push(ch),
+            _ => write!(name, "_u{:x}_", ch as u32).unwrap(),
+        }
+    }
+
+    name
+}
+
+/// A C string literal of the UTF-8 bytes of `s`, with octal escapes for anything but
+/// printable ASCII. `?` is escaped too, so that it can't start a trigraph.
+fn c_string(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(out, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => out.push(byte as char),
+            _ => write!(out, "\\{byte:03o}").unwrap(),
+        }
+    }
+    out.push('"');
+
+    out
+}
@@ -0,0 +1,120 @@
+//! Wadler-style documents ("A prettier printer") for width-aware layout.
+
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    /// A space when its group fits on the line, otherwise a newline plus indentation.
+    Line,
+    /// Always a newline; the groups around it can't be laid out flat.
+    HardLine,
+    /// Text put off to the end of its line, like a trailing comment. Nothing else may
+    /// follow it on a line laid out flat.
+    LineSuffix(String),
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    /// Lays out its content flat if it fits in the remaining width.
+    Group(Box<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+impl Doc {
+    pub fn text(s: impl Into<String>) -> Doc {
+        Doc::Text(s.into())
+    }
+
+    pub fn concat(docs: impl IntoIterator<Item = Doc>) -> Doc {
+        Doc::Concat(docs.into_iter().collect())
+    }
+
+    pub fn nest(self, indent: usize) -> Doc {
+        Doc::Nest(indent, Box::new(self))
+    }
+
+    pub fn group(self) -> Doc {
+        Doc::Group(Box::new(self))
+    }
+
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut col = 0;
+        let mut suffixes = String::new();
+        let mut stack = vec![(0, Mode::Break, self)];
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil => {}
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    col += s.chars().count();
+                }
+                Doc::Line if mode == Mode::Flat => {
+                    out.push(' ');
+                    col += 1;
+                }
+                Doc::LineSuffix(s) => suffixes.push_str(s),
+                Doc::Line | Doc::HardLine => {
+                    out.push_str(&std::mem::take(&mut suffixes));
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                    col = indent;
+                }
+                Doc::Nest(i, doc) => stack.push((indent + i, mode, doc)),
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+                Doc::Group(doc) if mode == Mode::Flat => stack.push((indent, Mode::Flat, doc)),
+                Doc::Group(doc) => {
+                    let remaining =
+                        isize::try_from(width.saturating_sub(col)).unwrap_or(isize::MAX);
+                    let mode = if fits(remaining, (indent, Mode::Flat, doc), &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+            }
+        }
+        out.push_str(&suffixes);
+
+        out
+    }
+}
+
+/// Whether `next` laid out flat, followed by `rest` up to its first line break,
+/// stays within `remaining` columns.
+fn fits(mut remaining: isize, next: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut rest = rest.iter().rev();
+    let mut stack = vec![next];
+    // nothing may follow a line suffix on its line
+    let mut suffixed = false;
+
+    while remaining >= 0 {
+        let (indent, mode, doc) = match stack.pop() {
+            Some(item) => item,
+            None => match rest.next() {
+                Some(&item) => item,
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) if suffixed && !s.is_empty() => return false,
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line if mode == Mode::Flat && suffixed => return false,
+            Doc::Line if mode == Mode::Flat => remaining -= 1,
+            Doc::Line => return true,
+            Doc::HardLine => return mode == Mode::Break,
+            Doc::LineSuffix(_) => suffixed = true,
+            Doc::Nest(i, doc) => stack.push((indent + i, mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+            Doc::Group(doc) => stack.push((indent, mode, doc)),
+        }
+    }
+
+    false
+}
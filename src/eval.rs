@@ -0,0 +1,349 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use crate::models::*;
+use crate::prelude::Builtin;
+use crate::vm;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+    Char(char),
+    Str(Rc<str>),
+    Unit,
+    Closure(Rc<Closure>),
+    /// A closure compiled to bytecode.
+    VmClosure(Rc<vm::Closure>),
+    /// A constructor applied to the arguments it has received so far.
+    Con(String, Vec<Value>),
+    /// A builtin applied to the arguments it has received so far.
+    Builtin(&'static Builtin, Vec<Value>),
+    /// A suspended computation, see [`force`].
+    Thunk(Rc<Thunk>),
+    /// A mutable cell made by `ref`.
+    Ref(Rc<RefCell<Value>>),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Arguments and `let`s are evaluated before they are bound.
+    #[default]
+    CallByValue,
+    /// Arguments and `let`s are bound to thunks, evaluated at most once when needed.
+    CallByNeed,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub arg: String,
+    pub body: Expr,
+    pub env: Env,
+    pub strategy: Strategy,
+}
+
+#[derive(Debug)]
+pub struct Thunk {
+    state: RefCell<ThunkState>,
+}
+
+#[derive(Debug)]
+enum ThunkState {
+    Delayed(Expr, Env),
+    /// Being evaluated, forcing it again means the value depends on itself.
+    Forcing,
+    Forced(Value),
+}
+
+impl Thunk {
+    fn new(state: ThunkState) -> Rc<Thunk> {
+        Rc::new(Thunk {
+            state: RefCell::new(state),
+        })
+    }
+
+    /// A thunk for a recursive binding, to be [`Thunk::fill`]ed once it is evaluated.
+    pub fn pending() -> Rc<Thunk> {
+        Thunk::new(ThunkState::Forcing)
+    }
+
+    pub fn fill(&self, value: Value) {
+        *self.state.borrow_mut() = ThunkState::Forced(value);
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(lit: &Literal) -> Self {
+        match lit {
+            Literal::Int(i) => Value::Int(*i),
+            Literal::Bool(b) => Value::Bool(*b),
+            Literal::Float(x) => Value::Float(*x),
+            Literal::Char(ch) => Value::Char(*ch),
+            Literal::Str(s) => Value::Str(s.as_str().into()),
+            Literal::Unit => Value::Unit,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", Literal::Int(*i)),
+            Value::Bool(b) => write!(f, "{}", Literal::Bool(*b)),
+            Value::Float(x) => write!(f, "{}", Literal::Float(*x)),
+            Value::Char(ch) => write!(f, "{}", Literal::Char(*ch)),
+            Value::Str(s) => write!(f, "{}", Literal::Str(s.to_string())),
+            Value::Unit => write!(f, "{}", Literal::Unit),
+            Value::Closure(_) | Value::VmClosure(_) => write!(f, "<closure>"),
+            Value::Con(con, args) => {
+                write!(f, "{con}")?;
+                for arg in args {
+                    if arg.is_atom() {
+                        write!(f, " {arg}")?;
+                    } else {
+                        write!(f, " ({arg})")?;
+                    }
+                }
+                Ok(())
+            }
+            Value::Builtin(builtin, _) => write!(f, "<builtin {}>", builtin.name),
+            Value::Ref(_) => write!(f, "<ref>"),
+            Value::Thunk(thunk) => match &*thunk.state.borrow() {
+                ThunkState::Forced(value) => write!(f, "{value}"),
+                _ => write!(f, "<thunk>"),
+            },
+        }
+    }
+}
+
+impl Value {
+    /// Whether the value prints without parentheses as an argument, which a negative
+    /// number can't since `f -1` is a subtraction.
+    fn is_atom(&self) -> bool {
+        match self {
+            Value::Con(_, args) => args.is_empty(),
+            Value::Int(int) => *int >= 0,
+            Value::Float(float) => !float.is_sign_negative(),
+            Value::Thunk(thunk) => match &*thunk.state.borrow() {
+                ThunkState::Forced(value) => value.is_atom(),
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+}
+
+/// The values in scope, as a persistent linked list so closures can share it.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    head: Option<Rc<Binding>>,
+}
+
+#[derive(Debug)]
+struct Binding {
+    name: String,
+    value: Value,
+    next: Env,
+}
+
+impl Env {
+    pub fn extend_with(&self, name: String, value: Value) -> Env {
+        Env {
+            head: Some(Rc::new(Binding {
+                name,
+                value,
+                next: self.clone(),
+            })),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let mut env = self;
+        while let Some(binding) = &env.head {
+            if binding.name == name {
+                return Some(&binding.value);
+            }
+            env = &binding.next;
+        }
+
+        None
+    }
+}
+
+/// Evaluates `expr` call-by-value.
+pub fn eval(env: &Env, expr: &Expr) -> anyhow::Result<Value> {
+    eval_with(Strategy::CallByValue, env, expr)
+}
+
+/// Evaluates `expr` to weak head normal form, the result is never a thunk.
+pub fn eval_with(strategy: Strategy, env: &Env, expr: &Expr) -> anyhow::Result<Value> {
+    match expr {
+        Expr::Lit(lit) => Ok(lit.into()),
+        Expr::Var(var) => match env.get(var) {
+            Some(Value::Builtin(builtin, _))
+                if strategy == Strategy::CallByNeed && builtin.is_effectful() =>
+            {
+                anyhow::bail!("`{var}` needs call-by-value, its effects would happen out of order")
+            }
+            Some(value) => force(value.clone()),
+            None => anyhow::bail!("Undefiend variable: {var}"),
+        },
+        Expr::Con(con) => Ok(Value::Con(con.clone(), vec![])),
+        Expr::Abs(abs) => Ok(Value::Closure(Rc::new(Closure {
+            arg: abs.arg.clone(),
+            body: abs.body.clone(),
+            env: env.clone(),
+            strategy,
+        }))),
+        Expr::App(app) => {
+            let fun = eval_with(strategy, env, &app.fun)?;
+            let arg = bind(strategy, env, &app.arg)?;
+            apply(fun, arg)
+        }
+        Expr::Let(elet) => {
+            let value = bind(strategy, env, &elet.vexpr)?;
+            eval_with(
+                strategy,
+                &env.extend_with(elet.var.clone(), value),
+                &elet.aexpr,
+            )
+        }
+        Expr::LetRec(elet) => {
+            // `var` is bound to a thunk that refers to the environment it is bound in
+            let thunk = Thunk::pending();
+            let env = env.extend_with(elet.var.clone(), Value::Thunk(thunk.clone()));
+            let state = match strategy {
+                Strategy::CallByValue => {
+                    ThunkState::Forced(eval_with(strategy, &env, &elet.vexpr)?)
+                }
+                Strategy::CallByNeed => ThunkState::Delayed(elet.vexpr.clone(), env.clone()),
+            };
+            *thunk.state.borrow_mut() = state;
+
+            eval_with(strategy, &env, &elet.aexpr)
+        }
+        Expr::Ann(ann) => eval_with(strategy, env, &ann.expr),
+        Expr::Case(case) => {
+            let value = eval_with(strategy, env, &case.expr)?;
+            let (con, args) = match value {
+                Value::Con(con, args) => (con, args),
+                value => anyhow::bail!("`case` expects a constructor, got `{value}`"),
+            };
+            let Some(arm) = case
+                .arms
+                .iter()
+                .find(|arm| arm.con == con && arm.vars.len() == args.len())
+            else {
+                anyhow::bail!("No arm of the case matches `{}`", Value::Con(con, args));
+            };
+            let env = arm
+                .vars
+                .iter()
+                .zip(args)
+                .fold(env.clone(), |env, (var, arg)| {
+                    env.extend_with(var.clone(), arg)
+                });
+
+            eval_with(strategy, &env, &arm.body)
+        }
+    }
+}
+
+/// Evaluates what gets bound to a variable: the value itself when strict, otherwise a
+/// thunk, reusing the one of a variable so that its evaluation is shared.
+fn bind(strategy: Strategy, env: &Env, expr: &Expr) -> anyhow::Result<Value> {
+    match (strategy, expr) {
+        (Strategy::CallByValue, _) => eval_with(strategy, env, expr),
+        (Strategy::CallByNeed, Expr::Var(var)) => match env.get(var) {
+            Some(value) => Ok(value.clone()),
+            None => anyhow::bail!("Undefiend variable: {var}"),
+        },
+        (Strategy::CallByNeed, Expr::Lit(lit)) => Ok(lit.into()),
+        (Strategy::CallByNeed, _) => Ok(Value::Thunk(Thunk::new(ThunkState::Delayed(
+            expr.clone(),
+            env.clone(),
+        )))),
+    }
+}
+
+/// Evaluates a thunk to weak head normal form and remembers the result.
+pub fn force(value: Value) -> anyhow::Result<Value> {
+    let thunk = match value {
+        Value::Thunk(thunk) => thunk,
+        value => return Ok(value),
+    };
+
+    let state = std::mem::replace(&mut *thunk.state.borrow_mut(), ThunkState::Forcing);
+    let value = match state {
+        ThunkState::Forced(value) => value,
+        ThunkState::Forcing => anyhow::bail!("Infinite loop detected: a value depends on itself"),
+        ThunkState::Delayed(expr, env) => match eval_with(Strategy::CallByNeed, &env, &expr) {
+            Ok(value) => value,
+            Err(err) => {
+                *thunk.state.borrow_mut() = ThunkState::Delayed(expr, env);
+                return Err(err);
+            }
+        },
+    };
+    *thunk.state.borrow_mut() = ThunkState::Forced(value.clone());
+
+    Ok(value)
+}
+
+/// How deep [`force_deep`] goes into constructors, a lazy value can be infinite.
+pub const MAX_DEPTH: usize = 10_000;
+
+/// Forces every thunk inside constructors, for printing or comparing results. The
+/// constructors are rebuilt on a stack of their own, not the native one.
+pub fn force_deep(value: Value) -> anyhow::Result<Value> {
+    enum Task {
+        Force(Value, usize),
+        /// Rebuilds a constructor from its forced arguments.
+        Build(String, usize),
+    }
+
+    let mut tasks = vec![Task::Force(value, 0)];
+    let mut forced = vec![];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Force(value, depth) => match force(value)? {
+                Value::Con(_, args) if !args.is_empty() && depth == MAX_DEPTH => anyhow::bail!(
+                    "The value is nested more than {MAX_DEPTH} constructors deep, it may be infinite"
+                ),
+                Value::Con(con, args) => {
+                    tasks.push(Task::Build(con, args.len()));
+                    tasks.extend(args.into_iter().rev().map(|arg| Task::Force(arg, depth + 1)));
+                }
+                value => forced.push(value),
+            },
+            Task::Build(con, arity) => {
+                let args = forced.split_off(forced.len() - arity);
+                forced.push(Value::Con(con, args));
+            }
+        }
+    }
+
+    Ok(forced.pop().unwrap())
+}
+
+pub fn apply(fun: Value, arg: Value) -> anyhow::Result<Value> {
+    match fun {
+        Value::Closure(closure) => {
+            let env = closure.env.extend_with(closure.arg.clone(), arg);
+            eval_with(closure.strategy, &env, &closure.body)
+        }
+        Value::Con(con, mut args) => {
+            args.push(arg);
+            Ok(Value::Con(con, args))
+        }
+        Value::Builtin(builtin, mut args) => {
+            args.push(arg);
+            if args.len() < builtin.arity {
+                Ok(Value::Builtin(builtin, args))
+            } else {
+                force((builtin.fun)(&args)?)
+            }
+        }
+        _ => anyhow::bail!("Could not apply `{fun}`, it is not a function"),
+    }
+}
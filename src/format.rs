@@ -0,0 +1,76 @@
+//! The source formatter run by `hmfmt`.
+
+use anyhow::Context;
+
+use crate::lex::Lexer;
+use crate::parser::Parser;
+use crate::pretty::{Comments, ExprPrinter};
+
+pub const USAGE: &str = "usage: hmfmt [--check] [--width <n>] [<file>...]
+
+Formats each file in place, or stdin to stdout when no file is given.
+
+    --check        don't write anything, exit with 1 if a file isn't formatted
+    --width <n>    maximum line width (default 80)";
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub check: bool,
+    pub help: bool,
+    pub printer: ExprPrinter,
+    pub files: Vec<String>,
+}
+
+impl Options {
+    /// Reads the command line arguments, without the name of the program.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Options> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check" => options.check = true,
+                "--width" => {
+                    let width = args.next().context("expected a number after --width")?;
+                    options.printer.width = width
+                        .parse()
+                        .with_context(|| format!("invalid width `{width}`"))?;
+                }
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => anyhow::bail!("unknown option `{arg}`\n\n{USAGE}"),
+                _ => options.files.push(arg),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Formats a whole source file, keeping its comments.
+pub fn format_source(source: &str, printer: &ExprPrinter) -> anyhow::Result<String> {
+    let program = Parser::new(Lexer::new(source)).parse_program()?;
+    let mut formatted = printer.print_program_with_comments(&program, &Comments::collect(source));
+    formatted.push('\n');
+
+    Ok(formatted)
+}
+
+/// Formats the files of `options` in place, or only checks them with `--check`.
+/// Gives the files that weren't formatted.
+pub fn format_files(options: &Options) -> anyhow::Result<Vec<String>> {
+    let mut unformatted = vec![];
+    for file in options.files.iter() {
+        let source =
+            std::fs::read_to_string(file).with_context(|| format!("failed to read {file}"))?;
+        let formatted = format_source(&source, &options.printer).context(file.clone())?;
+        if formatted == source {
+            continue;
+        }
+
+        if !options.check {
+            std::fs::write(file, formatted).with_context(|| format!("failed to write {file}"))?;
+        }
+        unformatted.push(file.clone());
+    }
+
+    Ok(unformatted)
+}
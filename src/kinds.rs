@@ -0,0 +1,524 @@
+//! Kinds, the types of type constructors, inferred for data declarations, synonyms and
+//! classes and checked for every type a program mentions before its own types are
+//! inferred.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::Context as _;
+
+use crate::lex::Lexer;
+use crate::models::*;
+use crate::parser::Parser;
+use crate::system_f::FType;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// `*`, the kind of the types of values.
+    Star,
+    Arrow(Box<Kind>, Box<Kind>),
+    /// A kind that is still unknown while inferring.
+    Var(usize),
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Star => write!(f, "*"),
+            Kind::Arrow(from, to) if matches!(**from, Kind::Arrow(..)) => {
+                write!(f, "({from}) -> {to}")
+            }
+            Kind::Arrow(from, to) => write!(f, "{from} -> {to}"),
+            Kind::Var(n) => write!(f, "k{n}"),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(Lexer::new(s));
+        let kind = parser.parse_kind()?;
+        parser.expect_eof()?;
+
+        Ok(kind)
+    }
+}
+
+impl Kind {
+    pub fn arrow(from: Kind, to: Kind) -> Kind {
+        Kind::Arrow(Box::new(from), Box::new(to))
+    }
+
+    fn occurs(&self, n: usize) -> bool {
+        match self {
+            Kind::Star => false,
+            Kind::Arrow(from, to) => from.occurs(n) || to.occurs(n),
+            Kind::Var(m) => *m == n,
+        }
+    }
+}
+
+/// The kinds of the type constructors in scope, and of the parameters of classes.
+#[derive(Debug, Clone, Default)]
+pub struct KindEnv {
+    pub types: HashMap<String, Kind>,
+    pub classes: HashMap<String, Kind>,
+    /// The number of parameters of each type synonym, which must all be given.
+    pub synonyms: HashMap<String, usize>,
+}
+
+impl KindEnv {
+    /// Checks the kinds of the types of `ctx` and of everything `program` declares or
+    /// annotates, declaring its data types, synonyms and classes on the way.
+    pub fn check_program(&mut self, ctx: &Context, program: &Program) -> anyhow::Result<()> {
+        self.check_context(ctx)?;
+        check_cycles(program)?;
+        for decl in program.decls.iter() {
+            match decl {
+                Decl::Data(data) => {
+                    self.declare_data(data)?;
+                }
+                Decl::Type(synonym) => {
+                    self.declare_synonym(synonym)?;
+                }
+                Decl::Class(class) => {
+                    self.declare_class(class)?;
+                }
+                Decl::Instance(instance) => self.check_instance(instance)?,
+                Decl::Default(pred) => {
+                    let mut inference = KindInference::new(self);
+                    inference
+                        .pred(pred)
+                        .with_context(|| format!("in `default {pred}`"))?;
+                }
+            }
+        }
+
+        self.check_annotations(&program.body)
+    }
+
+    pub fn check_context(&self, ctx: &Context) -> anyhow::Result<()> {
+        let mut names: Vec<_> = ctx.constrains.keys().collect();
+        names.sort();
+        for name in names {
+            self.check_poly(&ctx.constrains[name])
+                .with_context(|| format!("in the type of `{name}`"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a scheme is the type of values, and its predicates well-kinded.
+    pub fn check_poly(&self, poly: &PolyType) -> anyhow::Result<()> {
+        let (_, mono) = poly.split();
+        let mut inference = KindInference::new(self);
+        for pred in poly.preds() {
+            inference.pred(pred)?;
+        }
+        inference.star(mono)
+    }
+
+    /// Checks the annotations `(e : σ)` in `expr`.
+    pub fn check_annotations(&self, expr: &Expr) -> anyhow::Result<()> {
+        match expr {
+            Expr::Lit(_) | Expr::Var(_) | Expr::Con(_) => Ok(()),
+            Expr::App(app) => {
+                self.check_annotations(&app.fun)?;
+                self.check_annotations(&app.arg)
+            }
+            Expr::Abs(abs) => self.check_annotations(&abs.body),
+            Expr::Let(elet) | Expr::LetRec(elet) => {
+                self.check_annotations(&elet.vexpr)?;
+                self.check_annotations(&elet.aexpr)
+            }
+            Expr::Case(case) => {
+                self.check_annotations(&case.expr)?;
+                for arm in case.arms.iter() {
+                    self.check_annotations(&arm.body)?;
+                }
+                Ok(())
+            }
+            Expr::Ann(ann) => {
+                let mut inference = KindInference::new(self);
+                let kind = inference
+                    .ftype(&ann.ty)
+                    .with_context(|| format!("in the annotation of `{}`", ann.expr))?;
+                inference
+                    .unify(&kind, &Kind::Star)
+                    .with_context(|| format!("`{}` is not the type of a value", ann.ty))?;
+                self.check_annotations(&ann.expr)
+            }
+        }
+    }
+
+    /// Infers the kind of a data type from the fields of its constructors, the
+    /// parameters that nothing constrains are of kind `*`.
+    pub fn declare_data(&mut self, data: &DataDecl) -> anyhow::Result<Kind> {
+        if self.types.contains_key(&data.name) {
+            anyhow::bail!("Type `{}` is already defined", data.name);
+        }
+
+        let mut inference = KindInference::new(self);
+        let params: Vec<_> = data.params.iter().map(|_| inference.fresh()).collect();
+        let kind = params
+            .iter()
+            .rev()
+            .fold(Kind::Star, |acc, param| Kind::arrow(param.clone(), acc));
+        inference.local.insert(data.name.clone(), kind.clone());
+        for (param, kind) in data.params.iter().zip(params) {
+            inference.vars.insert(param.clone(), kind);
+        }
+
+        for con in data.constructors.iter() {
+            // the existential variables of a constructor are its own
+            for var in con.exists.iter() {
+                let kind = inference.fresh();
+                inference.vars.insert(var.clone(), kind);
+            }
+            for field in con.fields.iter() {
+                if let Some(var) = field.type_vars().into_iter().find(|v| {
+                    !data.params.iter().any(|p| p == v) && !con.exists.iter().any(|e| e == v)
+                }) {
+                    anyhow::bail!(
+                        "Type variable `{var}` in `{}` is not a parameter of `{}`",
+                        con.name,
+                        data.name
+                    );
+                }
+                inference.star(field).with_context(|| {
+                    format!("in the constructor `{}` of `{}`", con.name, data.name)
+                })?;
+            }
+            for var in con.exists.iter() {
+                inference.vars.remove(var);
+            }
+        }
+
+        let kind = inference.default(&kind);
+        self.types.insert(data.name.clone(), kind.clone());
+        Ok(kind)
+    }
+
+    /// Infers the kind of a type synonym from the type it stands for, which need not
+    /// be the type of values: `type Parser = List` is of kind `* -> *`.
+    pub fn declare_synonym(&mut self, synonym: &SynonymDecl) -> anyhow::Result<Kind> {
+        if self.types.contains_key(&synonym.name) {
+            anyhow::bail!("Type `{}` is already defined", synonym.name);
+        }
+        if let Some(var) = synonym
+            .ty
+            .type_vars()
+            .into_iter()
+            .find(|v| !synonym.params.iter().any(|p| p == v))
+        {
+            anyhow::bail!(
+                "Type variable `{var}` is not a parameter of the synonym `{}`",
+                synonym.name
+            );
+        }
+
+        let mut inference = KindInference::new(self);
+        let params: Vec<_> = synonym.params.iter().map(|p| inference.var(p)).collect();
+        let body = inference
+            .mono(&synonym.ty)
+            .with_context(|| format!("in `{synonym}`"))?;
+        let kind = params
+            .into_iter()
+            .rev()
+            .fold(body, |acc, param| Kind::arrow(param, acc));
+
+        let kind = inference.default(&kind);
+        self.types.insert(synonym.name.clone(), kind.clone());
+        self.synonyms
+            .insert(synonym.name.clone(), synonym.params.len());
+        Ok(kind)
+    }
+
+    /// Infers the kind of the parameter of a class from its superclasses and methods.
+    pub fn declare_class(&mut self, class: &ClassDecl) -> anyhow::Result<Kind> {
+        let mut inference = KindInference::new(self);
+        let kind = inference.fresh();
+        inference.vars.insert(class.var.clone(), kind.clone());
+        for name in class.supers.iter() {
+            let pred = Pred {
+                class: name.clone(),
+                ty: MonoType::TyVar(class.var.clone()),
+            };
+            inference.pred(&pred)?;
+        }
+        for (name, ty) in class.methods.iter() {
+            inference
+                .star(ty)
+                .with_context(|| format!("in the method `{name}` of `{}`", class.name))?;
+        }
+
+        let kind = inference.default(&kind);
+        self.classes.insert(class.name.clone(), kind.clone());
+        Ok(kind)
+    }
+
+    pub fn check_instance(&self, instance: &InstanceDecl) -> anyhow::Result<()> {
+        let mut inference = KindInference::new(self);
+        for pred in instance.context.iter().chain([&instance.head]) {
+            inference
+                .pred(pred)
+                .with_context(|| format!("in `instance {}`", instance.head))?;
+        }
+        for (_, method) in instance.methods.iter() {
+            self.check_annotations(method)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Kind inference for the types of one declaration or annotation, whose type
+/// variables share their kinds.
+struct KindInference<'a> {
+    env: &'a KindEnv,
+    /// The type being declared, in scope of its own fields.
+    local: HashMap<String, Kind>,
+    vars: HashMap<String, Kind>,
+    subst: HashMap<usize, Kind>,
+    next: usize,
+}
+
+impl<'a> KindInference<'a> {
+    fn new(env: &'a KindEnv) -> Self {
+        Self {
+            env,
+            local: HashMap::new(),
+            vars: HashMap::new(),
+            subst: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    fn fresh(&mut self) -> Kind {
+        self.next += 1;
+        Kind::Var(self.next)
+    }
+
+    fn apply(&self, kind: &Kind) -> Kind {
+        match kind {
+            Kind::Star => Kind::Star,
+            Kind::Arrow(from, to) => Kind::arrow(self.apply(from), self.apply(to)),
+            Kind::Var(n) => match self.subst.get(n) {
+                Some(kind) => self.apply(kind),
+                None => kind.clone(),
+            },
+        }
+    }
+
+    /// Like [`KindInference::apply`], with `*` for the kinds left unknown.
+    fn default(&self, kind: &Kind) -> Kind {
+        match self.apply(kind) {
+            Kind::Arrow(from, to) => Kind::arrow(self.default(&from), self.default(&to)),
+            _ => Kind::Star,
+        }
+    }
+
+    fn unify(&mut self, k1: &Kind, k2: &Kind) -> anyhow::Result<()> {
+        match (self.apply(k1), self.apply(k2)) {
+            (Kind::Star, Kind::Star) => Ok(()),
+            (Kind::Var(n), Kind::Var(m)) if n == m => Ok(()),
+            (Kind::Var(n), kind) | (kind, Kind::Var(n)) => {
+                if kind.occurs(n) {
+                    anyhow::bail!("Infinite kind detected");
+                }
+                self.subst.insert(n, kind);
+                Ok(())
+            }
+            (Kind::Arrow(from1, to1), Kind::Arrow(from2, to2)) => {
+                self.unify(&from1, &from2)?;
+                self.unify(&to1, &to2)
+            }
+            (k1, k2) => anyhow::bail!("Could not match kinds `{k1}` and `{k2}`"),
+        }
+    }
+
+    fn var(&mut self, var: &str) -> Kind {
+        match self.vars.get(var) {
+            Some(kind) => kind.clone(),
+            None => {
+                let kind = self.fresh();
+                self.vars.insert(var.to_string(), kind.clone());
+                kind
+            }
+        }
+    }
+
+    /// The kind of the type constructor `name`, applied to `given` arguments.
+    fn constructor(&self, name: &str, given: usize) -> anyhow::Result<Kind> {
+        if let Some(&arity) = self.env.synonyms.get(name) {
+            if given < arity {
+                anyhow::bail!(
+                    "Type synonym `{name}` is partially applied, it has {arity} parameter(s) but is given {given}"
+                );
+            }
+        }
+        match self.local.get(name).or_else(|| self.env.types.get(name)) {
+            Some(kind) => Ok(kind.clone()),
+            None => anyhow::bail!("Undefined type constructor: {name}"),
+        }
+    }
+
+    /// Applies a type of kind `fun` to types of kinds `args`.
+    fn apply_kinds(
+        &mut self,
+        ty: &dyn Display,
+        fun: Kind,
+        args: Vec<Kind>,
+    ) -> anyhow::Result<Kind> {
+        let result = self.fresh();
+        let expected = args
+            .into_iter()
+            .rev()
+            .fold(result.clone(), |acc, arg| Kind::arrow(arg, acc));
+        self.unify(&fun, &expected).with_context(|| {
+            format!(
+                "`{ty}` is ill-kinded, its head has kind `{}`",
+                self.apply(&fun)
+            )
+        })?;
+
+        Ok(result)
+    }
+
+    fn mono(&mut self, ty: &MonoType) -> anyhow::Result<Kind> {
+        match ty {
+            MonoType::TyVar(var) => Ok(self.var(var)),
+            MonoType::TyApp(app) => {
+                let fun = self.constructor(&app.func, app.monotypes.len())?;
+                let args = app
+                    .monotypes
+                    .iter()
+                    .map(|arg| self.mono(arg))
+                    .collect::<Result<_, _>>()?;
+                self.apply_kinds(ty, fun, args)
+            }
+            MonoType::TyVarApp(app) => {
+                let fun = self.var(&app.var);
+                let args = app
+                    .monotypes
+                    .iter()
+                    .map(|arg| self.mono(arg))
+                    .collect::<Result<_, _>>()?;
+                self.apply_kinds(ty, fun, args)
+            }
+        }
+    }
+
+    fn ftype(&mut self, ty: &FType) -> anyhow::Result<Kind> {
+        match ty {
+            FType::Var(var) => Ok(self.var(var)),
+            FType::Con(head, args) | FType::VarApp(head, args) => {
+                let fun = match ty {
+                    FType::VarApp(..) => self.var(head),
+                    _ => self.constructor(head, args.len())?,
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| self.ftype(arg))
+                    .collect::<Result<_, _>>()?;
+                self.apply_kinds(ty, fun, args)
+            }
+            FType::Forall(var, body) => {
+                let kind = self.fresh();
+                let shadowed = self.vars.insert(var.clone(), kind);
+                let body = self.ftype(body)?;
+                match shadowed {
+                    Some(kind) => self.vars.insert(var.clone(), kind),
+                    None => self.vars.remove(var),
+                };
+                self.unify(&body, &Kind::Star)
+                    .with_context(|| format!("`{ty}` quantifies over a type constructor"))?;
+                Ok(Kind::Star)
+            }
+        }
+    }
+
+    /// Checks that `ty` is the type of values.
+    fn star(&mut self, ty: &MonoType) -> anyhow::Result<()> {
+        let kind = self.mono(ty)?;
+        self.unify(&kind, &Kind::Star)
+            .with_context(|| format!("`{ty}` is not the type of a value"))
+    }
+
+    fn pred(&mut self, pred: &Pred) -> anyhow::Result<()> {
+        let Some(class) = self.env.classes.get(&pred.class) else {
+            anyhow::bail!("Undefined class: {}", pred.class);
+        };
+        let kind = self.mono(&pred.ty)?;
+        self.unify(&kind, class).with_context(|| {
+            format!(
+                "`{pred}` is ill-kinded, `{}` expects a type of kind `{class}`",
+                pred.class
+            )
+        })
+    }
+}
+
+/// Rejects the type synonyms of `program` that stand for themselves, directly or
+/// through one another, as they could be expanded forever.
+fn check_cycles(program: &Program) -> anyhow::Result<()> {
+    fn constructors<'a>(ty: &'a MonoType, acc: &mut Vec<&'a str>) {
+        match ty {
+            MonoType::TyVar(_) => {}
+            MonoType::TyApp(app) => {
+                acc.push(&app.func);
+                app.monotypes.iter().for_each(|m| constructors(m, acc));
+            }
+            MonoType::TyVarApp(app) => app.monotypes.iter().for_each(|m| constructors(m, acc)),
+        }
+    }
+
+    fn visit<'a>(
+        name: &'a str,
+        synonyms: &HashMap<&'a str, &'a SynonymDecl>,
+        path: &mut Vec<&'a str>,
+        done: &mut Vec<&'a str>,
+    ) -> anyhow::Result<()> {
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            anyhow::bail!("Type synonym `{name}` is cyclic: {}", cycle.join(" -> "));
+        }
+        if done.contains(&name) {
+            return Ok(());
+        }
+        let Some(synonym) = synonyms.get(name) else {
+            return Ok(());
+        };
+
+        path.push(name);
+        let mut refs = vec![];
+        constructors(&synonym.ty, &mut refs);
+        for next in refs {
+            visit(next, synonyms, path, done)?;
+        }
+        path.pop();
+        done.push(name);
+        Ok(())
+    }
+
+    let synonyms: HashMap<_, _> = program
+        .decls
+        .iter()
+        .filter_map(|decl| match decl {
+            Decl::Type(synonym) => Some((synonym.name.as_str(), synonym)),
+            _ => None,
+        })
+        .collect();
+    let mut done = vec![];
+    for decl in program.decls.iter() {
+        if let Decl::Type(synonym) = decl {
+            visit(&synonym.name, &synonyms, &mut vec![], &mut done)?;
+        }
+    }
+
+    Ok(())
+}
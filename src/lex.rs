@@ -12,6 +12,12 @@ impl Loc {
     }
 }
 
+impl Default for Loc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Display for Loc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{}", self.row, self.col)
@@ -22,14 +28,44 @@ impl Display for Loc {
 pub enum TokenKind {
     LitBool,
     LitInt,
+    LitFloat,
+    LitChar,
+    LitString,
     Let,
+    LetRec,
     In,
+    Case,
+    Of,
     Var,
+    /// An identifier starting with an uppercase letter.
+    Con,
+    /// `Module.name` or `Module.Con`.
+    QualName,
     BackSlash,
     Eq,
     OpenP,
     ClosP,
     Arrow,
+    Forall,
+    Dot,
+    /// `=>`, after the predicates of a qualified type.
+    FatArrow,
+    Colon,
+    Semi,
+    Comma,
+    /// A symbolic identifier like `+` or `==`, used infix.
+    Op,
+    Class,
+    Instance,
+    Where,
+    Default,
+    Data,
+    Newtype,
+    Type,
+    /// `-- ...` or `{- ... -}`, only produced by [`Lexer::keep_comments`].
+    Comment,
+    /// Malformed input, `value` holds the message.
+    Error,
 }
 
 #[derive(Debug, Clone)]
@@ -39,9 +75,11 @@ pub struct Token {
     pub value: String,
 }
 
+#[derive(Clone)]
 pub struct Lexer<'c> {
     chars: Peekable<Chars<'c>>,
     loc: Loc,
+    keep_comments: bool,
 }
 
 impl<'c> Iterator for Lexer<'c> {
@@ -57,19 +95,30 @@ impl<'c> Lexer<'c> {
         Self {
             chars: source.chars().peekable(),
             loc: Loc::new(),
+            keep_comments: false,
         }
     }
 
+    /// Emits comments as [`TokenKind::Comment`] trivia instead of skipping them.
+    pub fn keep_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
     pub fn lex(&mut self) -> Option<Token> {
         let ch = *self.chars.peek()?;
 
         if ch.is_whitespace() {
             self.skip_whitespace();
             self.lex()
-        } else if ch.is_alphabetic() {
+        } else if is_ident_start(ch) {
             self.lex_var_or_keyword()
         } else if ch.is_numeric() {
-            self.lex_int()
+            self.lex_number(self.loc, String::new())
+        } else if ch == '"' {
+            self.lex_string()
+        } else if ch == '\'' {
+            self.lex_char()
         } else if ch == '\\' {
             self.loc.col += 1;
             self.chars.next();
@@ -78,13 +127,33 @@ impl<'c> Lexer<'c> {
                 kind: TokenKind::BackSlash,
                 value: "\\".to_string(),
             })
-        } else if ch == '=' {
+        } else if ch == ';' || ch == ',' {
+            self.loc.col += 1;
+            self.chars.next();
+            let kind = match ch {
+                ';' => TokenKind::Semi,
+                _ => TokenKind::Comma,
+            };
+            Some(Token {
+                loc: self.loc,
+                kind,
+                value: ch.to_string(),
+            })
+        } else if ch == '.' {
+            self.loc.col += 1;
+            self.chars.next();
+            Some(Token {
+                loc: self.loc,
+                kind: TokenKind::Dot,
+                value: ".".to_string(),
+            })
+        } else if ch == '∀' {
             self.loc.col += 1;
             self.chars.next();
             Some(Token {
                 loc: self.loc,
-                kind: TokenKind::Eq,
-                value: "=".to_string(),
+                kind: TokenKind::Forall,
+                value: "∀".to_string(),
             })
         } else if ch == '(' {
             self.loc.col += 1;
@@ -103,20 +172,123 @@ impl<'c> Lexer<'c> {
                 value: ")".to_string(),
             })
         } else if ch == '-' {
-            self.chars.next();
-            let ra = self.chars.next()?;
-            if ra == '>' {
-                self.loc.col += 2;
-                return Some(Token {
-                    loc: self.loc,
-                    kind: TokenKind::Arrow,
-                    value: "->".to_string(),
-                });
+            // two dashes or more start a comment, unless a symbol makes them an operator
+            // like `-->`
+            let token_loc = self.loc;
+            let mut dashes = String::new();
+            while self.chars.peek() == Some(&'-') {
+                self.push_next(&mut dashes);
+            }
+            if dashes.len() >= 2 && !self.chars.peek().is_some_and(|&ch| is_op_char(ch)) {
+                let comment = self.lex_line_comment(token_loc, dashes);
+                self.comment_or_next(comment)
             } else {
-                return None;
+                self.lex_op(token_loc, dashes)
+            }
+        } else if is_op_char(ch) {
+            self.lex_op(self.loc, String::new())
+        } else if ch == '{' {
+            let token_loc = self.loc;
+            self.chars.next();
+            if self.chars.next_if_eq(&'-').is_none() {
+                return Some(self.error(token_loc, "unexpected character `{`"));
             }
+            self.loc.col += 2;
+            let comment = self.lex_block_comment(token_loc);
+            self.comment_or_next(comment)
         } else {
-            return None;
+            self.chars.next();
+            Some(self.error(self.loc, &format!("unexpected character `{ch}`")))
+        }
+    }
+
+    /// Lexes a run of symbols, `value` holds the ones already consumed. `=`, `->` and
+    /// `=>` are punctuation, any other run is an operator.
+    fn lex_op(&mut self, token_loc: Loc, mut value: String) -> Option<Token> {
+        while self.chars.peek().is_some_and(|&ch| is_op_char(ch)) {
+            self.push_next(&mut value);
+        }
+
+        let kind = match value.as_str() {
+            "=" => TokenKind::Eq,
+            "->" => TokenKind::Arrow,
+            "=>" => TokenKind::FatArrow,
+            ":" => TokenKind::Colon,
+            _ => TokenKind::Op,
+        };
+
+        Some(Token {
+            loc: token_loc,
+            kind,
+            value,
+        })
+    }
+
+    fn error(&self, loc: Loc, message: &str) -> Token {
+        Token {
+            loc,
+            kind: TokenKind::Error,
+            value: message.to_string(),
+        }
+    }
+
+    fn comment_or_next(&mut self, comment: Token) -> Option<Token> {
+        if self.keep_comments || comment.kind == TokenKind::Error {
+            Some(comment)
+        } else {
+            self.lex()
+        }
+    }
+
+    /// Lexes the rest of a line after its leading dashes, `value`.
+    pub fn lex_line_comment(&mut self, token_loc: Loc, mut value: String) -> Token {
+        while let Some(&ch) = self.chars.peek() {
+            if ch == '\n' {
+                break;
+            }
+            self.loc.col += 1;
+            value.push(ch);
+            self.chars.next();
+        }
+
+        Token {
+            loc: token_loc,
+            kind: TokenKind::Comment,
+            value,
+        }
+    }
+
+    /// Lexes a possibly nested block comment after its leading `{-`.
+    /// An unterminated comment is an error token.
+    pub fn lex_block_comment(&mut self, token_loc: Loc) -> Token {
+        let mut value = "{-".to_string();
+        let mut depth = 1;
+        while depth > 0 {
+            let ch = match self.chars.next() {
+                Some(ch) => ch,
+                None => return self.error(token_loc, "unterminated block comment"),
+            };
+            if ch == '\n' {
+                self.loc.col = 1;
+                self.loc.row += 1;
+            } else {
+                self.loc.col += 1;
+            }
+            value.push(ch);
+
+            let opens = ch == '{' && self.chars.peek() == Some(&'-');
+            let closes = ch == '-' && self.chars.peek() == Some(&'}');
+            if opens || closes {
+                depth = if opens { depth + 1 } else { depth - 1 };
+                self.loc.col += 1;
+                value.push(self.chars.next().unwrap());
+            }
+        }
+
+        Token {
+            loc: token_loc,
+            kind: TokenKind::Comment,
+            value,
         }
     }
 
@@ -139,20 +311,37 @@ impl<'c> Lexer<'c> {
     pub fn lex_var_or_keyword(&mut self) -> Option<Token> {
         let token_loc = self.loc;
         let mut value = String::new();
-        while let Some(&ch) = self.chars.peek() {
-            if ch.is_alphanumeric() {
-                self.loc.col += 1;
-                value.push(ch);
-                self.chars.next();
-            } else {
+        self.lex_ident(&mut value);
+
+        // every segment but the last of a qualified name is a module name
+        let mut qualified = false;
+        while is_con(value.rsplit('.').next().unwrap()) {
+            let mut ahead = self.chars.clone();
+            if ahead.next() != Some('.') || !ahead.peek().is_some_and(|&ch| is_ident_start(ch)) {
                 break;
             }
+            qualified = true;
+            self.push_next(&mut value);
+            self.lex_ident(&mut value);
         }
 
         let kind = match value.as_str() {
+            _ if qualified => TokenKind::QualName,
             "true" | "false" => TokenKind::LitBool,
             "let" => TokenKind::Let,
+            "letrec" => TokenKind::LetRec,
             "in" => TokenKind::In,
+            "case" => TokenKind::Case,
+            "of" => TokenKind::Of,
+            "forall" => TokenKind::Forall,
+            "class" => TokenKind::Class,
+            "instance" => TokenKind::Instance,
+            "where" => TokenKind::Where,
+            "default" => TokenKind::Default,
+            "data" => TokenKind::Data,
+            "newtype" => TokenKind::Newtype,
+            "type" => TokenKind::Type,
+            _ if is_con(&value) => TokenKind::Con,
             _ => TokenKind::Var,
         };
 
@@ -163,23 +352,157 @@ impl<'c> Lexer<'c> {
         })
     }
 
-    pub fn lex_int(&mut self) -> Option<Token> {
+    /// Lexes letters, digits and underscores followed by any number of primes.
+    fn lex_ident(&mut self, value: &mut String) {
+        while self
+            .chars
+            .peek()
+            .is_some_and(|&ch| ch.is_alphanumeric() || ch == '_')
+        {
+            self.push_next(value);
+        }
+        while self.chars.peek() == Some(&'\'') {
+            self.push_next(value);
+        }
+    }
+
+    /// Lexes `12`, `-3`, `1.5` or `1e-3`, `value` holds the sign if there is one.
+    pub fn lex_number(&mut self, token_loc: Loc, mut value: String) -> Option<Token> {
+        let mut kind = TokenKind::LitInt;
+        self.lex_digits(&mut value);
+
+        let mut ahead = self.chars.clone();
+        if ahead.next() == Some('.') && ahead.peek().is_some_and(|ch| ch.is_numeric()) {
+            kind = TokenKind::LitFloat;
+            self.push_next(&mut value);
+            self.lex_digits(&mut value);
+        }
+
+        let mut ahead = self.chars.clone();
+        if matches!(ahead.next(), Some('e' | 'E')) {
+            ahead.next_if(|&ch| ch == '+' || ch == '-');
+            if ahead.peek().is_some_and(|ch| ch.is_numeric()) {
+                kind = TokenKind::LitFloat;
+                self.push_next(&mut value);
+                if matches!(self.chars.peek(), Some('+' | '-')) {
+                    self.push_next(&mut value);
+                }
+                self.lex_digits(&mut value);
+            }
+        }
+        // `inf` wouldn't lex back
+        if kind == TokenKind::LitFloat && value.parse::<f64>().is_ok_and(f64::is_infinite) {
+            return Some(self.error(token_loc, "float literal out of range"));
+        }
+
+        Some(Token {
+            loc: token_loc,
+            kind,
+            value,
+        })
+    }
+
+    /// Lexes a string literal, `value` holds the unescaped string.
+    pub fn lex_string(&mut self) -> Option<Token> {
         let token_loc = self.loc;
+        self.loc.col += 1;
+        self.chars.next();
+
         let mut value = String::new();
-        while let Some(&ch) = self.chars.peek() {
-            if ch.is_numeric() {
-                self.loc.col += 1;
-                value.push(ch);
-                self.chars.next();
-            } else {
-                break;
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return Some(self.error(token_loc, "unterminated string")),
+                Some('"') => break,
+                Some('\\') => match self.lex_escape() {
+                    Ok(ch) => value.push(ch),
+                    Err(message) => return Some(self.error(token_loc, &message)),
+                },
+                Some(ch) => value.push(ch),
             }
+            self.loc.col += 1;
         }
+        self.loc.col += 1;
 
         Some(Token {
             loc: token_loc,
-            kind: TokenKind::LitInt,
+            kind: TokenKind::LitString,
             value,
         })
     }
+
+    /// Lexes a char literal, `value` holds the unescaped char.
+    pub fn lex_char(&mut self) -> Option<Token> {
+        let token_loc = self.loc;
+        self.loc.col += 1;
+        self.chars.next();
+
+        let ch = match self.chars.next() {
+            None | Some('\n') | Some('\'') => {
+                return Some(self.error(token_loc, "expected a char literal"))
+            }
+            Some('\\') => match self.lex_escape() {
+                Ok(ch) => ch,
+                Err(message) => return Some(self.error(token_loc, &message)),
+            },
+            Some(ch) => ch,
+        };
+        self.loc.col += 1;
+        if self.chars.next_if_eq(&'\'').is_none() {
+            return Some(self.error(token_loc, "unterminated char literal"));
+        }
+        self.loc.col += 1;
+
+        Some(Token {
+            loc: token_loc,
+            kind: TokenKind::LitChar,
+            value: ch.to_string(),
+        })
+    }
+
+    /// Lexes the char after a `\` that has already been consumed.
+    fn lex_escape(&mut self) -> Result<char, String> {
+        self.loc.col += 1;
+        let escaped = match self.chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(ch @ ('\\' | '"' | '\'')) => ch,
+            Some(ch) => return Err(format!("unknown escape `\\{ch}`")),
+            None => return Err("unterminated escape".to_string()),
+        };
+
+        Ok(escaped)
+    }
+
+    fn lex_digits(&mut self, value: &mut String) {
+        while self.chars.peek().is_some_and(|ch| ch.is_numeric()) {
+            self.push_next(value);
+        }
+    }
+
+    fn push_next(&mut self, value: &mut String) {
+        if let Some(ch) = self.chars.next() {
+            self.loc.col += 1;
+            value.push(ch);
+        }
+    }
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
+
+/// Identifiers starting with an uppercase letter are reserved for constructors.
+pub fn is_con(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_uppercase())
+}
+
+fn is_op_char(ch: char) -> bool {
+    "+-*/<>=&|^!$~?:".contains(ch)
+}
+
+/// Operators are identifiers made of symbols, they are applied infix.
+pub fn is_op(name: &str) -> bool {
+    name.starts_with(is_op_char)
 }
@@ -1,28 +1,46 @@
 pub mod alg_w;
+pub mod anf;
+pub mod bidir;
+pub mod classes;
+pub mod codegen;
+pub mod doc;
+pub mod eval;
+pub mod format;
+pub mod kinds;
 pub mod lex;
+pub mod lift;
 pub mod models;
+pub mod mono;
 pub mod parser;
+pub mod prelude;
+pub mod pretty;
+pub mod repl;
+pub mod system_f;
+pub mod typed;
 pub mod utils;
+pub mod vm;
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
     use crate::alg_w::alg_w;
+    use crate::eval::*;
     use crate::lex::*;
     use crate::models::*;
     use crate::parser::*;
+    use crate::pretty::*;
 
     macro_rules! infer {
         ($source: expr) => {
             let expr = Parser::new(Lexer::new($source)).parse().unwrap();
-            let ctx = default_ctx();
+            let ctx = empty_ctx();
             let (_, ty) = alg_w(ctx, expr)?;
             println!("`{}` infer as `{}`", $source, ty);
         };
     }
 
-    fn default_ctx() -> Context {
+    fn empty_ctx() -> Context {
         let constrains = HashMap::new();
         Context { constrains }
     }
@@ -36,6 +54,1793 @@ mod tests {
         infer!(r"(let const = (\y -> true) in const)");
         infer!(r"(let const = (\x -> true) in (\y -> (\z -> (const 20))))");
 
+        let cases = [
+            (r"\f x -> f (f x)", "forall a. (a -> a) -> a -> a"),
+            (
+                r"\f g x -> f (g x)",
+                "forall a b c. (a -> b) -> (c -> a) -> c -> b",
+            ),
+            (r"let id = \x -> x in id id", "forall a. a -> a"),
+            (r"\x -> let y = x in y", "forall a. a -> a"),
+        ];
+        for (source, expected) in cases {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let (_, ty) = alg_w(empty_ctx(), expr)?;
+            let ty = ty.generalise(&empty_ctx());
+            assert!(ty.alpha_eq(&expected.parse()?), "{source}: {ty}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_type() -> anyhow::Result<()> {
+        let sources = [
+            "Int",
+            "a -> a",
+            "forall a b. (a -> b) -> List a -> List b",
+            "forall a. forall b. a -> b -> a",
+            "∀f. List (List f) -> (Int -> Bool) -> f",
+            "Map (List a) (b -> c)",
+        ];
+        for source in sources {
+            let poly: PolyType = source.parse()?;
+            let reparsed: PolyType = poly.to_string().parse()?;
+            assert!(poly.alpha_eq(&reparsed), "`{source}` printed as `{poly}`");
+        }
+
+        let poly: PolyType = "forall a b. (a -> b) -> List a -> List b".parse()?;
+        assert!(poly.alpha_eq(&"forall x y. (x -> y) -> List x -> List y".parse()?));
+        assert!(!poly.alpha_eq(&"forall x y. (y -> x) -> List x -> List x".parse()?));
+
+        assert!("forall. a".parse::<PolyType>().is_err());
+        assert!("forall A. A".parse::<PolyType>().is_err());
+        assert!("(a -> b) c".parse::<MonoType>().is_err());
+        assert!("(a -> b".parse::<MonoType>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_type() -> anyhow::Result<()> {
+        let cases = [
+            (
+                "forall x. forall y. (x -> y) -> List x -> List y",
+                "∀a b. (a -> b) -> List a -> List b",
+            ),
+            ("forall q p. p -> q -> p", "∀a b. a -> b -> a"),
+            ("forall b. a -> b", "∀b. a -> b"),
+            ("(a -> b) -> c", "(a -> b) -> c"),
+            ("a -> (b -> c)", "a -> b -> c"),
+            ("List (List (Int -> Int))", "List (List (Int -> Int))"),
+        ];
+        for (source, expected) in cases {
+            let poly: PolyType = source.parse()?;
+            assert_eq!(poly.to_string(), expected);
+        }
+
+        let poly: PolyType = "forall t3 t1. t1 -> t3".parse()?;
+        let ascii = TypePrinter::new(Charset::Ascii, true);
+        assert_eq!(ascii.print_poly(&poly), "forall a b. a -> b");
+        let raw = TypePrinter::new(Charset::Ascii, false);
+        assert_eq!(raw.print_poly(&poly), "forall t3 t1. t1 -> t3");
+        assert_eq!(ascii.print_mono(&"t7 -> t2 -> t7".parse()?), "a -> b -> a");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_expr() -> anyhow::Result<()> {
+        let cases = [
+            (r"((f x) y)", "f x y"),
+            (r"(f (g x))", "f (g x)"),
+            (r"((\x -> x) 10)", r"(\x -> x) 10"),
+            (
+                r"(\x -> (\y -> (let z = (x y) in z)))",
+                r"\x y -> let z = x y in z",
+            ),
+            (
+                r"(let f = (let g = true in g) in f)",
+                "let f = let g = true in g in f",
+            ),
+        ];
+        for (source, expected) in cases {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            assert_eq!(expr.to_string(), expected);
+        }
+
+        let source = r"let compose = \f g x -> f (g x) in
+            let twice = \f -> compose f f in
+            let x = twice inc 10 in add x (dec (add x x))";
+        let expr = Parser::new(Lexer::new(source)).parse()?;
+        let expected = r"let compose = \f g x -> f (g x) in
+let twice = \f -> compose f f in
+let x = twice inc 10 in
+add x (dec (add x x))";
+        assert_eq!(ExprPrinter::new(40).print(&expr), expected);
+
+        for width in [10, 20, 40, 80] {
+            let printed = ExprPrinter::new(width).print(&expr);
+            assert_eq!(Parser::new(Lexer::new(&printed)).parse()?, expr);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format() -> anyhow::Result<()> {
+        use crate::format::*;
+
+        let options = Options::parse(["--width", "20", "a.hm"].map(String::from))?;
+        assert_eq!(options.printer.width, 20);
+        assert_eq!(options.files, ["a.hm"]);
+        assert!(Options::parse(["--width".to_string()]).is_err());
+        assert!(Options::parse(["--width", "wide"].map(String::from)).is_err());
+        assert!(Options::parse(["--tabs".to_string()]).is_err());
+
+        let dir = std::env::temp_dir().join(format!("hmfmt_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let file = dir.join("twice.hm");
+        let path = file.to_string_lossy().to_string();
+        let source = "let twice = (\\f x -> f (f x)) in twice inc 1";
+        std::fs::write(&file, source)?;
+
+        // `--check` reports the file without writing it
+        let check = Options::parse(["--check", "--width", "20", &path].map(String::from))?;
+        assert_eq!(format_files(&check)?, [path.as_str()]);
+        assert_eq!(std::fs::read_to_string(&file)?, source);
+
+        let options = Options::parse(["--width", "20", &path].map(String::from))?;
+        assert_eq!(format_files(&options)?, [path.as_str()]);
+        let formatted = std::fs::read_to_string(&file)?;
+        assert_eq!(
+            formatted,
+            "let twice =\n  \\f x -> f (f x)\nin\ntwice inc 1\n"
+        );
+        assert!(format_files(&check)?.is_empty());
+
+        // wider lines fit the program on one line again
+        let wide = Options::parse(["--check", &path].map(String::from))?;
+        assert_eq!(format_files(&wide)?, [path.as_str()]);
+
+        std::fs::write(&file, "let x = in 1")?;
+        assert!(format_files(&options).is_err());
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repl() -> anyhow::Result<()> {
+        use crate::eval::Strategy;
+        use crate::repl::run_line;
+
+        let cases = [
+            ("1 + 2", "it : Int = 3\n"),
+            // the value restriction leaves weak variables
+            ("ref Nil", "it : Ref (List '_a) = <ref>\n"),
+            (
+                r"let r = ref (\x -> x) in Cons !r Nil",
+                "it : List ('_a -> '_a) = Cons <closure> Nil\n",
+            ),
+            (r"\x -> ref x", "it : ∀a. a -> Ref a = <closure>\n"),
+            ("let x =", "Syntax Error: expected vexpr: Unexpected EOF\n"),
+            (
+                "type P a = a -> Bool in type Q = P in 1",
+                "Type Error: in `type Q = P`: Type synonym `P` is partially applied, it has 1 parameter(s) but is given 0\n",
+            ),
+            (
+                "data T = T (List) in 1",
+                "Type Error: in the constructor `T` of `T`: `List` is not the type of a value: Could not match kinds `* -> *` and `*`\n",
+            ),
+            (
+                "class Show a where show : a -> Str in 1",
+                "Type Error: in the method `show` of `Show`: Undefined type constructor: Str\n",
+            ),
+            (r"let (-) = \x y -> add x y in let x = 3 in x -1", "it : Int = 4\n"),
+            // higher-rank annotations are left to the bidirectional checker
+            (
+                r"let g = (\f -> let a = f 1 in f true : (forall a. a -> a) -> Bool) in g (\x -> x)",
+                "it : Bool = true\n",
+            ),
+            (
+                r"let k = (\f -> f : (forall a. a -> a) -> Int -> Int) in k (\x -> x) 1",
+                "it : Int = 1\n",
+            ),
+            (
+                r"newtype N = N Int in let k = (\f -> f : (forall a. a -> a) -> N -> N) in k (\x -> x) (N 1)",
+                "it : N = 1\n",
+            ),
+            (
+                r"type P a = a -> Bool in let k = (\f -> (\x -> true : P Int) : (forall a. a -> a) -> Int -> Bool) in 1",
+                "it : Int = 1\n",
+            ),
+            (
+                r"type P a = a -> Bool in let k = (\f -> f 1 : (forall a. P a) -> Bool) in k (\x -> true)",
+                "it : Bool = true\n",
+            ),
+            (
+                r"(\f -> f 1 : (forall a. a -> a) -> Int)",
+                "Type Error: The type of the program `(∀a. a -> a) -> Int` is of higher rank\n",
+            ),
+            (
+                r"let g = (\f -> f 1 + 1 : (forall a. a -> a) -> Int) in g (\x -> x)",
+                "Type Error: The method `+` can't be used along with higher-rank annotations\n",
+            ),
+            (
+                r"let g = (\f -> f 1 : (forall a. a -> a) -> Int) in case list 1 of Cons x xs -> g (\y -> y)",
+                "Type Error: `case` can't be used along with higher-rank annotations\n",
+            ),
+        ];
+        for (source, output) in cases {
+            assert_eq!(run_line(source, Strategy::CallByValue), output, "{source}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comments() -> anyhow::Result<()> {
+        let source = "-- line\n{- a {- nested -}\n block -} x -- after\ny";
+        let tokens: Vec<_> = Lexer::new(source).map(|t| t.value).collect();
+        assert_eq!(tokens, ["x", "y"]);
+
+        let comments: Vec<_> = Lexer::new(source)
+            .keep_comments()
+            .filter(|t| t.kind == TokenKind::Comment)
+            .map(|t| (t.value, t.loc.row, t.loc.col))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                ("-- line".to_string(), 1, 1),
+                ("{- a {- nested -}\n block -}".to_string(), 2, 1),
+                ("-- after".to_string(), 3, 13),
+            ]
+        );
+        let y = Lexer::new(source).last().unwrap();
+        assert_eq!((y.loc.row, y.loc.col), (4, 1));
+
+        let source = "-- identity\nlet id = \\x -> x in\n-- apply it\nid 1 -- done\n";
+        let expr = Parser::new(Lexer::new(source)).parse()?;
+        let printed = ExprPrinter::default().print_with_comments(&expr, &Comments::collect(source));
+        assert_eq!(
+            printed,
+            "-- identity\nlet id = \\x -> x in\n-- apply it\nid 1 -- done"
+        );
+
+        // operators may start with `--` but a run of dashes alone starts a comment, and
+        // comments stay at the end of their lines
+        let ops: Vec<_> = Lexer::new("x --> y --- z").map(|t| t.kind).collect();
+        assert_eq!(ops, [TokenKind::Var, TokenKind::Op, TokenKind::Var]);
+        let source = "data L a\n  = N -- empty\n  | C a (L a) -- more\nin\ntype P = Int -- pred\nin\nlet (-->) = \\x y -> y in\n1 --> 2";
+        let program: Program = source.parse()?;
+        let printed = ExprPrinter::default()
+            .print_program_with_comments(&program, &Comments::collect(source));
+        assert_eq!(
+            printed,
+            "data L a =\n  N -- empty\n  | C a (L a) -- more\nin\ntype P = Int in -- pred\nlet (-->) = \\x y -> y in 1 --> 2"
+        );
+        let reprinted: Program = printed.parse()?;
+        assert_eq!(reprinted, program);
+        assert_eq!(
+            ExprPrinter::default()
+                .print_program_with_comments(&reprinted, &Comments::collect(&printed)),
+            printed
+        );
+
+        let source = "f {- first -} x\n  -- second\n  (g y)";
+        let expr = Parser::new(Lexer::new(source)).parse()?;
+        let printed = ExprPrinter::default().print_with_comments(&expr, &Comments::collect(source));
+        assert_eq!(printed, "f\n  {- first -}\n  x\n  -- second\n  (g y)");
+        let reprinted = Parser::new(Lexer::new(&printed)).parse()?;
+        assert_eq!(
+            ExprPrinter::default().print_with_comments(&reprinted, &Comments::collect(&printed)),
+            printed
+        );
+
+        let error = Lexer::new("x {- open").last().unwrap();
+        assert_eq!(error.kind, TokenKind::Error);
+        assert_eq!(error.value, "unterminated block comment");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_literals() -> anyhow::Result<()> {
+        let cases = [
+            (
+                r#""a\t\"b\"\n""#,
+                Literal::Str("a\t\"b\"\n".to_string()),
+                "String",
+            ),
+            (r"'\''", Literal::Char('\''), "Char"),
+            ("'λ'", Literal::Char('λ'), "Char"),
+            ("1.5", Literal::Float(1.5), "Float"),
+            ("1e-3", Literal::Float(1e-3), "Float"),
+            ("-2.5E2", Literal::Float(-250.0), "Float"),
+            ("-42", Literal::Int(-42), "Int"),
+            ("()", Literal::Unit, "Unit"),
+        ];
+        for (source, lit, ty) in cases {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            assert_eq!(expr, Expr::Lit(lit.clone()));
+            assert_eq!(lit.ty().to_string(), ty);
+            assert_eq!(Parser::new(Lexer::new(&expr.to_string())).parse()?, expr);
+        }
+
+        let expr = Parser::new(Lexer::new("let x = 1.0 in list x")).parse()?;
+        assert_eq!(expr.to_string(), "let x = 1.0 in list x");
+
+        for source in [
+            r#""abc"#, r#""\q""#, "''", "'ab'", "1 % 2", "1e999", "-1.5e400",
+        ] {
+            assert!(Parser::new(Lexer::new(source)).parse().is_err(), "{source}");
+        }
+        let error = Parser::new(Lexer::new("1e999")).parse().unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "float literal out of range in 1:1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_identifiers() -> anyhow::Result<()> {
+        let source = "is_null x' _unused f'' List.map Data.List.foldr Maybe.Just Nil Cons' A.b.c";
+        let tokens: Vec<_> = Lexer::new(source).map(|t| (t.kind, t.value)).collect();
+        let expected = [
+            (TokenKind::Var, "is_null"),
+            (TokenKind::Var, "x'"),
+            (TokenKind::Var, "_unused"),
+            (TokenKind::Var, "f''"),
+            (TokenKind::QualName, "List.map"),
+            (TokenKind::QualName, "Data.List.foldr"),
+            (TokenKind::QualName, "Maybe.Just"),
+            (TokenKind::Con, "Nil"),
+            (TokenKind::Con, "Cons'"),
+            (TokenKind::QualName, "A.b"),
+            (TokenKind::Dot, "."),
+            (TokenKind::Var, "c"),
+        ];
+        let expected: Vec<_> = expected.map(|(k, v)| (k, v.to_string())).into();
+        assert_eq!(tokens, expected);
+
+        let expr = Parser::new(Lexer::new("Maybe.Just (List.map f' Nil)")).parse()?;
+        let expected = Parser::new(Lexer::new("(Maybe.Just ((List.map f') Nil))")).parse()?;
+        assert_eq!(expr, expected);
+        assert!(matches!(&expr, Expr::App(app) if app.fun == Expr::Con("Maybe.Just".to_string())));
+
+        assert!(Parser::new(Lexer::new(r"\X -> X")).parse().is_err());
+        assert!(Parser::new(Lexer::new("let Y = 1 in Y")).parse().is_err());
+
+        let ty: MonoType = "Map.Map k (List.List v)".parse()?;
+        assert_eq!(ty.to_string(), "Map.Map k (List.List v)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval() -> anyhow::Result<()> {
+        let cases = [
+            ("add (inc 1) 1", "3"),
+            (r"let twice = \f x -> f (f x) in twice dec 10", "8"),
+            (r"let id = \x -> x in id id (add 1) 2", "3"),
+            (r"(\x y -> x) true ()", "true"),
+            (
+                "Cons (list 1) (Cons Nil Nil)",
+                "Cons (Cons 1 Nil) (Cons Nil Nil)",
+            ),
+            ("isNull (Cons 1 Nil)", "false"),
+            (r"add 1", "<builtin add>"),
+            (r#"\x -> "a""#, "<closure>"),
+            ("Cons (-1) (Cons (-2.5) Nil)", "Cons (-1) (Cons (-2.5) Nil)"),
+        ];
+        for (source, expected) in cases {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let value = eval(&crate::prelude::default_env(), &expr)?;
+            assert_eq!(value.to_string(), expected, "{source}");
+        }
+        // printed constructors read back
+        let expr = Parser::new(Lexer::new("Cons (-1) (Cons (inc 1) Nil)")).parse()?;
+        let printed = eval(&crate::prelude::default_env(), &expr)?.to_string();
+        assert_eq!(
+            Parser::new(Lexer::new(&printed)).parse()?.to_string(),
+            "Cons (-1) (Cons 2 Nil)"
+        );
+
+        let env = crate::prelude::default_env();
+        assert!(eval(&env, &Parser::new(Lexer::new("undefined")).parse()?).is_err());
+        assert!(eval(&env, &Parser::new(Lexer::new("1 2")).parse()?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_eval() -> anyhow::Result<()> {
+        let run = |strategy, source| -> anyhow::Result<String> {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            alg_w(crate::prelude::default_ctx(), expr.clone())?;
+            let value = eval_with(strategy, &crate::prelude::default_env(), &expr)?;
+            Ok(force_deep(value)?.to_string())
+        };
+
+        let terminating = [
+            "add (inc 1) 1",
+            r"let twice = \f x -> f (f x) in twice dec 10",
+            r"let x = add 1 2 in Cons x (Cons x Nil)",
+            r"letrec f = \x -> x in f (list 5)",
+            r"(\x y -> y) (add 1 2) (take 2 (Cons 1 (Cons 2 (Cons 3 Nil))))",
+            r"head (tail (Cons (inc 0) (list (dec 0))))",
+        ];
+        for source in terminating {
+            let strict = run(Strategy::CallByValue, source)?;
+            let lazy = run(Strategy::CallByNeed, source)?;
+            assert_eq!(strict, lazy, "{source}");
+        }
+
+        let ones = "letrec ones = Cons 1 ones in take 3 ones";
+        assert_eq!(
+            run(Strategy::CallByNeed, ones)?,
+            "Cons 1 (Cons 1 (Cons 1 Nil))"
+        );
+        assert!(run(Strategy::CallByValue, ones).is_err());
+
+        let from = r"letrec from = \n -> Cons n (from (inc n)) in head (tail (tail (from 0)))";
+        assert_eq!(run(Strategy::CallByNeed, from)?, "2");
+
+        // unused arguments are never evaluated
+        let unused = r"letrec loop = loop in (\x y -> y) loop 1";
+        assert_eq!(run(Strategy::CallByNeed, unused)?, "1");
+        assert!(run(Strategy::CallByValue, unused).is_err());
+
+        // an infinite value can't be printed, but doesn't crash
+        let cycle = "letrec xs = Cons 1 xs in xs";
+        let err = run(Strategy::CallByNeed, cycle).unwrap_err();
+        assert!(err.to_string().contains("it may be infinite"), "{err}");
+        let from = r"letrec from = \n -> Cons n (from (inc n)) in from 0";
+        assert!(run(Strategy::CallByNeed, from).is_err());
+
+        // effects have no order under call-by-need
+        let effects = r"let x = ref 1 in let u = x := 2 in !x";
+        assert_eq!(run(Strategy::CallByValue, effects)?, "2");
+        let err = run(Strategy::CallByNeed, effects).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`!` needs call-by-value, its effects would happen out of order"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm() -> anyhow::Result<()> {
+        let sources = [
+            "add (inc 1) 1",
+            r"let twice = \f x -> f (f x) in twice dec 10",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"(\x y -> x) true ()",
+            "Cons (list 1) (Cons Nil Nil)",
+            r"let x = 1 in let f = \y -> add x y in let x = 10 in f x",
+            r"\x -> x",
+            "add 1",
+            r"let k = \x -> \y -> \z -> add x (add y z) in k 1 2 3",
+            r"letrec f = \x -> x in f (list 5)",
+            r"letrec loop = \x -> loop x in take 0 (list loop)",
+            r"let two = \f x -> f (f x) in two two two two inc 0",
+            r#"head (tail (Cons "a" (Cons "b" Nil)))"#,
+        ];
+        let env = crate::prelude::default_env();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            alg_w(crate::prelude::default_ctx(), expr.clone())?;
+            let expected = eval(&env, &expr)?;
+            let program = crate::vm::compile(&env, &expr)?;
+            let value = crate::vm::run(&program)?;
+            assert_eq!(value.to_string(), expected.to_string(), "{source}");
+        }
+
+        let expr = Parser::new(Lexer::new("letrec xs = Cons 1 xs in xs")).parse()?;
+        let program = crate::vm::compile(&env, &expr)?;
+        assert!(crate::vm::run(&program).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lambda_lift() -> anyhow::Result<()> {
+        let sources = [
+            r"\x -> x",
+            r"let twice = \f x -> f (f x) in twice dec 10",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"let x = 1 in let f = \y -> add x y in let x = 10 in f x",
+            r"\x -> \y -> add x y",
+            r"\x -> let f = \y -> add x y in let g = \z -> f (f z) in g",
+            r"(\x -> (\y -> x) 1) true",
+            r"letrec len = \xs -> len xs in len",
+            r"\n -> letrec count = \k -> take n (list k) in count 1",
+            r"let k = \x -> \y -> \z -> add x (add y z) in k 1 2 3",
+            r"let add = \x -> x in add 1",
+            r"\inc -> inc (\x -> x)",
+            r"letrec f = \x -> (\y -> f y) x in f",
+            r"letrec f = \x -> let g = \y -> f y in g x in f",
+        ];
+        let env = crate::prelude::default_env();
+        let globals: Vec<_> = crate::prelude::default_ctx()
+            .constrains
+            .into_keys()
+            .collect();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let lifted = crate::lift::lambda_lift(&expr);
+            let lifted_expr = lifted.to_expr();
+
+            let (_, ty) = alg_w(crate::prelude::default_ctx(), expr.clone())?;
+            let (_, lifted_ty) = alg_w(crate::prelude::default_ctx(), lifted_expr.clone())?;
+            let ctx = crate::prelude::default_ctx();
+            assert!(
+                ty.generalise(&ctx).alpha_eq(&lifted_ty.generalise(&ctx)),
+                "{source} => {lifted_expr}"
+            );
+
+            // every function is closed over the globals and the functions before it
+            let mut scope: Vec<&str> = globals.iter().map(String::as_str).collect();
+            for (i, function) in lifted.functions.iter().enumerate() {
+                let mut names = lifted.functions[..i].iter().map(|f| &f.name);
+                assert!(names.all(|name| *name != function.name));
+                scope.push(&function.name);
+                let free = function.expr.free_vars();
+                assert!(free.iter().all(|v| scope.contains(v)), "{}", function.expr);
+            }
+            assert!(!lifted.main.to_string().contains('\\'), "{}", lifted.main);
+
+            let expected = eval(&env, &expr)?.to_string();
+            assert_eq!(eval(&env, &lifted_expr)?.to_string(), expected, "{source}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_anf() -> anyhow::Result<()> {
+        use crate::anf;
+
+        let sources = [
+            "add (inc 1) 2",
+            r"let twice = \f x -> f (f x) in twice dec 10",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"(\x y -> x) true ()",
+            "Cons (list 1) (Cons Nil Nil)",
+            r"let x = 1 in let f = \y -> add x y in let x = 10 in f x",
+            r"\f g x -> f (g x)",
+            r"let p = (\x -> x) (\y -> y) in p (p 1)",
+            r"letrec f = \x -> x in f (list 5)",
+            r"\n -> letrec count = \k -> take n (list k) in count 1",
+            r"letrec loop = \x -> loop x in take 0 (list loop)",
+            r"let add = \x y -> x in add 1 2",
+            r#"head (tail (Cons "a" (Cons "b" Nil)))"#,
+        ];
+        let ctx = crate::prelude::default_ctx();
+        let env = crate::prelude::default_env();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let (_, expected) = alg_w(ctx.clone(), expr.clone())?;
+            let expected = expected.generalise(&ctx);
+            let value = eval(&env, &expr)?.to_string();
+
+            let term = anf::from_expr(&ctx, &expr)?;
+            let optimised = anf::optimise(term.clone());
+            for term in [term, optimised] {
+                let ty = anf::check(&ctx, &term).map_err(|e| e.context(term.to_string()))?;
+                assert!(ty.generalise(&ctx).alpha_eq(&expected), "{source}: {ty}");
+                assert_eq!(eval(&env, &term.to_expr())?.to_string(), value, "{term}");
+            }
+        }
+
+        let expr = Parser::new(Lexer::new(r"let id = \x -> x in id 1")).parse()?;
+        let term = anf::from_expr(&ctx, &expr)?;
+        let printed = term.to_string();
+        assert!(printed.starts_with("let id : ∀"), "{printed}");
+        assert!(printed.ends_with(" in\nid @Int 1"), "{printed}");
+
+        let expr = Parser::new(Lexer::new(r"add (inc 1) (let k = \x -> x in k 2)")).parse()?;
+        let term = anf::optimise(anf::from_expr(&ctx, &expr)?);
+        assert_eq!(term.to_string(), "4");
+
+        let expr = Parser::new(Lexer::new("inc 1")).parse()?;
+        let term = match anf::from_expr(&ctx, &expr)? {
+            anf::Term::Comp(anf::Comp::App(fun, _)) => {
+                let arg = anf::Atom::Lit(Literal::Bool(true));
+                anf::Term::Comp(anf::Comp::App(fun, arg))
+            }
+            term => panic!("unexpected translation: {term}"),
+        };
+        assert!(anf::check(&ctx, &term).is_err());
+
+        let expr = Parser::new(Lexer::new("letrec xs = Cons 1 xs in xs")).parse()?;
+        assert!(anf::from_expr(&ctx, &expr).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_codegen_c() -> anyhow::Result<()> {
+        use std::process::Command;
+
+        let sources = [
+            "add (inc 1) 2",
+            r"let twice = \f x -> f (f x) in twice dec 10",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"(\x y -> x) true ()",
+            "Cons (list 1) (Cons Nil Nil)",
+            r"let x = 1 in let f = \y -> add x y in let x = 10 in f x",
+            r"\f g x -> f (g x)",
+            r"let p = (\x -> x) (\y -> y) in p (p 1)",
+            r"\n -> letrec count = \k -> take n (list k) in count 1",
+            r"let two = \f x -> f (f x) in two two two two inc 0",
+            r"letrec loop = \x -> loop x in take 0 (list loop)",
+            r#"head (tail (Cons "a\n\"?" (Cons "b" Nil)))"#,
+            r"let k = \x -> \y -> \z -> add x (add y z) in k 1 2 3",
+            r"let f = \x -> add x in f 1",
+            r"let n = add (-9223372036854775807) (-1) in Cons (isNull Nil) (list (isNull (list n)))",
+            r"Cons 'λ' (Cons '\'' Nil)",
+            r"let x' = 1 in let x_ = x' in take 2 (Cons x_ (Cons x' Nil))",
+            "Cons 1e-7 (Cons 0.1 (Cons 1.5e16 (Cons 123456.789 (Cons 0.0001 Nil))))",
+            "Cons (-0.0) (Cons 1e100 (Cons 2.5e-300 (Cons 1e15 (Cons 0.3 Nil))))",
+        ];
+        let ctx = crate::prelude::default_ctx();
+        let env = crate::prelude::default_env();
+        let dir = std::env::temp_dir().join(format!("hm_codegen_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        for (i, source) in sources.iter().enumerate() {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let expected = eval(&env, &expr)?.to_string();
+            let program = crate::codegen::expr_to_c(&ctx, &expr)?;
+
+            let file = dir.join(format!("program_{i}.c"));
+            let exe = dir.join(format!("program_{i}"));
+            std::fs::write(&file, &program)?;
+            let status = Command::new("cc")
+                .args(["-std=c11", "-Wall", "-Werror", "-Wno-unused", "-o"])
+                .arg(&exe)
+                .arg(&file)
+                .status();
+            match status {
+                // no C compiler to run the output
+                Err(_) => continue,
+                Ok(status) => assert!(status.success(), "{source}:\n{program}"),
+            }
+            let output = Command::new(&exe).output()?;
+            assert!(output.status.success(), "{source}");
+            let output = String::from_utf8(output.stdout)?;
+            assert_eq!(output.trim_end(), expected, "{source}:\n{program}");
+        }
+        std::fs::remove_dir_all(&dir)?;
+
+        let program = crate::codegen::expr_to_c(&ctx, &Parser::new(Lexer::new("inc 41")).parse()?)?;
+        assert!(
+            program.contains("print_value(mk_int(INT64_C(42)))"),
+            "{program}"
+        );
+        let expr = Parser::new(Lexer::new("case list 1 of Cons x xs -> x; Nil -> 0")).parse()?;
+        let err = crate::codegen::expr_to_c(&ctx, &expr).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`case` can't be compiled to C yet, only the interpreters run it"
+        );
+        let expr = Parser::new(Lexer::new(r"\x -> add (inc x) x")).parse()?;
+        let program = crate::codegen::expr_to_c(&ctx, &expr)?;
+        assert!(program.contains("int64_t v_x = arg->i;"), "{program}");
+        assert!(program.contains("wrapping_add(v_x, 1)"), "{program}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_monomorphise() -> anyhow::Result<()> {
+        use crate::anf;
+        use crate::mono::monomorphise;
+
+        let sources = [
+            r"let id = \x -> x in let a = id 1 in id true",
+            r"let twice = \f x -> f (f x) in twice dec 10",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"let p = (\x -> x) (\y -> y) in p (p 1)",
+            r"letrec f = \x -> f x in let g = \y -> f y in let h = \u -> g 1 in isNull (list h)",
+            r"let k = \x y -> x in let f = \z -> k z in Cons (f 1 true) (list (f 2 ()))",
+            r"let unused = \x -> x in 1",
+            r"let id = \x -> x in id",
+        ];
+        let ctx = crate::prelude::default_ctx();
+        let env = crate::prelude::default_env();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let term = anf::from_expr(&ctx, &expr)?;
+            let expected = anf::check(&ctx, &term)?.generalise(&ctx);
+
+            let result = monomorphise(&term);
+            let ty =
+                anf::check(&ctx, &result.term).map_err(|e| e.context(result.term.to_string()))?;
+            assert!(ty.generalise(&ctx).alpha_eq(&expected), "{source}: {ty}");
+            let value = eval(&env, &expr)?.to_string();
+            assert_eq!(
+                eval(&env, &result.term.to_expr())?.to_string(),
+                value,
+                "{source}"
+            );
+        }
+
+        let monomorphised = |source| -> anyhow::Result<_> {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            Ok(monomorphise(&anf::from_expr(&ctx, &expr)?))
+        };
+        let result = monomorphised(r"let id = \x -> x in let a = id 1 in id true")?;
+        let printed = result.term.to_string();
+        assert!(printed.contains("let id_Int : Int -> Int ="), "{printed}");
+        assert!(
+            printed.contains("let id_Bool : Bool -> Bool ="),
+            "{printed}"
+        );
+        assert!(!printed.contains('∀'), "{printed}");
+        assert!(result.polymorphic.is_empty());
+
+        let result = monomorphised(r"letrec len = \xs -> len (tail xs) in len (list 1)")?;
+        let printed = result.term.to_string();
+        assert!(printed.starts_with("letrec len_Int_a :"), "{printed}");
+        // the result type of `len` is never fixed
+        assert_eq!(result.polymorphic, ["len_Int_a"]);
+        let source =
+            r"letrec f = \x -> f x in let g = \y -> f y in let h = \u -> g 1 in isNull (list h)";
+        let result = monomorphised(source)?;
+        assert_eq!(
+            result.polymorphic,
+            ["tmp_1_a", "h_a_b", "g_Int_a", "f_Int_a"]
+        );
+        assert!(result.term.to_string().starts_with("letrec f_Int_a :"));
+
+        let result = monomorphised(r"let unused = \x -> x in 1")?;
+        assert_eq!(result.polymorphic, ["unused"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_expr() -> anyhow::Result<()> {
+        use crate::alg_w::infer;
+        use crate::typed::*;
+
+        let sources = [
+            r"\f x -> f (f x)",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"let p = (\x -> x) (\y -> y) in p (p 1)",
+            r"letrec len = \xs -> len (tail xs) in len (list 1)",
+            r"\x -> let y = x in let k = \z -> y in k 1",
+            r#"Cons (head (list "a")) Nil"#,
+        ];
+        let ctx = crate::prelude::default_ctx();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            let (_, expected) = alg_w(ctx.clone(), expr.clone())?;
+            let typed = infer(ctx.clone(), expr.clone())?;
+            assert_eq!(typed.erase(), expr);
+            assert!(typed
+                .ty
+                .generalise(&ctx)
+                .alpha_eq(&expected.generalise(&ctx)));
+
+            // every node agrees with its children
+            for node in typed.nodes() {
+                let consistent = match &node.node {
+                    TypedNode::Lit(lit) => lit.ty() == node.ty,
+                    TypedNode::Var(var) | TypedNode::Con(var) => {
+                        var.scheme.instantiate_with(&var.inst) == node.ty
+                    }
+                    TypedNode::App(app) => {
+                        app.fun.ty == MonoType::arrow(app.arg.ty.clone(), node.ty.clone())
+                    }
+                    TypedNode::Abs(abs) => {
+                        node.ty == MonoType::arrow(abs.arg_ty.clone(), abs.body.ty.clone())
+                    }
+                    TypedNode::Let(elet) | TypedNode::LetRec(elet) => {
+                        *elet.scheme.split().1 == elet.vexpr.ty && elet.aexpr.ty == node.ty
+                    }
+                    TypedNode::Case(case) => case.arms.iter().all(|arm| arm.body.ty == node.ty),
+                };
+                assert!(consistent, "{source}: {node}");
+            }
+        }
+
+        let expr = Parser::new(Lexer::new(r"let id = \x -> x in id 1")).parse()?;
+        let typed = infer(ctx.clone(), expr)?;
+        let TypedNode::Let(elet) = &typed.node else {
+            panic!("expected a let: {typed}");
+        };
+        assert!(elet.scheme.alpha_eq(&"forall a. a -> a".parse()?));
+        let TypedNode::App(app) = &elet.aexpr.node else {
+            panic!("expected an application: {typed}");
+        };
+        let TypedNode::Var(id) = &app.fun.node else {
+            panic!("expected a variable: {typed}");
+        };
+        assert_eq!(id.inst, ["Int".parse()?]);
+        assert_eq!(app.fun.ty.to_string(), "Int -> Int");
+        assert_eq!(typed.to_string(), r"let id = \x -> x in id 1 : Int");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_f() -> anyhow::Result<()> {
+        use crate::alg_w::infer;
+        use crate::system_f::*;
+
+        let sources = [
+            r"\f x -> f (f x)",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"let p = (\x -> x) (\y -> y) in p (p 1)",
+            r"letrec len = \xs -> len (tail xs) in len (list 1)",
+            r"letrec f = \x -> let g = \y -> f y in g x in f",
+            r"\x -> let y = x in let k = \z -> y in k 1",
+            r"let pair = \x y -> list x in let k = \a b -> a in k (pair 1 true) (pair Nil 2)",
+            r#"Cons (head (list "a")) Nil"#,
+        ];
+        let ctx = crate::prelude::default_ctx();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            verify_inference(&ctx, &expr)?;
+        }
+
+        let expr = Parser::new(Lexer::new(r"let id = \x -> x in id 1")).parse()?;
+        let term = elaborate(&infer(ctx.clone(), expr)?);
+        let FTerm::Let(_, ty, _, _) = &term else {
+            panic!("expected a let: {term}");
+        };
+        assert!(ty.alpha_eq(&(&"forall a. a -> a".parse::<PolyType>()?).into()));
+        let id = term.to_string();
+        assert!(id.starts_with("let id : ∀"), "{id}");
+        assert!(id.ends_with("in id [Int] 1"), "{id}");
+
+        // type abstractions must not capture variables of the context
+        let a = FType::Var("a".to_string());
+        let escaping = FTerm::Abs(
+            "x".to_string(),
+            a.clone(),
+            Box::new(FTerm::TyAbs(
+                "a".to_string(),
+                Box::new(FTerm::Var("x".to_string())),
+            )),
+        );
+        assert!(type_check(&ctx, &escaping).is_err());
+        let poly_id = FTerm::TyAbs(
+            "a".to_string(),
+            Box::new(FTerm::Abs(
+                "x".to_string(),
+                a.clone(),
+                Box::new(FTerm::Var("x".to_string())),
+            )),
+        );
+        let int = FType::Con("Int".to_string(), vec![]);
+        let applied = FTerm::TyApp(Box::new(poly_id.clone()), int.clone());
+        assert_eq!(
+            type_check(&ctx, &applied)?,
+            FType::arrow(int.clone(), int.clone())
+        );
+        let wrong = FTerm::App(Box::new(applied), Box::new(FTerm::Lit(Literal::Bool(true))));
+        assert!(type_check(&ctx, &wrong).is_err());
+        assert!(type_check(
+            &ctx,
+            &FTerm::TyApp(Box::new(FTerm::Lit(Literal::Int(1))), int)
+        )
+        .is_err());
+
+        // instantiating under a quantifier renames it instead of capturing
+        let konst: FType = (&"forall a b. a -> b -> a".parse::<PolyType>()?).into();
+        let FType::Forall(var, body) = konst else {
+            panic!("expected a quantifier");
+        };
+        let b = FType::Var("b".to_string());
+        let inst = body.subst(&var, &b);
+        assert!(inst.alpha_eq(&FType::Forall(
+            "c".to_string(),
+            Box::new(FType::arrow(
+                b.clone(),
+                FType::arrow(FType::Var("c".to_string()), b)
+            ))
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_classes() -> anyhow::Result<()> {
+        use crate::classes::translate_program;
+
+        let run = |source: &str| -> anyhow::Result<(String, String)> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            let translated = translate_program(&crate::prelude::default_ctx(), &program)?;
+            let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+            Ok((translated.ty.to_string(), value.to_string()))
+        };
+
+        let classes = r"
+            class Num a => Inc a where incr : a -> a; zero : a in
+            instance Inc Int where incr = \x -> x + 1; zero = 0 in
+            instance Num a => Num (List a) where (+) = \xs ys -> list (head xs + head ys) in
+            ";
+        let cases = [
+            ("1 + 2 + 3", "Int", "6"),
+            (r"let double = \x -> x + x in double 4", "Int", "8"),
+            (r"\x -> x + x", "∀a. Num a => a -> a", "<closure>"),
+            (
+                r"let succ = \x -> incr x + zero in succ",
+                "∀a. Inc a => a -> a",
+                "<closure>",
+            ),
+            (r"let succ = \x -> incr x + zero in succ 41", "Int", "42"),
+            ("head (list 1 + list 2)", "Int", "3"),
+            (r"letrec sum = \n -> sum (n + 1) in 1", "Int", "1"),
+            (
+                r"let f = \x -> (list x + list x) in f",
+                "∀a. Num a => a -> List a",
+                "<closure>",
+            ),
+            (
+                r"let f = \x -> (list x + list x) in head (f (incr 0))",
+                "Int",
+                "2",
+            ),
+            ("zero", "∀a. Inc a => a", "<closure>"),
+        ];
+        for (source, ty, value) in cases {
+            let (actual_ty, actual_value) = run(&format!("{classes}{source}"))?;
+            assert_eq!(
+                (actual_ty.as_str(), actual_value.as_str()),
+                (ty, value),
+                "{source}"
+            );
+        }
+
+        let errors = [
+            "true + false",
+            "isNull (list zero)",
+            "class Num a where m : a in 1",
+            "class C a where m : Int in 1",
+            "instance Show Int where in 1",
+            "instance Num Int where (+) = add in 1",
+            "instance Inc Bool where incr = \\x -> x; zero = true in 1",
+            "class C a where m : a -> a in instance C Int where m = \\x -> true in 1",
+            "class C a where m : a -> a in instance C (List a) where m = \\x -> x + x in 1",
+            "class C a where m : a -> a in instance C Int where in 1",
+        ];
+        for source in errors {
+            assert!(run(&format!("{classes}{source}")).is_err(), "{source}");
+        }
+
+        let program: Program = r"class Eq a where (==) : a -> a -> Bool in
+            instance Eq a => Eq (List a) where (==) = \x y -> head x == head y in
+            let f = (+) 1 in \x -> f x + 1 * 2"
+            .parse()?;
+        let printed = program.to_string();
+        assert_eq!(
+            printed,
+            r"class Eq a where (==) : a -> a -> Bool in instance Eq a => Eq (List a) where (==) = \x y -> head x == head y in let f = (+) 1 in \x -> f x + 1 * 2"
+        );
+        assert_eq!(printed.parse::<Program>()?, program);
+        let expr = Parser::new(Lexer::new("(1 + 2) * 3 - (4 - 5)")).parse()?;
+        assert_eq!(expr.to_string(), "(1 + 2) * 3 - (4 - 5)");
+        // `-` only negates at the start of an operand
+        for (source, printed) in [
+            ("x -1", "x - 1"),
+            ("x - 1", "x - 1"),
+            ("x-1", "x - 1"),
+            ("x - -1", "x - -1"),
+            ("-1.5 * x", "-1.5 * x"),
+            ("f (-1) x", "f (-1) x"),
+            ("(-)", "(-)"),
+        ] {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            assert_eq!(expr.to_string(), printed, "{source}");
+            assert_eq!(Parser::new(Lexer::new(printed)).parse()?, expr, "{source}");
+        }
+        assert!(Parser::new(Lexer::new("-x")).parse().is_err());
+        assert!("forall a. (Num a, Eq a) => a -> a"
+            .parse::<PolyType>()?
+            .alpha_eq(&"forall b. (Num b, Eq b) => b -> b".parse()?));
+        assert_eq!(
+            "forall a. Num (List a) => a"
+                .parse::<PolyType>()?
+                .to_string(),
+            "∀a. Num (List a) => a"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_defaulting() -> anyhow::Result<()> {
+        use crate::classes::{translate_program, Translated};
+
+        let translate = |source: &str| -> anyhow::Result<Translated> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            translate_program(&crate::prelude::default_ctx(), &program)
+        };
+
+        let classes = r#"
+            class Show a where show : a -> String in
+            instance Show Int where show = \x -> "int" in
+            instance Show Bool where show = \x -> "bool" in
+            class Read a where read : String -> a in
+            instance Read Int where read = \s -> 0 in
+            class Zero a where zero : a in
+            instance Zero Bool where zero = false in
+            "#;
+        let source = format!("{classes}\\s -> show (read s)");
+        let err = translate(&source).unwrap_err().to_string();
+        assert!(err.starts_with("Ambiguous type variable"), "{err}");
+
+        // `x` is generalised, it isn't ambiguous
+        let translated = translate(&format!("{classes}let x = zero + zero in 1 + 1"))?;
+        assert!(translated.warnings.is_empty());
+        let translated = translate(&format!(
+            "{classes}default Zero Bool in let x = zero in isNull (list x)"
+        ))?;
+        assert_eq!(translated.ty.to_string(), "Bool");
+        assert_eq!(translated.warnings.len(), 1);
+        assert!(
+            translated.warnings[0].contains("to `Bool` in `Zero"),
+            "{:?}",
+            translated.warnings
+        );
+
+        let translated = translate(&format!("{classes}show (head (list (1 + 1)))"))?;
+        assert!(translated.warnings.is_empty());
+        let translated = translate(&format!("{classes}\\s -> isNull (list (read s + read s))"))?;
+        assert_eq!(translated.ty.to_string(), "String -> Bool");
+        assert_eq!(translated.warnings.len(), 1);
+        assert!(
+            translated.warnings[0].contains("to `Int` in `"),
+            "{:?}",
+            translated.warnings
+        );
+        let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+        assert_eq!(value.to_string(), "<closure>");
+
+        // a default is only chosen if all the predicates of the variable hold for it
+        let source = format!("{classes}\\s -> isNull (list (show (read s + zero)))");
+        let err = translate(&source).unwrap_err().to_string();
+        assert!(err.starts_with("Ambiguous type variable"), "{err}");
+        assert!(translate(&format!("{classes}default Zero Int in 1")).is_err());
+        assert!(translate(&format!("{classes}default Eq Int in 1")).is_err());
+
+        let scheme: PolyType = "forall a b. (Show a, Read b) => a -> String".parse()?;
+        assert_eq!(scheme.ambiguous_vars(), ["b"]);
+        let program: Program = "default Num Int in 1".parse()?;
+        assert_eq!(program.to_string(), "default Num Int in 1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_restriction() -> anyhow::Result<()> {
+        use crate::anf;
+
+        let ctx = crate::prelude::default_ctx();
+        let parse = |source: &str| Parser::new(Lexer::new(source)).parse();
+
+        // `r` would be `∀a. Ref (List a)`, storing a list of one type and reading another
+        let unsound = [
+            r"let r = ref Nil in let u = r := list true in add 1 (head !r)",
+            r"let r = ref (\x -> x) in let u = r := inc in !r true",
+            r"let p = (\x -> x) (\y -> y) in p p 1",
+        ];
+        for source in unsound {
+            let expr = parse(source)?;
+            assert!(alg_w(ctx.clone(), expr.clone()).is_err(), "{source}");
+            assert!(anf::from_expr(&ctx, &expr).is_err(), "{source}");
+        }
+
+        let cases = [
+            (
+                r"let r = ref Nil in let u = r := list true in !r",
+                "List Bool",
+            ),
+            (r"let id = \x -> x in let a = id 1 in id true", "Bool"),
+            (
+                r"let n = Cons Nil Nil in Cons (Cons 1 Nil) n",
+                "List (List Int)",
+            ),
+            // `a` is only covariant in `List a`, so it is generalised all the same
+            (
+                r"let n = head (list Nil) in let a = Cons 1 n in Cons true n",
+                "List Bool",
+            ),
+            (r"let f = (\x -> x) (\y -> y) in f 1", "Int"),
+            (r"\x -> let r = ref x in !r", "forall a. a -> a"),
+        ];
+        for (source, expected) in cases {
+            let expr = parse(source)?;
+            let (_, ty) = alg_w(ctx.clone(), expr.clone())?;
+            let ty = ty.generalise(&ctx);
+            assert!(ty.alpha_eq(&expected.parse()?), "{source}: {ty}");
+            let term = anf::from_expr(&ctx, &expr)?;
+            anf::check(&ctx, &term)?;
+        }
+
+        assert!(parse(r"\x -> ref x")?.is_value());
+        assert!(parse(r"Cons 1 (Cons (\x -> x) Nil)")?.is_value());
+        assert!(!parse("Cons (ref 1) Nil")?.is_value());
+        assert!(!parse(r"let x = ref 1 in \y -> y")?.is_value());
+
+        // so is the type of a whole program, and the variables left are defaulted
+        let program_ty = |source: &str| -> anyhow::Result<PolyType> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            Ok(crate::classes::translate_program(&ctx, &program)?.ty)
+        };
+        for (source, quantified) in [
+            (r"let r = ref (\x -> x) in r", 0),
+            ("ref Nil", 0),
+            ("head (list Nil)", 1),
+            (r"\x -> ref x", 1),
+        ] {
+            let ty = program_ty(source)?;
+            assert_eq!(ty.split().0.len(), quantified, "{source}: {ty}");
+        }
+        let ty = program_ty(r"ref (\x -> x + x)")?;
+        assert!(ty.alpha_eq(&"Ref (Int -> Int)".parse()?), "{ty}");
+
+        let env = crate::prelude::default_env();
+        let source = r"let r = ref 1 in let u = r := add !r 41 in let v = (:=) r (inc !r) in !r";
+        assert_eq!(eval(&env, &parse(source)?)?.to_string(), "43");
+        let source = r"let r = ref (ref 1) in let u = !r := 2 in !(!r)";
+        assert_eq!(eval(&env, &parse(source)?)?.to_string(), "2");
+
+        for source in ["r := !r + 1", r"f !r (!) (:=)", "!(!r) x", "!(f x)"] {
+            assert_eq!(parse(source)?.to_string(), source);
+        }
+        assert_eq!(parse("f (!r)")?.to_string(), "f !r");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bidirectional() -> anyhow::Result<()> {
+        use crate::bidir::{self, type_env, TypeEnv};
+        use crate::system_f::FType;
+
+        let parse = |source: &str| Parser::new(Lexer::new(source)).parse();
+        let ctx = crate::prelude::default_ctx();
+        let env = type_env(&ctx);
+
+        // rank 1 programs need no annotation and get the types Algorithm W gives them
+        let sources = [
+            r"\f x -> f (f x)",
+            r"let id = \x -> x in id id (add 1) 2",
+            r"letrec len = \xs -> len (tail xs) in len (list 1)",
+            r"letrec f = \x -> let g = \y -> f y in g x in f",
+            r"\x -> let y = x in let k = \z -> y in k 1",
+            r"let n = head (list Nil) in let a = Cons 1 n in Cons true n",
+            r"\x -> let r = ref x in !r",
+            r"(\x -> x : a -> a)",
+        ];
+        for source in sources {
+            let expr = parse(source)?;
+            let (_, ty) = alg_w(ctx.clone(), expr.clone())?;
+            let expected = FType::from(&ty.generalise(&ctx));
+            let ty = bidir::infer(&env, &expr)?;
+            assert!(
+                ty.alpha_eq(&expected),
+                "{source}: {ty}, expected {expected}"
+            );
+        }
+        let unsound = r"let r = ref Nil in let u = r := list true in add 1 (head !r)";
+        assert!(bidir::infer(&env, &parse(unsound)?).is_err());
+
+        let mut env: TypeEnv = env;
+        let st = [
+            ("runST", "forall a. (forall s. ST s a) -> a"),
+            ("ret", "forall s a. a -> ST s a"),
+            ("bind", "forall s a b. ST s a -> (a -> ST s b) -> ST s b"),
+            ("newRef", "forall s a. a -> ST s (STRef s a)"),
+            ("readRef", "forall s a. STRef s a -> ST s a"),
+        ];
+        for (name, ty) in st {
+            env.insert(name.to_string(), ty.parse()?);
+        }
+        let cases = [
+            ("runST (bind (newRef 1) readRef)", "Int"),
+            (r"\x -> runST (ret x)", "forall a. a -> a"),
+            (
+                r"(\f -> let a = f 1 in f true : (forall a. a -> a) -> Bool)",
+                "(forall a. a -> a) -> Bool",
+            ),
+            (
+                r"let g = (\f -> let a = f 1 in f true : (forall a. a -> a) -> Bool) in g (\x -> x)",
+                "Bool",
+            ),
+            (
+                r"let k = (\f -> f : (forall a. a -> a) -> Int -> Int) in k (\x -> x) 1",
+                "Int",
+            ),
+            (
+                r"(\g -> g (\x -> x) : ((forall a. a -> a) -> Int) -> Int) (\f -> f 1)",
+                "Int",
+            ),
+        ];
+        for (source, expected) in cases {
+            let ty = bidir::infer(&env, &parse(source)?)?;
+            assert!(ty.alpha_eq(&expected.parse()?), "{source}: {ty}");
+        }
+
+        let errors = [
+            // the reference would escape the computation it belongs to
+            "runST (newRef 1)",
+            // a lambda argument is only polymorphic if it is annotated to be
+            r"\f -> let a = f 1 in f true",
+            r"let g = (\f -> let a = f 1 in f true : (forall a. a -> a) -> Bool) in g inc",
+            r"(\x -> inc x : a -> a)",
+        ];
+        for source in errors {
+            assert!(bidir::infer(&env, &parse(source)?).is_err(), "{source}");
+        }
+        bidir::check(&env, &parse(r"\x -> x")?, &"forall a. a -> a".parse()?)?;
+        bidir::check(
+            &env,
+            &parse(r"\f -> f")?,
+            &"(forall a. a -> a) -> b -> b".parse()?,
+        )?;
+        assert!(bidir::check(&env, &parse(r"\x -> inc x")?, &"a -> a".parse()?).is_err());
+
+        // Algorithm W checks rank 1 annotations only
+        assert!(alg_w(ctx.clone(), parse(r"(\x -> inc x : a -> a)")?).is_err());
+        assert!(alg_w(ctx.clone(), parse(r"\y -> (\x -> y : a -> a)")?).is_err());
+        let (_, ty) = alg_w(ctx.clone(), parse(r"(\x -> x : Int -> Int)")?)?;
+        assert_eq!(ty.to_string(), "Int -> Int");
+        let err = alg_w(
+            ctx.clone(),
+            parse(r"(\f -> f : (forall a. a -> a) -> Int)")?,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("higher rank"), "{err}");
+
+        let source = r"(\f -> f : (∀a. a -> a) -> Int -> Int) (\x -> x : a -> a)";
+        assert_eq!(parse(source)?.to_string(), source);
+        let value = eval(&crate::prelude::default_env(), &parse(source)?)?;
+        assert_eq!(value.to_string(), "<closure>");
+        assert_eq!(
+            "forall a. (forall s. ST s a) -> List (forall b. b)"
+                .parse::<FType>()?
+                .to_string(),
+            "∀a. (∀s. ST s a) -> List (∀b. b)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kinds() -> anyhow::Result<()> {
+        use crate::classes::translate_program;
+        use crate::kinds::Kind;
+        use crate::prelude::default_kinds;
+
+        for kind in ["*", "* -> *", "(* -> *) -> * -> *", "* -> (* -> *) -> *"] {
+            assert_eq!(kind.parse::<Kind>()?.to_string(), kind);
+        }
+
+        let cases = [
+            ("data Maybe a = Nothing | Just a in 1", "* -> *"),
+            (
+                "data Tree a = Leaf | Node (Tree a) a (Tree a) in 1",
+                "* -> *",
+            ),
+            ("data Pair a b = Pair a (List b) in 1", "* -> * -> *"),
+            ("data Phantom a = Phantom in 1", "* -> *"),
+            ("data Void in 1", "*"),
+            ("data Cell a = Cell (Ref a) (a -> Unit) in 1", "* -> *"),
+        ];
+        for (source, expected) in cases {
+            let program: Program = source.parse()?;
+            let Decl::Data(data) = &program.decls[0] else {
+                unreachable!()
+            };
+            let kind = default_kinds().declare_data(data)?;
+            assert_eq!(kind.to_string(), expected, "{source}");
+            assert_eq!(program.to_string(), source);
+        }
+
+        let ctx = crate::prelude::default_ctx();
+        let translate = |source: &str| -> anyhow::Result<_> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            translate_program(&ctx, &program)
+        };
+        let translated = translate("data Maybe a = Nothing | Just a in Just (1 + 1)")?;
+        assert_eq!(translated.ty.to_string(), "Maybe Int");
+        let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+        assert_eq!(value.to_string(), "Just 2");
+        let translated = translate("data T = A | B T in B (B A)")?;
+        assert_eq!(translated.ty.to_string(), "T");
+
+        let errors = [
+            ("data Bad a = Bad (List a a) in 1", "ill-kinded"),
+            ("data Bad = Bad (Int Bool) in 1", "ill-kinded"),
+            ("data Bad = Bad a in 1", "not a parameter"),
+            ("data Bad a = Bad (a -> List) in 1", "ill-kinded"),
+            ("data List a = Nil in 1", "already defined"),
+            ("data Bad = Bad Maybe in 1", "Undefined type constructor"),
+            ("(Nil : List Int Bool)", "ill-kinded"),
+            ("(Nil : List)", "not the type of a value"),
+            (
+                "class C a where m : List a -> a in instance C List where m = head in 1",
+                "ill-kinded",
+            ),
+            ("class C a where m : a in default C List in 1", "ill-kinded"),
+        ];
+        for (source, expected) in errors {
+            let err = format!("{:#}", translate(source).unwrap_err());
+            assert!(err.contains(expected), "{source}: {err}");
+        }
+
+        // types in the context are checked before inference gets to see them
+        for ty in ["List Int Bool", "forall a. Int a", "Maybe Int"] {
+            let ctx = ctx.extend_with("x".to_string(), ty.parse()?);
+            let program: Program = "x".parse()?;
+            let err = format!("{:#}", translate_program(&ctx, &program).unwrap_err());
+            assert!(err.contains("in the type of `x`"), "{ty}: {err}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_higher_kinds() -> anyhow::Result<()> {
+        use crate::classes::translate_program;
+        use crate::prelude::default_kinds;
+        use crate::system_f::FType;
+        use crate::utils::{unify, Subst};
+
+        for source in ["∀a b c. (a -> b) -> c a -> c b", "f (g a) -> m (List a) b"] {
+            let poly: PolyType = source.parse()?;
+            assert_eq!(poly.to_string(), source);
+        }
+        for source in ["∀f. f Int -> f (List Int)", "(∀a. f a) -> (->) Int"] {
+            assert_eq!(source.parse::<FType>()?.to_string(), source);
+        }
+
+        let cases = [
+            ("f a", "List Int", "f a -> f b", "List Int -> List b"),
+            (
+                "f a",
+                "Pair Int Bool",
+                "f a -> f b",
+                "Pair Int Bool -> Pair Int b",
+            ),
+            ("f a", "g b c", "f a -> f d", "g b c -> g b d"),
+            ("f x", "a -> b", "f c", "a -> c"),
+            ("f a", "Int -> b", "f", "(->) Int"),
+        ];
+        for (ty1, ty2, ty, expected) in cases {
+            let s = unify(ty1.parse()?, ty2.parse()?)?;
+            let ty: MonoType = ty.parse()?;
+            assert_eq!(ty.apply(&s).to_string(), expected, "{ty1} ~ {ty2}");
+        }
+        for (ty1, ty2) in [("f a", "Int"), ("f a b", "List Int"), ("f a", "f (f a)")] {
+            assert!(unify(ty1.parse()?, ty2.parse()?).is_err(), "{ty1} ~ {ty2}");
+        }
+
+        let cases = [
+            ("data Fix f = In (f (Fix f)) in 1", "(* -> *) -> *"),
+            (
+                "data Compose f g a = Compose (f (g a)) in 1",
+                "(* -> *) -> (* -> *) -> * -> *",
+            ),
+        ];
+        for (source, expected) in cases {
+            let program: Program = source.parse()?;
+            let Decl::Data(data) = &program.decls[0] else {
+                unreachable!()
+            };
+            assert_eq!(default_kinds().declare_data(data)?.to_string(), expected);
+            assert_eq!(program.to_string(), source);
+        }
+
+        let functor = "class Functor f where fmap : (a -> b) -> f a -> f b in
+            instance Functor List where fmap = \\g xs -> list (g (head xs)) in ";
+        let program: Program = format!("{functor}1").parse()?;
+        let Decl::Class(class) = &program.decls[0] else {
+            unreachable!()
+        };
+        assert_eq!(default_kinds().declare_class(class)?.to_string(), "* -> *");
+
+        let ctx = crate::prelude::default_ctx();
+        let translate = |source: &str| -> anyhow::Result<_> {
+            let program = crate::prelude::with_prelude(format!("{functor}{source}").parse()?);
+            translate_program(&ctx, &program)
+        };
+        let translated = translate("fmap inc (list 1)")?;
+        assert_eq!(translated.ty.to_string(), "List Int");
+        let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+        assert_eq!(value.to_string(), "Cons 2 Nil");
+        let translated = translate("let twice = \\g x -> fmap g (fmap g x) in twice")?;
+        assert_eq!(
+            translated.ty.to_string(),
+            "∀a b. Functor b => (a -> a) -> b a -> b a"
+        );
+        let translated = translate("let twice = \\g x -> fmap g (fmap g x) in twice inc (list 1)")?;
+        let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+        assert_eq!(value.to_string(), "Cons 3 Nil");
+        let translated = translate(
+            "instance Functor ((->) r) where fmap = \\g h x -> g (h x) in fmap inc inc 1",
+        )?;
+        let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+        assert_eq!(value.to_string(), "3");
+
+        let errors = [
+            ("fmap inc 1", "different argument lengths"),
+            (
+                "instance Functor Int where fmap = \\g x -> x in 1",
+                "ill-kinded",
+            ),
+            ("fmap inc (ref 1)", "No instance for `Functor Ref`"),
+        ];
+        for (source, expected) in errors {
+            let err = format!("{:#}", translate(source).unwrap_err());
+            assert!(err.contains(expected), "{source}: {err}");
+        }
+
+        // higher kinds are left to Algorithm W
+        let env = crate::bidir::type_env(&ctx.extend_with(
+            "fmap".to_string(),
+            "∀f a b. (a -> b) -> f a -> f b".parse()?,
+        ));
+        let expr = Parser::new(Lexer::new("fmap inc")).parse()?;
+        let err = crate::bidir::infer(&env, &expr).unwrap_err();
+        assert!(err.to_string().contains("only Algorithm W"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_synonyms() -> anyhow::Result<()> {
+        use crate::classes::translate_program;
+        use crate::prelude::default_kinds;
+        use crate::utils::{unify_with, Subst, Synonyms};
+
+        let cases = [
+            ("type Pred a = a -> Bool in 1", "* -> *"),
+            ("type Parser = List in 1", "* -> *"),
+            ("type Name = String in 1", "*"),
+        ];
+        for (source, expected) in cases {
+            let program: Program = source.parse()?;
+            let Decl::Type(synonym) = &program.decls[0] else {
+                unreachable!()
+            };
+            let kind = default_kinds().declare_synonym(synonym)?;
+            assert_eq!(kind.to_string(), expected, "{source}");
+            assert_eq!(program.to_string(), source);
+        }
+
+        // synonyms are only expanded where the types differ
+        let mut synonyms = Synonyms::default();
+        for source in ["type Pred a = a -> Bool in 1", "type Const a = Int in 1"] {
+            let program: Program = source.parse()?;
+            let Decl::Type(synonym) = &program.decls[0] else {
+                unreachable!()
+            };
+            synonyms.declare(synonym.clone());
+        }
+        let cases = [
+            ("a", "Pred Int", "a", "Pred Int"),
+            ("Pred a", "Int -> b", "a -> b", "Int -> Bool"),
+            ("Const Bool", "Const Char", "Const Bool", "Const Bool"),
+            ("a", "List (Const a)", "a", "List Int"),
+        ];
+        for (ty1, ty2, ty, expected) in cases {
+            let s = unify_with(&synonyms, ty1.parse()?, ty2.parse()?)?;
+            let ty: MonoType = ty.parse()?;
+            assert_eq!(ty.apply(&s).to_string(), expected, "{ty1} ~ {ty2}");
+        }
+        let err = unify_with(&synonyms, "Pred Int".parse()?, "Int".parse()?).unwrap_err();
+        assert_eq!(err.to_string(), "Could not unify types: Pred Int and Int");
+
+        let ctx = crate::prelude::default_ctx();
+        let translate = |source: &str| -> anyhow::Result<_> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            translate_program(&ctx, &program)
+        };
+        let cases = [
+            (
+                "type Pred a = a -> Bool in (\\x -> isNull x : Pred Int)",
+                "Pred Int",
+                None,
+            ),
+            (
+                "type Pred a = a -> Bool in let p = (\\x -> true : Pred Int) in p 1",
+                "Bool",
+                Some("true"),
+            ),
+            (
+                "type Count = Int in class C a where m : a -> a in
+                 instance C Int where m = inc in m (1 : Count)",
+                "Count",
+                Some("2"),
+            ),
+            (
+                "type Endo a = a -> a in type Twice a = Endo (Endo a) in
+                 ((\\f x -> f (f x)) : Twice Int) inc 1",
+                "Int",
+                Some("3"),
+            ),
+        ];
+        for (source, ty, value) in cases {
+            let translated = translate(source)?;
+            assert_eq!(translated.ty.to_string(), ty, "{source}");
+            if let Some(expected) = value {
+                let value = eval(&crate::prelude::default_env(), &translated.expr)?;
+                assert_eq!(value.to_string(), expected, "{source}");
+            }
+        }
+
+        let errors = [
+            (
+                "type Pred a = a -> Bool in (inc : Pred Int)",
+                "Could not unify types: Int -> Int and Pred Int",
+            ),
+            ("type T = List T in 1", "Type synonym `T` is cyclic: T -> T"),
+            (
+                "type A = List B in type B = Int -> A in 1",
+                "Type synonym `A` is cyclic: A -> B -> A",
+            ),
+            (
+                "type Pred a = a -> Bool in (\\x -> true : Pred)",
+                "Type synonym `Pred` is partially applied",
+            ),
+            (
+                "type Pred a = a -> Bool in class F f where m : f Int in
+                 instance F Pred where m = \\x -> true in 1",
+                "Type synonym `Pred` is partially applied",
+            ),
+            (
+                "type Count = Int in class C a where m : a in instance C Count where m = 1 in 1",
+                "is for the type synonym `Count`",
+            ),
+            ("type P = a -> Bool in 1", "not a parameter"),
+            ("type Int = Bool in 1", "already defined"),
+        ];
+        for (source, expected) in errors {
+            let err = format!("{:#}", translate(source).unwrap_err());
+            assert!(err.contains(expected), "{source}: {err}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newtypes() -> anyhow::Result<()> {
+        use crate::classes::translate_program;
+
+        for source in [
+            "newtype UserId = UserId Int in 1",
+            "newtype Wrap f a = Wrap (f a) in 1",
+        ] {
+            let program: Program = source.parse()?;
+            assert_eq!(program.to_string(), source);
+        }
+
+        let ctx = crate::prelude::default_ctx();
+        let translate = |source: &str| -> anyhow::Result<_> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            translate_program(&ctx, &program)
+        };
+        let cases = [
+            ("newtype UserId = UserId Int in UserId 1", "UserId", "1"),
+            (
+                "newtype Wrap f a = Wrap (f a) in Wrap (list 1)",
+                "Wrap List Int",
+                "Cons 1 Nil",
+            ),
+            (
+                "newtype Age = Age Int in class Old a where old : a -> Bool in
+                 instance Old Age where old = \\a -> true in
+                 instance Old Int where old = \\n -> false in old (Age 1)",
+                "Bool",
+                "true",
+            ),
+        ];
+        for (source, ty, value) in cases {
+            let translated = translate(source)?;
+            assert_eq!(translated.ty.to_string(), ty, "{source}");
+            let result = eval(&crate::prelude::default_env(), &translated.expr)?;
+            assert_eq!(result.to_string(), value, "{source}");
+        }
+
+        let errors = [
+            (
+                "newtype UserId = UserId Int in add (UserId 1) 2",
+                "different type functions",
+            ),
+            (
+                "newtype UserId = UserId Int in (1 : UserId)",
+                "different type functions",
+            ),
+            (
+                "newtype Age = Age Int in class Old a where old : a -> Bool in
+                 instance Old Int where old = \\n -> false in old (Age 1)",
+                "No instance for `Old Age`",
+            ),
+            (
+                "newtype Bad = A Int | B Int in 1",
+                "exactly one constructor",
+            ),
+            ("newtype Bad = Bad Int Int in 1", "exactly one constructor"),
+            ("newtype Bad = Bad in 1", "exactly one constructor"),
+            ("newtype Bad in 1", "exactly one constructor"),
+        ];
+        for (source, expected) in errors {
+            let err = format!("{:#}", translate(source).unwrap_err());
+            assert!(err.contains(expected), "{source}: {err}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_existentials() -> anyhow::Result<()> {
+        use crate::classes::translate_program;
+        use crate::system_f::verify_inference;
+
+        for source in [
+            "data Showable = forall a. MkShowable a (a -> String) in 1",
+            "data T a = forall b c. T a b (b -> c) | U in 1",
+            "case xs of Nil -> 0; Cons _ t -> 1",
+            "case xs of Cons h t -> (case t of Nil -> h; Cons _ _ -> 0); Nil -> 0",
+            "\\s -> case s of MkShowable x f -> f x",
+        ] {
+            let program: Program = source.parse()?;
+            assert_eq!(program.to_string(), source);
+        }
+
+        // `case` runs the same on every backend
+        let sources = [
+            "case list 1 of Nil -> 0; Cons h t -> h",
+            r"letrec len = \xs -> case xs of Nil -> 0; Cons _ t -> inc (len t) in len (Cons 1 (list 2))",
+            r"\xs -> case xs of Nil -> true; Cons h t -> false",
+            r"let x = 5 in (\f -> f (list 1)) (\ys -> case ys of Cons y _ -> add x y)",
+        ];
+        let env = crate::prelude::default_env();
+        let ctx = crate::prelude::default_ctx();
+        for source in sources {
+            let expr = Parser::new(Lexer::new(source)).parse()?;
+            verify_inference(&ctx, &expr)?;
+            let expected = eval(&env, &expr)?.to_string();
+            let lazy = eval_with(Strategy::CallByNeed, &env, &expr).and_then(force_deep)?;
+            assert_eq!(lazy.to_string(), expected, "{source}");
+            let program = crate::vm::compile(&env, &expr)?;
+            assert_eq!(crate::vm::run(&program)?.to_string(), expected, "{source}");
+            let lifted = crate::lift::lambda_lift(&expr).to_expr();
+            assert_eq!(eval(&env, &lifted)?.to_string(), expected, "{source}");
+        }
+        let expr = Parser::new(Lexer::new("case list 1 of Nil -> 0")).parse()?;
+        let err = eval(&env, &expr).unwrap_err().to_string();
+        assert!(
+            err.contains("No arm of the case matches `Cons 1 Nil`"),
+            "{err}"
+        );
+        let program = crate::vm::compile(&env, &expr)?;
+        assert!(crate::vm::run(&program).is_err());
+
+        let counter = "data Counter = forall s. Counter s (s -> s) (s -> Int) in
+            let run = \\c -> case c of Counter s step get -> get (step (step s)) in ";
+        let program: Program = format!("{counter}run (Counter 0 inc (\\n -> n))").parse()?;
+        let Decl::Data(data) = &program.decls[0] else {
+            unreachable!()
+        };
+        let ctx = data
+            .constructor_schemes()
+            .into_iter()
+            .fold(ctx.clone(), |ctx, (con, scheme)| {
+                ctx.extend_with(con, scheme)
+            });
+        assert_eq!(verify_inference(&ctx, &program.body)?.to_string(), "Int");
+
+        let translate = |source: &str| -> anyhow::Result<_> {
+            let program = crate::prelude::with_prelude(source.parse()?);
+            translate_program(&crate::prelude::default_ctx(), &program)
+        };
+        let showable = "data Showable = forall a. MkShowable a (a -> String) in
+            let show = \\s -> case s of MkShowable x f -> f x in
+            let items = Cons (MkShowable 1 (\\n -> \"int\"))
+                (Cons (MkShowable true (\\b -> \"bool\")) Nil) in ";
+        let cases = [
+            (
+                format!("{showable}show (head (tail items))"),
+                "String",
+                "\"bool\"",
+            ),
+            (format!("{showable}show"), "Showable -> String", "<closure>"),
+            (
+                format!("{counter}run (Counter (list 1) (Cons 2) (\\xs -> 7))"),
+                "Int",
+                "7",
+            ),
+            (
+                "newtype Age = Age Int in case Age 3 of Age n -> inc n".to_string(),
+                "Int",
+                "4",
+            ),
+            (
+                "class Size a where size : a -> Int; empty : a -> Bool in
+                 instance Size (List a) where
+                   size = \\xs -> case xs of Nil -> 0; Cons _ _ -> 1;
+                   empty = \\xs -> case xs of Nil -> true; Cons _ _ -> false
+                 in empty (list 2)"
+                    .to_string(),
+                "Bool",
+                "false",
+            ),
+        ];
+        for (source, ty, value) in cases {
+            let translated = translate(&source)?;
+            assert_eq!(translated.ty.to_string(), ty, "{source}");
+            if value != "<closure>" {
+                let result = eval(&crate::prelude::default_env(), &translated.expr)?;
+                assert_eq!(result.to_string(), value, "{source}");
+            }
+        }
+
+        let errors = [
+            (
+                "data Showable = forall a. MkShowable a (a -> String) in
+                 \\s -> case s of MkShowable x f -> x",
+                "Type variable `a` would escape its scope in the arm `MkShowable x f`",
+            ),
+            (
+                "data Box = forall a. Box a in \\g -> case Box 1 of Box x -> g x",
+                "Type variable `a` would escape its scope in the arm `Box x`",
+            ),
+            (
+                "data Box = forall a. Box a in case Box 1 of Box x -> add x 1",
+                "different type functions",
+            ),
+            (
+                "data Box = forall a. Box a in
+                 \\b c -> case b of Box x -> case c of Box y -> (\\u v -> v) x y",
+                "would escape its scope in the arm `Box y`",
+            ),
+            (
+                "case list 1 of Cons h -> h",
+                "`Cons` has 2 field(s) but the arm `Cons h` binds 1",
+            ),
+            ("case 1 of Foo -> 1", "Undefined constructor: Foo"),
+            ("case 1 of Nil -> 1", "in the arm `Nil`"),
+            (
+                "case list 1 of Nil -> 0; Cons h t -> true",
+                "different type functions",
+            ),
+            (
+                "data Bad = forall a. Bad b in 1",
+                "Type variable `b` in `Bad` is not a parameter of `Bad`",
+            ),
+            ("data Bad a = forall a. Bad a in 1", "`a` is bound twice"),
+            (
+                "newtype Bad = forall a. Bad a in 1",
+                "newtype Bad can't have existential variables",
+            ),
+            ("case list 1 of Cons x x -> x", "`x` is bound twice"),
+            ("case list 1 of x -> x", "expected a constructor pattern"),
+        ];
+        for (source, expected) in errors {
+            let err = format!("{:#}", translate(source).unwrap_err());
+            assert!(err.contains(expected), "{source}: {err}");
+        }
+
+        let expr = Parser::new(Lexer::new("case list 1 of Nil -> 0")).parse()?;
+        let env = crate::bidir::type_env(&crate::prelude::default_ctx());
+        let err = crate::bidir::infer(&env, &expr).unwrap_err();
+        assert!(err.to_string().contains("only Algorithm W handles `case`"));
+
         Ok(())
     }
 }
@@ -0,0 +1,386 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::models::*;
+
+/// A function moved to the top level of a program.
+#[derive(Debug, Clone)]
+pub struct TopLevel {
+    pub name: String,
+    pub expr: Expr,
+    pub recursive: bool,
+}
+
+/// The result of [`lambda_lift`]: closed functions, each only referring to globals and
+/// the functions before it, and the expression that uses them.
+#[derive(Debug, Clone)]
+pub struct LiftedProgram {
+    pub functions: Vec<TopLevel>,
+    pub main: Expr,
+}
+
+impl LiftedProgram {
+    /// Binds the functions around `main` again, as `let`s and `letrec`s.
+    pub fn to_expr(&self) -> Expr {
+        self.functions
+            .iter()
+            .rev()
+            .fold(self.main.clone(), |aexpr, f| {
+                let elet = Box::new(LetExpr {
+                    var: f.name.clone(),
+                    vexpr: f.expr.clone(),
+                    aexpr,
+                });
+                if f.recursive {
+                    Expr::LetRec(elet)
+                } else {
+                    Expr::Let(elet)
+                }
+            })
+    }
+}
+
+/// Closure-converts every lambda of `expr`, so that it takes the local variables it
+/// uses as extra leading arguments, and lifts it to the top level. Each lambda is
+/// replaced by its function applied to those variables, its explicit environment.
+///
+/// `let`-bound lambdas are lifted under their own name and stay polymorphic at the
+/// top level, so the result has the same type as `expr`. Free variables of `expr` are
+/// left alone, they are the globals.
+pub fn lambda_lift(expr: &Expr) -> LiftedProgram {
+    let mut names = NameSupply::new(expr);
+    let expr = names.rename(expr, &HashMap::new());
+
+    let mut lifter = Lifter {
+        names,
+        functions: vec![],
+    };
+    let main = lifter.lift(&expr, &HashMap::new());
+
+    LiftedProgram {
+        functions: lifter.functions,
+        main,
+    }
+}
+
+/// Hands out names that are not used anywhere in a program.
+pub(crate) struct NameSupply {
+    used: HashSet<String>,
+    /// Names already given to a binder, or free in the program.
+    bound: HashSet<String>,
+}
+
+impl NameSupply {
+    pub(crate) fn new(expr: &Expr) -> NameSupply {
+        fn collect(expr: &Expr, used: &mut HashSet<String>) {
+            match expr {
+                Expr::Lit(_) | Expr::Con(_) => {}
+                Expr::Var(var) => {
+                    used.insert(var.clone());
+                }
+                Expr::App(app) => {
+                    collect(&app.fun, used);
+                    collect(&app.arg, used);
+                }
+                Expr::Abs(abs) => {
+                    used.insert(abs.arg.clone());
+                    collect(&abs.body, used);
+                }
+                Expr::Let(elet) | Expr::LetRec(elet) => {
+                    used.insert(elet.var.clone());
+                    collect(&elet.vexpr, used);
+                    collect(&elet.aexpr, used);
+                }
+                Expr::Ann(ann) => collect(&ann.expr, used),
+                Expr::Case(case) => {
+                    collect(&case.expr, used);
+                    for arm in case.arms.iter() {
+                        used.extend(arm.vars.iter().cloned());
+                        collect(&arm.body, used);
+                    }
+                }
+            }
+        }
+
+        let mut used = HashSet::new();
+        collect(expr, &mut used);
+        NameSupply {
+            used,
+            bound: expr.free_vars().into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    pub(crate) fn fresh(&mut self, base: &str) -> String {
+        let name = (1..)
+            .map(|i| format!("{base}_{i}"))
+            .find(|name| !self.used.contains(name))
+            .unwrap();
+        self.used.insert(name.clone());
+        name
+    }
+
+    /// `name` itself if nothing uses it yet, otherwise a fresh name based on it.
+    pub(crate) fn name(&mut self, name: &str) -> String {
+        if self.used.insert(name.to_string()) {
+            name.to_string()
+        } else {
+            self.fresh(name)
+        }
+    }
+
+    /// Renames binders so that no two bind the same name and none shadows a global,
+    /// so that any of them can be moved to the top level.
+    pub(crate) fn rename(&mut self, expr: &Expr, mapping: &HashMap<String, String>) -> Expr {
+        let bind = |this: &mut Self, var: &str| {
+            let name = if this.bound.contains(var) {
+                this.fresh(var)
+            } else {
+                var.to_string()
+            };
+            this.bound.insert(name.clone());
+            let mut mapping = mapping.clone();
+            mapping.insert(var.to_string(), name.clone());
+            (name, mapping)
+        };
+
+        match expr {
+            Expr::Lit(_) | Expr::Con(_) => expr.clone(),
+            Expr::Var(var) => Expr::Var(mapping.get(var).unwrap_or(var).clone()),
+            Expr::App(app) => Expr::App(Box::new(AppExpr {
+                fun: self.rename(&app.fun, mapping),
+                arg: self.rename(&app.arg, mapping),
+            })),
+            Expr::Abs(abs) => {
+                let (arg, mapping) = bind(self, &abs.arg);
+                let body = self.rename(&abs.body, &mapping);
+                Expr::Abs(Box::new(AbsExpr { arg, body }))
+            }
+            Expr::Let(elet) => {
+                let vexpr = self.rename(&elet.vexpr, mapping);
+                let (var, mapping) = bind(self, &elet.var);
+                let aexpr = self.rename(&elet.aexpr, &mapping);
+                Expr::Let(Box::new(LetExpr { var, vexpr, aexpr }))
+            }
+            Expr::LetRec(elet) => {
+                let (var, mapping) = bind(self, &elet.var);
+                let vexpr = self.rename(&elet.vexpr, &mapping);
+                let aexpr = self.rename(&elet.aexpr, &mapping);
+                Expr::LetRec(Box::new(LetExpr { var, vexpr, aexpr }))
+            }
+            Expr::Ann(ann) => Expr::Ann(Box::new(AnnExpr {
+                expr: self.rename(&ann.expr, mapping),
+                ty: ann.ty.clone(),
+            })),
+            Expr::Case(case) => {
+                let expr = self.rename(&case.expr, mapping);
+                let arms = case
+                    .arms
+                    .iter()
+                    .map(|arm| {
+                        let mut mapping = mapping.clone();
+                        let vars = arm
+                            .vars
+                            .iter()
+                            .map(|var| {
+                                let (name, inner) = bind(self, var);
+                                mapping.insert(var.clone(), inner[var].clone());
+                                name
+                            })
+                            .collect();
+                        CaseArm {
+                            con: arm.con.clone(),
+                            vars,
+                            body: self.rename(&arm.body, &mapping),
+                        }
+                    })
+                    .collect();
+                Expr::Case(Box::new(CaseExpr { expr, arms }))
+            }
+        }
+    }
+}
+
+/// What a variable in scope stands for while lifting.
+#[derive(Debug, Clone)]
+enum Binding {
+    Local,
+    /// A lifted function, applied to its environment.
+    Lifted(Expr),
+}
+
+struct Lifter {
+    names: NameSupply,
+    functions: Vec<TopLevel>,
+}
+
+impl Lifter {
+    fn lift(&mut self, expr: &Expr, scope: &HashMap<String, Binding>) -> Expr {
+        match expr {
+            Expr::Lit(_) | Expr::Con(_) => expr.clone(),
+            Expr::Var(var) => match scope.get(var) {
+                Some(Binding::Lifted(replacement)) => replacement.clone(),
+                _ => expr.clone(),
+            },
+            Expr::App(app) => Expr::App(Box::new(AppExpr {
+                fun: self.lift(&app.fun, scope),
+                arg: self.lift(&app.arg, scope),
+            })),
+            Expr::Abs(_) => {
+                let name = self.names.fresh("lambda");
+                self.lift_function(name, false, expr, scope)
+            }
+            Expr::Let(elet) | Expr::LetRec(elet) if matches!(elet.vexpr, Expr::Abs(_)) => {
+                let recursive = matches!(expr, Expr::LetRec(_));
+                let replacement =
+                    self.lift_function(elet.var.clone(), recursive, &elet.vexpr, scope);
+                let mut scope = scope.clone();
+                scope.insert(elet.var.clone(), Binding::Lifted(replacement));
+                self.lift(&elet.aexpr, &scope)
+            }
+            Expr::Let(elet) => {
+                let vexpr = self.lift(&elet.vexpr, scope);
+                let mut scope = scope.clone();
+                scope.insert(elet.var.clone(), Binding::Local);
+                let aexpr = self.lift(&elet.aexpr, &scope);
+                Expr::Let(Box::new(LetExpr {
+                    var: elet.var.clone(),
+                    vexpr,
+                    aexpr,
+                }))
+            }
+            Expr::LetRec(elet) => {
+                let mut scope = scope.clone();
+                scope.insert(elet.var.clone(), Binding::Local);
+                let vexpr = self.lift(&elet.vexpr, &scope);
+                let aexpr = self.lift(&elet.aexpr, &scope);
+                Expr::LetRec(Box::new(LetExpr {
+                    var: elet.var.clone(),
+                    vexpr,
+                    aexpr,
+                }))
+            }
+            // applying a lifted function to its environment doesn't change its type
+            Expr::Ann(ann) => Expr::Ann(Box::new(AnnExpr {
+                expr: self.lift(&ann.expr, scope),
+                ty: ann.ty.clone(),
+            })),
+            Expr::Case(case) => {
+                let arms = case
+                    .arms
+                    .iter()
+                    .map(|arm| {
+                        let mut scope = scope.clone();
+                        for var in arm.vars.iter() {
+                            scope.insert(var.clone(), Binding::Local);
+                        }
+                        CaseArm {
+                            con: arm.con.clone(),
+                            vars: arm.vars.clone(),
+                            body: self.lift(&arm.body, &scope),
+                        }
+                    })
+                    .collect();
+                Expr::Case(Box::new(CaseExpr {
+                    expr: self.lift(&case.expr, scope),
+                    arms,
+                }))
+            }
+        }
+    }
+
+    /// Lifts the lambda `abs` bound to `name` and returns what replaces it.
+    fn lift_function(
+        &mut self,
+        name: String,
+        recursive: bool,
+        abs: &Expr,
+        scope: &HashMap<String, Binding>,
+    ) -> Expr {
+        let env = captured(abs, scope, if recursive { Some(&name) } else { None });
+        let replacement = env.iter().fold(Expr::Var(name.clone()), |fun, var| {
+            Expr::App(Box::new(AppExpr {
+                fun,
+                arg: Expr::Var(var.clone()),
+            }))
+        });
+
+        let mut args = env;
+        let mut body = abs;
+        while let Expr::Abs(abs) = body {
+            args.push(abs.arg.clone());
+            body = &abs.body;
+        }
+
+        // the lambdas of the body are lifted before the function, so they can't refer
+        // to it, they take it as an argument like any other local
+        let rebind = recursive && lambda_uses(body, &name);
+        let mut inner = scope.clone();
+        if rebind {
+            inner.insert(name.clone(), Binding::Local);
+        } else if recursive {
+            inner.insert(name.clone(), Binding::Lifted(replacement.clone()));
+        }
+        for arg in args.iter() {
+            inner.insert(arg.clone(), Binding::Local);
+        }
+        let mut body = self.lift(body, &inner);
+        if rebind {
+            body = Expr::Let(Box::new(LetExpr {
+                var: name.clone(),
+                vexpr: replacement.clone(),
+                aexpr: body,
+            }));
+        }
+
+        let expr = args
+            .into_iter()
+            .rev()
+            .fold(body, |body, arg| Expr::Abs(Box::new(AbsExpr { arg, body })));
+        self.functions.push(TopLevel {
+            name,
+            expr,
+            recursive,
+        });
+
+        replacement
+    }
+}
+
+/// Whether a lambda inside `expr` uses `var`.
+fn lambda_uses(expr: &Expr, var: &str) -> bool {
+    match expr {
+        Expr::Lit(_) | Expr::Var(_) | Expr::Con(_) => false,
+        Expr::Abs(_) => expr.free_vars().contains(var),
+        Expr::App(app) => lambda_uses(&app.fun, var) || lambda_uses(&app.arg, var),
+        Expr::Let(elet) | Expr::LetRec(elet) => {
+            lambda_uses(&elet.vexpr, var) || lambda_uses(&elet.aexpr, var)
+        }
+        Expr::Ann(ann) => lambda_uses(&ann.expr, var),
+        Expr::Case(case) => {
+            lambda_uses(&case.expr, var) || case.arms.iter().any(|arm| lambda_uses(&arm.body, var))
+        }
+    }
+}
+
+/// The local variables `expr` uses, directly or through the environment of a lifted
+/// function, in a stable order.
+fn captured(expr: &Expr, scope: &HashMap<String, Binding>, itself: Option<&str>) -> Vec<String> {
+    let mut env = BTreeSet::new();
+    for var in expr.free_vars() {
+        match scope.get(var) {
+            _ if Some(var) == itself => {}
+            Some(Binding::Local) => {
+                env.insert(var.to_string());
+            }
+            Some(Binding::Lifted(replacement)) => {
+                let locals = replacement
+                    .free_vars()
+                    .into_iter()
+                    .filter(|v| matches!(scope.get(*v), Some(Binding::Local)));
+                env.extend(locals.map(str::to_string));
+            }
+            None => {}
+        }
+    }
+
+    env.into_iter().collect()
+}
@@ -1,25 +1,38 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
+    str::FromStr,
 };
 
+use crate::lex::Lexer;
+use crate::parser::Parser;
+use crate::pretty::{Charset, ExprPrinter, TypePrinter};
+use crate::system_f::FType;
 use crate::utils::new_type_var;
 
 pub trait GetFreeVars {
     fn free_vars(&self) -> HashSet<&str>;
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-    Int,
-    Bool,
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+    Char(char),
+    Str(String),
+    Unit,
 }
 
 impl Literal {
     pub fn ty(&self) -> MonoType {
         let type_name = match self {
-            Literal::Int => "Int",
-            Literal::Bool => "Bool",
+            Literal::Int(_) => "Int",
+            Literal::Bool(_) => "Bool",
+            Literal::Float(_) => "Float",
+            Literal::Char(_) => "Char",
+            Literal::Str(_) => "String",
+            Literal::Unit => "Unit",
         }
         .to_string();
 
@@ -33,13 +46,34 @@ impl Literal {
 impl Display for Literal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Literal::Int => write!(f, "int"),
-            Literal::Bool => write!(f, "bool"),
+            Literal::Int(i) => write!(f, "{i}"),
+            Literal::Bool(b) => write!(f, "{b}"),
+            // `{:?}` keeps the `.0` of whole numbers so they read back as floats
+            Literal::Float(x) => write!(f, "{x:?}"),
+            Literal::Char(ch) => write!(f, "'{}'", escape(*ch, '\'')),
+            Literal::Str(s) => {
+                let escaped: String = s.chars().map(|ch| escape(ch, '"')).collect();
+                write!(f, "\"{escaped}\"")
+            }
+            Literal::Unit => write!(f, "()"),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Escapes `ch` the way the lexer reads it back inside a literal delimited by `quote`.
+fn escape(ch: char, quote: char) -> String {
+    match ch {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\r' => "\\r".to_string(),
+        '\0' => "\\0".to_string(),
+        '\\' => "\\\\".to_string(),
+        _ if ch == quote => format!("\\{ch}"),
+        _ => ch.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct AppExpr {
     pub fun: Expr,
     pub arg: Expr,
@@ -47,11 +81,11 @@ pub struct AppExpr {
 
 impl Display for AppExpr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({} {})", self.fun, self.arg)
+        write!(f, "{}", Expr::App(Box::new(self.clone())))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AbsExpr {
     pub arg: String,
     pub body: Expr,
@@ -59,11 +93,11 @@ pub struct AbsExpr {
 
 impl Display for AbsExpr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(\\{} -> {})", self.arg, self.body)
+        write!(f, "{}", Expr::Abs(Box::new(self.clone())))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LetExpr {
     pub var: String,
     pub vexpr: Expr,
@@ -72,32 +106,287 @@ pub struct LetExpr {
 
 impl Display for LetExpr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(let {} = {} in {})", self.var, self.vexpr, self.aexpr)
+        write!(f, "{}", Expr::Let(Box::new(self.clone())))
     }
 }
 
-#[derive(Debug, Clone)]
+/// `(e : σ)`, where `σ` may have quantifiers anywhere, and its free variables are
+/// implicitly quantified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnExpr {
+    pub expr: Expr,
+    pub ty: FType,
+}
+
+impl Display for AnnExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Expr::Ann(Box::new(self.clone())))
+    }
+}
+
+/// `C x .. -> e`, an arm of a `case` binding the fields of the constructor `C`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseArm {
+    pub con: String,
+    pub vars: Vec<String>,
+    pub body: Expr,
+}
+
+impl CaseArm {
+    /// `C x ..`, what the arm matches.
+    pub fn pattern(&self) -> String {
+        let mut pattern = self.con.clone();
+        for var in self.vars.iter() {
+            pattern.push(' ');
+            pattern.push_str(var);
+        }
+        pattern
+    }
+}
+
+impl Display for CaseArm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.pattern(), self.body)
+    }
+}
+
+/// `case e of C x .. -> e1; D -> e2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseExpr {
+    pub expr: Expr,
+    pub arms: Vec<CaseArm>,
+}
+
+impl Display for CaseExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Expr::Case(Box::new(self.clone())))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Lit(Literal),
     Var(String),
+    Con(String),
     App(Box<AppExpr>),
     Abs(Box<AbsExpr>),
     Let(Box<LetExpr>),
+    /// Like `Let`, with `var` also in scope in `vexpr`.
+    LetRec(Box<LetExpr>),
+    Ann(Box<AnnExpr>),
+    Case(Box<CaseExpr>),
+}
+
+/// `class Eq a => Ord a where (<) : a -> a -> Bool`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDecl {
+    pub name: String,
+    pub supers: Vec<String>,
+    pub var: String,
+    /// The methods with their types, in which `var` is free.
+    pub methods: Vec<(String, MonoType)>,
+}
+
+/// `instance Show a => Show (List a) where show = \xs -> ...`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceDecl {
+    pub context: Vec<Pred>,
+    pub head: Pred,
+    pub methods: Vec<(String, Expr)>,
+}
+
+/// `forall a. MkShowable a (a -> String)`, a constructor of a data type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConDecl {
+    pub name: String,
+    /// The existential variables, free in the fields but not in the type built.
+    pub exists: Vec<String>,
+    /// The types of the fields, in which the parameters and `exists` are free.
+    pub fields: Vec<MonoType>,
+}
+
+/// `data Maybe a = Nothing | Just a`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub constructors: Vec<ConDecl>,
+    /// Declared with `newtype`: a type distinct from that of its only field, whose
+    /// constructor is erased when the program is translated.
+    pub newtype: bool,
+}
+
+impl DataDecl {
+    /// The type of a value built by the constructors, `T a ..`.
+    pub fn ty(&self) -> MonoType {
+        MonoType::TyApp(TypeApp {
+            func: self.name.clone(),
+            monotypes: self.params.iter().cloned().map(MonoType::TyVar).collect(),
+        })
+    }
+
+    /// The scheme of each constructor, `∀a... b... t1 -> .. -> T a ..`, quantified
+    /// over the parameters then the existential variables.
+    pub fn constructor_schemes(&self) -> Vec<(String, PolyType)> {
+        self.constructors
+            .iter()
+            .map(|con| {
+                let ty = con
+                    .fields
+                    .iter()
+                    .rev()
+                    .fold(self.ty(), |acc, field| MonoType::arrow(field.clone(), acc));
+                let scheme = self.params.iter().chain(con.exists.iter()).rev().fold(
+                    PolyType::Mono(ty),
+                    |acc, param| {
+                        PolyType::TyQuantifier(TypeQuantifier {
+                            bounded: param.clone(),
+                            ty: Box::new(acc),
+                        })
+                    },
+                );
+                (con.name.clone(), scheme)
+            })
+            .collect()
+    }
+}
+
+/// `type Pred a = a -> Bool`, another name for a type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynonymDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub ty: MonoType,
+}
+
+impl Display for SynonymDecl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "type {}", self.name)?;
+        for param in self.params.iter() {
+            write!(f, " {param}")?;
+        }
+        write!(f, " = {}", self.ty)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl {
+    Class(ClassDecl),
+    Instance(InstanceDecl),
+    /// `default Num Int`, a type to try for ambiguous variables of the class.
+    Default(Pred),
+    Data(DataDecl),
+    Type(SynonymDecl),
+}
+
+/// Declarations followed by the expression they are in scope of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub decls: Vec<Decl>,
+    pub body: Expr,
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", ExprPrinter::new(usize::MAX).print_program(self))
+    }
+}
+
+impl FromStr for Program {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser::new(Lexer::new(s)).parse_program()
+    }
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", ExprPrinter::new(usize::MAX).print(self))
+    }
+}
+
+impl Expr {
+    /// Whether the expression is a syntactic value, whose evaluation can't allocate a
+    /// `ref`: a literal, a variable, a lambda, or a constructor applied to values.
+    pub fn is_value(&self) -> bool {
+        match self {
+            Expr::Lit(_) | Expr::Var(_) | Expr::Con(_) | Expr::Abs(_) => true,
+            Expr::App(app) => {
+                let mut fun = &app.fun;
+                while let Expr::App(app) = fun {
+                    if !app.arg.is_value() {
+                        return false;
+                    }
+                    fun = &app.fun;
+                }
+                matches!(fun, Expr::Con(_)) && app.arg.is_value()
+            }
+            Expr::Let(elet) | Expr::LetRec(elet) => elet.vexpr.is_value() && elet.aexpr.is_value(),
+            Expr::Ann(ann) => ann.expr.is_value(),
+            Expr::Case(_) => false,
+        }
+    }
+
+    /// Whether a `case` occurs in the expression, which the bidirectional checker and
+    /// the C backend don't handle.
+    pub fn has_case(&self) -> bool {
         match self {
-            Expr::Lit(lit) => write!(f, "{lit}"),
-            Expr::Var(var) => write!(f, "{var}"),
-            Expr::App(app) => write!(f, "{app}"),
-            Expr::Abs(abs) => write!(f, "{abs}"),
-            Expr::Let(elet) => write!(f, "{elet}"),
+            Expr::Lit(_) | Expr::Var(_) | Expr::Con(_) => false,
+            Expr::App(app) => app.fun.has_case() || app.arg.has_case(),
+            Expr::Abs(abs) => abs.body.has_case(),
+            Expr::Let(elet) | Expr::LetRec(elet) => elet.vexpr.has_case() || elet.aexpr.has_case(),
+            Expr::Ann(ann) => ann.expr.has_case(),
+            Expr::Case(_) => true,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// The variables an expression uses without binding them.
+impl GetFreeVars for Expr {
+    fn free_vars(&self) -> HashSet<&str> {
+        match self {
+            Expr::Lit(_) | Expr::Con(_) => HashSet::new(),
+            Expr::Var(var) => HashSet::from([var.as_str()]),
+            Expr::App(app) => {
+                let mut vars = app.fun.free_vars();
+                vars.extend(app.arg.free_vars());
+                vars
+            }
+            Expr::Abs(abs) => {
+                let mut vars = abs.body.free_vars();
+                vars.remove(abs.arg.as_str());
+                vars
+            }
+            Expr::Let(elet) => {
+                let mut vars = elet.aexpr.free_vars();
+                vars.remove(elet.var.as_str());
+                vars.extend(elet.vexpr.free_vars());
+                vars
+            }
+            Expr::LetRec(elet) => {
+                let mut vars = elet.aexpr.free_vars();
+                vars.extend(elet.vexpr.free_vars());
+                vars.remove(elet.var.as_str());
+                vars
+            }
+            Expr::Ann(ann) => ann.expr.free_vars(),
+            Expr::Case(case) => {
+                let mut vars = case.expr.free_vars();
+                for arm in case.arms.iter() {
+                    let mut arm_vars = arm.body.free_vars();
+                    for var in arm.vars.iter() {
+                        arm_vars.remove(var.as_str());
+                    }
+                    vars.extend(arm_vars);
+                }
+                vars
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TypeApp {
     pub func: String,
     pub monotypes: Vec<MonoType>,
@@ -105,45 +394,183 @@ pub struct TypeApp {
 
 impl Display for TypeApp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.func.as_str() {
-            "->" => {
-                let len = self.monotypes.len();
-                assert!(len == 2, "expected 2 args for `->` but got {len}");
-                write!(f, "{} -> {}", self.monotypes[0], self.monotypes[1])
-            }
-            _ => {
-                write!(f, "{}", self.func)?;
-                for mono in self.monotypes.iter() {
-                    write!(f, " {}", mono)?;
-                }
-                Ok(())
-            }
-        }
+        write!(f, "{}", MonoType::TyApp(self.clone()))
     }
 }
 
-#[derive(Debug, Clone)]
+/// A type variable of a higher kind applied to types, like the `f a` of
+/// `(a -> b) -> f a -> f b`. It always has arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeVarApp {
+    pub var: String,
+    pub monotypes: Vec<MonoType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MonoType {
     TyVar(String),
     TyApp(TypeApp),
+    TyVarApp(TypeVarApp),
 }
 
 impl Display for MonoType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MonoType::TyVar(var) => write!(f, "{var}"),
-            MonoType::TyApp(app) => write!(f, "{app}"),
-        }
+        let printer = TypePrinter::new(Charset::Unicode, false);
+        write!(f, "{}", printer.print_mono(self))
+    }
+}
+
+impl FromStr for MonoType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(Lexer::new(s));
+        let ty = parser.parse_mono_type()?;
+        parser.expect_eof()?;
+
+        Ok(ty)
     }
 }
 
 impl MonoType {
+    pub fn arrow(from: MonoType, to: MonoType) -> MonoType {
+        MonoType::TyApp(TypeApp {
+            func: "->".to_string(),
+            monotypes: vec![from, to],
+        })
+    }
+
+    pub fn is_arrow(&self) -> bool {
+        matches!(self, MonoType::TyApp(app) if app.func == "->" && app.monotypes.len() == 2)
+    }
+
+    /// Applies the type to more arguments, keeping the head of the result a name.
+    pub fn applied_to(self, args: Vec<MonoType>) -> MonoType {
+        if args.is_empty() {
+            return self;
+        }
+        match self {
+            MonoType::TyVar(var) => MonoType::TyVarApp(TypeVarApp {
+                var,
+                monotypes: args,
+            }),
+            MonoType::TyApp(mut app) => {
+                app.monotypes.extend(args);
+                MonoType::TyApp(app)
+            }
+            MonoType::TyVarApp(mut app) => {
+                app.monotypes.extend(args);
+                MonoType::TyVarApp(app)
+            }
+        }
+    }
+
+    /// Whether the type can be printed as an argument without parentheses.
+    pub fn is_atom(&self) -> bool {
+        match self {
+            MonoType::TyVar(_) => true,
+            MonoType::TyApp(app) => app.monotypes.is_empty(),
+            MonoType::TyVarApp(_) => false,
+        }
+    }
+
+    /// The type variables of the type, in order of first appearance.
+    pub fn type_vars(&self) -> Vec<&str> {
+        fn collect<'a>(mono: &'a MonoType, acc: &mut Vec<&'a str>) {
+            match mono {
+                MonoType::TyVar(var) => {
+                    if !acc.contains(&var.as_str()) {
+                        acc.push(var);
+                    }
+                }
+                MonoType::TyApp(app) => app.monotypes.iter().for_each(|m| collect(m, acc)),
+                MonoType::TyVarApp(app) => {
+                    if !acc.contains(&app.var.as_str()) {
+                        acc.push(&app.var);
+                    }
+                    app.monotypes.iter().for_each(|m| collect(m, acc));
+                }
+            }
+        }
+
+        let mut acc = vec![];
+        collect(self, &mut acc);
+        acc
+    }
+
+    /// Quantifies the type variables that are not free in `ctx`, in order of first
+    /// appearance.
     pub fn generalise(&self, ctx: &Context) -> PolyType {
-        let ty_free_vars = self.free_vars();
-        let ctx_free_vars = ctx.free_vars();
-        let quantifiers = ty_free_vars.difference(&ctx_free_vars);
+        self.generalise_with(vec![], ctx)
+    }
+
+    /// Like [`MonoType::generalise`], qualifying the type with `preds`.
+    pub fn generalise_with(&self, preds: Vec<Pred>, ctx: &Context) -> PolyType {
+        self.quantify(preds, ctx.free_vars())
+    }
+
+    /// Generalises the type of an expression that isn't a value, which may have
+    /// allocated a `ref` of it: only the variables that occur nowhere but in covariant
+    /// positions are quantified, Garrigue's relaxation of the value restriction.
+    pub fn generalise_relaxed(&self, preds: Vec<Pred>, ctx: &Context) -> PolyType {
+        let mut fixed = ctx.free_vars();
+        fixed.extend(self.non_covariant_vars());
+        self.quantify(preds, fixed)
+    }
+
+    /// The variables with an occurrence left of an odd number of arrows, or under a
+    /// type constructor other than `->` and `List`, which are assumed invariant, as
+    /// are applied variables and their arguments.
+    fn non_covariant_vars(&self) -> HashSet<&str> {
+        fn collect<'a>(mono: &'a MonoType, covariant: Option<bool>, acc: &mut HashSet<&'a str>) {
+            match mono {
+                MonoType::TyVar(var) => {
+                    if covariant != Some(true) {
+                        acc.insert(var);
+                    }
+                }
+                MonoType::TyApp(app) => {
+                    for (i, m) in app.monotypes.iter().enumerate() {
+                        let variance = match app.func.as_str() {
+                            "->" if i == 0 => covariant.map(|c| !c),
+                            "->" | "List" => covariant,
+                            _ => None,
+                        };
+                        collect(m, variance, acc);
+                    }
+                }
+                MonoType::TyVarApp(app) => {
+                    acc.insert(&app.var);
+                    app.monotypes.iter().for_each(|m| collect(m, None, acc));
+                }
+            }
+        }
 
-        quantifiers.fold(PolyType::Mono(self.clone()), |acc, q| {
+        let mut acc = HashSet::new();
+        collect(self, Some(true), &mut acc);
+        acc
+    }
+
+    fn quantify(&self, preds: Vec<Pred>, fixed: HashSet<&str>) -> PolyType {
+        let mut vars = self.type_vars();
+        for pred in preds.iter() {
+            for var in pred.ty.type_vars() {
+                if !vars.contains(&var) {
+                    vars.push(var);
+                }
+            }
+        }
+        let quantifiers = vars.into_iter().filter(|var| !fixed.contains(var));
+
+        let body = if preds.is_empty() {
+            PolyType::Mono(self.clone())
+        } else {
+            PolyType::Qualified(QualType {
+                preds: preds.clone(),
+                ty: self.clone(),
+            })
+        };
+        quantifiers.rev().fold(body, |acc, q| {
             PolyType::TyQuantifier(TypeQuantifier {
                 bounded: q.to_string(),
                 ty: Box::new(acc),
@@ -169,6 +596,15 @@ impl MonoType {
                     monotypes,
                 })
             }
+            MonoType::TyVarApp(app) => {
+                let head = MonoType::TyVar(app.var.clone()).instantiate_impl(mapping);
+                let monotypes = app
+                    .monotypes
+                    .iter()
+                    .map(|m| m.instantiate_impl(mapping))
+                    .collect();
+                head.applied_to(monotypes)
+            }
         }
     }
 }
@@ -182,11 +618,17 @@ impl GetFreeVars for MonoType {
                 set
             }
             MonoType::TyApp(app) => app.monotypes.iter().flat_map(|m| m.free_vars()).collect(),
+            MonoType::TyVarApp(app) => {
+                let mut set: HashSet<_> =
+                    app.monotypes.iter().flat_map(|m| m.free_vars()).collect();
+                set.insert(app.var.as_str());
+                set
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TypeQuantifier {
     pub bounded: String,
     pub ty: Box<PolyType>,
@@ -194,38 +636,224 @@ pub struct TypeQuantifier {
 
 impl Display for TypeQuantifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "∀{}.{} -> {}", self.bounded, self.bounded, self.ty)
+        write!(f, "{}", PolyType::TyQuantifier(self.clone()))
     }
 }
 
-#[derive(Debug, Clone)]
+/// A class constraint on a type, `Num a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pred {
+    pub class: String,
+    pub ty: MonoType,
+}
+
+impl Display for Pred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.ty.is_atom() {
+            write!(f, "{} {}", self.class, self.ty)
+        } else {
+            write!(f, "{} ({})", self.class, self.ty)
+        }
+    }
+}
+
+impl GetFreeVars for Pred {
+    fn free_vars(&self) -> HashSet<&str> {
+        self.ty.free_vars()
+    }
+}
+
+/// A type that only holds under its predicates, `Num a => a -> a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QualType {
+    pub preds: Vec<Pred>,
+    pub ty: MonoType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PolyType {
     Mono(MonoType),
     TyQuantifier(TypeQuantifier),
+    /// Only under quantifiers, and only with at least one predicate.
+    Qualified(QualType),
 }
 
 impl Display for PolyType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            PolyType::Mono(mono) => write!(f, "{mono}"),
-            PolyType::TyQuantifier(quan) => write!(f, "{quan}"),
-        }
+        write!(f, "{}", TypePrinter::default().print_poly(self))
+    }
+}
+
+impl FromStr for PolyType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(Lexer::new(s));
+        let ty = parser.parse_poly_type()?;
+        parser.expect_eof()?;
+
+        Ok(ty)
     }
 }
 
 impl PolyType {
-    pub fn instantiate(&self) -> MonoType {
-        self.instantiate_impl(&mut HashMap::new())
+    /// Compares two type schemes up to renaming of their bound variables.
+    pub fn alpha_eq(&self, other: &PolyType) -> bool {
+        let (bounded1, mono1) = self.split();
+        let (bounded2, mono2) = other.split();
+        if bounded1.len() != bounded2.len() {
+            return false;
+        }
+
+        let (preds1, preds2) = (self.preds(), other.preds());
+        if preds1.len() != preds2.len() {
+            return false;
+        }
+
+        let mut mapping = HashMap::new();
+        let mut mapped = HashSet::new();
+        let mut alpha_eq =
+            |m1, m2| alpha_eq_impl(m1, m2, &bounded1, &bounded2, &mut mapping, &mut mapped);
+        alpha_eq(mono1, mono2)
+            && preds1
+                .iter()
+                .zip(preds2)
+                .all(|(p1, p2)| p1.class == p2.class && alpha_eq(&p1.ty, &p2.ty))
     }
 
-    fn instantiate_impl<'a>(&'a self, mapping: &mut HashMap<&'a str, MonoType>) -> MonoType {
-        match self {
-            PolyType::Mono(mono) => mono.instantiate_impl(mapping),
-            PolyType::TyQuantifier(quan) => {
-                mapping.insert(&quan.bounded, new_type_var());
-                quan.ty.instantiate_impl(mapping)
+    /// Splits a scheme into its bound variables (outermost first) and its body.
+    pub fn split(&self) -> (Vec<&str>, &MonoType) {
+        let mut bounded = vec![];
+        let mut poly = self;
+        loop {
+            match poly {
+                PolyType::Mono(mono) => return (bounded, mono),
+                PolyType::Qualified(qual) => return (bounded, &qual.ty),
+                PolyType::TyQuantifier(quan) => {
+                    bounded.push(quan.bounded.as_str());
+                    poly = &quan.ty;
+                }
+            }
+        }
+    }
+
+    /// The variables of the predicates that the type doesn't mention: nothing that
+    /// uses the scheme can determine them.
+    pub fn ambiguous_vars(&self) -> Vec<&str> {
+        let (_, mono) = self.split();
+        let ty_vars = mono.type_vars();
+        let mut vars = vec![];
+        for pred in self.preds() {
+            for var in pred.ty.type_vars() {
+                if !ty_vars.contains(&var) && !vars.contains(&var) {
+                    vars.push(var);
+                }
             }
         }
+
+        vars
+    }
+
+    /// The predicates under the quantifiers.
+    pub fn preds(&self) -> &[Pred] {
+        match self {
+            PolyType::Mono(_) => &[],
+            PolyType::Qualified(qual) => &qual.preds,
+            PolyType::TyQuantifier(quan) => quan.ty.preds(),
+        }
+    }
+
+    /// The predicates with the bound variables, outermost first, instantiated with `args`.
+    pub fn instantiate_preds(&self, args: &[MonoType]) -> Vec<Pred> {
+        let (bounded, _) = self.split();
+        let mut mapping = bounded.into_iter().zip(args.iter().cloned()).collect();
+        self.preds()
+            .iter()
+            .map(|pred| Pred {
+                class: pred.class.clone(),
+                ty: pred.ty.instantiate_impl(&mut mapping),
+            })
+            .collect()
+    }
+
+    pub fn instantiate(&self) -> MonoType {
+        self.instantiate_fresh().1
+    }
+
+    /// Instantiates the bound variables with fresh type variables, which are returned
+    /// too, outermost first.
+    pub fn instantiate_fresh(&self) -> (Vec<MonoType>, MonoType) {
+        let (bounded, _) = self.split();
+        let args: Vec<_> = bounded.iter().map(|_| new_type_var()).collect();
+        let ty = self.instantiate_with(&args);
+
+        (args, ty)
+    }
+
+    /// Instantiates the bound variables, outermost first, with `args`.
+    pub fn instantiate_with(&self, args: &[MonoType]) -> MonoType {
+        let (bounded, mono) = self.split();
+        let mut mapping = bounded.into_iter().zip(args.iter().cloned()).collect();
+        mono.instantiate_impl(&mut mapping)
+    }
+}
+
+fn alpha_eq_impl<'a>(
+    mono1: &'a MonoType,
+    mono2: &'a MonoType,
+    bounded1: &[&str],
+    bounded2: &[&str],
+    mapping: &mut HashMap<&'a str, &'a str>,
+    mapped: &mut HashSet<&'a str>,
+) -> bool {
+    match (mono1, mono2) {
+        (MonoType::TyVar(var1), MonoType::TyVar(var2)) => {
+            alpha_eq_var(var1, var2, bounded1, bounded2, mapping, mapped)
+        }
+        (MonoType::TyVarApp(app1), MonoType::TyVarApp(app2)) => {
+            alpha_eq_var(&app1.var, &app2.var, bounded1, bounded2, mapping, mapped)
+                && app1.monotypes.len() == app2.monotypes.len()
+                && app1
+                    .monotypes
+                    .iter()
+                    .zip(app2.monotypes.iter())
+                    .all(|(m1, m2)| alpha_eq_impl(m1, m2, bounded1, bounded2, mapping, mapped))
+        }
+        (MonoType::TyApp(app1), MonoType::TyApp(app2)) => {
+            app1.func == app2.func
+                && app1.monotypes.len() == app2.monotypes.len()
+                && app1
+                    .monotypes
+                    .iter()
+                    .zip(app2.monotypes.iter())
+                    .all(|(m1, m2)| alpha_eq_impl(m1, m2, bounded1, bounded2, mapping, mapped))
+        }
+        _ => false,
+    }
+}
+
+fn alpha_eq_var<'a>(
+    var1: &'a str,
+    var2: &'a str,
+    bounded1: &[&str],
+    bounded2: &[&str],
+    mapping: &mut HashMap<&'a str, &'a str>,
+    mapped: &mut HashSet<&'a str>,
+) -> bool {
+    let is_bound1 = bounded1.contains(&var1);
+    let is_bound2 = bounded2.contains(&var2);
+    match (is_bound1, is_bound2) {
+        (false, false) => var1 == var2,
+        (true, true) => match mapping.get(var1) {
+            Some(&target) => target == var2,
+            None if mapped.contains(var2) => false,
+            None => {
+                mapping.insert(var1, var2);
+                mapped.insert(var2);
+                true
+            }
+        },
+        _ => false,
     }
 }
 
@@ -233,6 +861,12 @@ impl GetFreeVars for PolyType {
     fn free_vars(&self) -> HashSet<&str> {
         match self {
             PolyType::Mono(mono) => mono.free_vars(),
+            PolyType::Qualified(qual) => qual
+                .preds
+                .iter()
+                .flat_map(Pred::free_vars)
+                .chain(qual.ty.free_vars())
+                .collect(),
             PolyType::TyQuantifier(quan) => quan
                 .ty
                 .free_vars()
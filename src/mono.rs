@@ -0,0 +1,288 @@
+//! Monomorphisation of typed ANF: every polymorphic `let` is replaced by one copy per
+//! instantiation it is used at.
+
+use std::collections::HashMap;
+
+use crate::anf::{Atom, Binding, Comp, Ident, Lambda, Term};
+use crate::lift::NameSupply;
+use crate::models::*;
+use crate::pretty::short_name;
+use crate::utils::*;
+
+#[derive(Debug, Clone)]
+pub struct Monomorphised {
+    pub term: Term,
+    /// Bindings that are still polymorphic: the unused ones, which are kept as they
+    /// are, and the copies made at types that have type variables in them.
+    pub polymorphic: Vec<String>,
+}
+
+/// Specialises the polymorphic bindings of `term`. The copy of `f` at `Int` is named
+/// `f_Int`, at `List Int -> Bool` it is `f_Fn_List_Int_Bool`.
+pub fn monomorphise(term: &Term) -> Monomorphised {
+    let mut monomorphiser = Monomorphiser {
+        names: NameSupply::new(&term.to_expr()),
+        polymorphic: vec![],
+    };
+    let term = monomorphiser.term(term.clone());
+
+    Monomorphised {
+        term,
+        polymorphic: monomorphiser.polymorphic,
+    }
+}
+
+struct Monomorphiser {
+    names: NameSupply,
+    polymorphic: Vec<String>,
+}
+
+impl Monomorphiser {
+    fn term(&mut self, term: Term) -> Term {
+        let (binding, body, recursive) = match term {
+            Term::Let(binding, body) => (binding, body, false),
+            Term::LetRec(binding, body) => (binding, body, true),
+            Term::Comp(comp) => return Term::Comp(self.comp(comp)),
+        };
+        // the uses of the binding are only known once its body is monomorphic
+        let body = self.term(*body);
+
+        let (bounded, mono) = binding.ty.split();
+        let insts = instantiations(&body, &binding.var);
+        if bounded.is_empty() || insts.is_empty() {
+            if !bounded.is_empty() {
+                self.polymorphic.push(binding.var.clone());
+            }
+            let value = self.comp(binding.value);
+            return rebind(recursive, Binding { value, ..binding }, body);
+        }
+
+        let mut copies = vec![];
+        let mut renaming = vec![];
+        for inst in insts {
+            let name = self.names.name(&specialised_name(&binding.var, &inst));
+            if inst.iter().any(|ty| !ty.free_vars().is_empty()) {
+                self.polymorphic.push(name.clone());
+            }
+
+            let mapping = bounded.iter().map(|b| b.to_string()).zip(inst.clone());
+            let s = Substitution::from_map(mapping.collect());
+            let mut names = HashMap::new();
+            if recursive {
+                names.insert(binding.var.clone(), name.clone());
+            }
+            let value = self.rename_comp(binding.value.clone().apply(&s), &names);
+            copies.push(Binding {
+                var: name.clone(),
+                ty: PolyType::Mono(mono.clone().apply(&s)),
+                value: self.comp(value),
+            });
+            renaming.push((inst, name));
+        }
+
+        let body = replace_instances(body, &binding.var, &renaming);
+        copies
+            .into_iter()
+            .rev()
+            .fold(body, |body, copy| rebind(recursive, copy, body))
+    }
+
+    fn comp(&mut self, comp: Comp) -> Comp {
+        match comp {
+            Comp::Abs(lambda) => Comp::Abs(Box::new(Lambda {
+                body: self.term(lambda.body),
+                ..*lambda
+            })),
+            comp => comp,
+        }
+    }
+
+    /// Gives fresh names to the binders of a copy, so that they stay unique.
+    fn rename_term(&mut self, term: Term, names: &HashMap<String, String>) -> Term {
+        match term {
+            Term::Let(binding, body) => {
+                let value = self.rename_comp(binding.value, names);
+                let var = self.names.fresh(&binding.var);
+                let mut names = names.clone();
+                names.insert(binding.var, var.clone());
+                let binding = Binding {
+                    var,
+                    ty: binding.ty,
+                    value,
+                };
+                Term::Let(binding, Box::new(self.rename_term(*body, &names)))
+            }
+            Term::LetRec(binding, body) => {
+                let var = self.names.fresh(&binding.var);
+                let mut names = names.clone();
+                names.insert(binding.var, var.clone());
+                let binding = Binding {
+                    var,
+                    ty: binding.ty,
+                    value: self.rename_comp(binding.value, &names),
+                };
+                Term::LetRec(binding, Box::new(self.rename_term(*body, &names)))
+            }
+            Term::Comp(comp) => Term::Comp(self.rename_comp(comp, names)),
+        }
+    }
+
+    fn rename_comp(&mut self, comp: Comp, names: &HashMap<String, String>) -> Comp {
+        match comp {
+            Comp::Atom(atom) => Comp::Atom(rename_atom(atom, names)),
+            Comp::App(fun, arg) => Comp::App(rename_atom(fun, names), rename_atom(arg, names)),
+            Comp::Abs(lambda) => {
+                let arg = self.names.fresh(&lambda.arg);
+                let mut names = names.clone();
+                names.insert(lambda.arg, arg.clone());
+                Comp::Abs(Box::new(Lambda {
+                    arg,
+                    arg_ty: lambda.arg_ty,
+                    body: self.rename_term(lambda.body, &names),
+                }))
+            }
+        }
+    }
+}
+
+fn rename_atom(atom: Atom, names: &HashMap<String, String>) -> Atom {
+    match atom {
+        Atom::Var(ident) => match names.get(&ident.name) {
+            Some(name) => Atom::Var(Ident {
+                name: name.clone(),
+                ..ident
+            }),
+            None => Atom::Var(ident),
+        },
+        atom => atom,
+    }
+}
+
+fn rebind(recursive: bool, binding: Binding, body: Term) -> Term {
+    if recursive {
+        Term::LetRec(binding, Box::new(body))
+    } else {
+        Term::Let(binding, Box::new(body))
+    }
+}
+
+/// The distinct instantiations of `var` in `term`, in order of first use.
+fn instantiations(term: &Term, var: &str) -> Vec<Vec<MonoType>> {
+    fn collect(term: &Term, var: &str, insts: &mut Vec<Vec<MonoType>>) {
+        let comp = match term {
+            Term::Let(binding, body) | Term::LetRec(binding, body) => {
+                collect_comp(&binding.value, var, insts);
+                return collect(body, var, insts);
+            }
+            Term::Comp(comp) => comp,
+        };
+        collect_comp(comp, var, insts)
+    }
+
+    fn collect_comp(comp: &Comp, var: &str, insts: &mut Vec<Vec<MonoType>>) {
+        let atoms = match comp {
+            Comp::Atom(atom) => vec![atom],
+            Comp::App(fun, arg) => vec![fun, arg],
+            Comp::Abs(lambda) => return collect(&lambda.body, var, insts),
+        };
+        for atom in atoms {
+            if let Atom::Var(ident) = atom {
+                if ident.name == var && !insts.contains(&ident.inst) {
+                    insts.push(ident.inst.clone());
+                }
+            }
+        }
+    }
+
+    let mut insts = vec![];
+    collect(term, var, &mut insts);
+    insts
+}
+
+/// Replaces the uses of `var` by the copies made for their instantiation.
+fn replace_instances(term: Term, var: &str, copies: &[(Vec<MonoType>, String)]) -> Term {
+    let replace_comp = |comp: Comp| -> Comp {
+        let replace = |atom: Atom| match atom {
+            Atom::Var(ident) if ident.name == var => {
+                let (_, name) = copies.iter().find(|(inst, _)| *inst == ident.inst).unwrap();
+                Atom::Var(Ident {
+                    name: name.clone(),
+                    inst: vec![],
+                })
+            }
+            atom => atom,
+        };
+        match comp {
+            Comp::Atom(atom) => Comp::Atom(replace(atom)),
+            Comp::App(fun, arg) => Comp::App(replace(fun), replace(arg)),
+            Comp::Abs(lambda) => Comp::Abs(Box::new(Lambda {
+                body: replace_instances(lambda.body, var, copies),
+                ..*lambda
+            })),
+        }
+    };
+
+    match term {
+        Term::Let(binding, body) => {
+            let value = replace_comp(binding.value);
+            let body = replace_instances(*body, var, copies);
+            Term::Let(Binding { value, ..binding }, Box::new(body))
+        }
+        Term::LetRec(binding, body) => {
+            let value = replace_comp(binding.value);
+            let body = replace_instances(*body, var, copies);
+            Term::LetRec(Binding { value, ..binding }, Box::new(body))
+        }
+        Term::Comp(comp) => Term::Comp(replace_comp(comp)),
+    }
+}
+
+/// `len_Int_a` for `len` at `Int` and a type left unknown: the free variables of the
+/// instantiation are named `a, b, ..` in order, so that the name doesn't depend on the
+/// fresh variables inference happened to pick.
+fn specialised_name(var: &str, inst: &[MonoType]) -> String {
+    let mut vars: Vec<&str> = vec![];
+    for var in inst.iter().flat_map(MonoType::type_vars) {
+        if !vars.contains(&var) {
+            vars.push(var);
+        }
+    }
+    let names = vars
+        .into_iter()
+        .enumerate()
+        .map(|(i, var)| (var, short_name(i)))
+        .collect();
+
+    let mut name = var.to_string();
+    for ty in inst {
+        name.push('_');
+        mangle(ty, &names, &mut name);
+    }
+
+    name
+}
+
+/// Writes a type as a part of an identifier, arrows as `Fn` followed by both sides.
+fn mangle(ty: &MonoType, names: &HashMap<&str, String>, out: &mut String) {
+    match ty {
+        MonoType::TyVar(var) => out.push_str(names.get(var.as_str()).unwrap_or(var)),
+        MonoType::TyApp(app) => {
+            if app.func == "->" {
+                out.push_str("Fn");
+            } else {
+                out.push_str(&app.func.replace('.', "_"));
+            }
+            for arg in app.monotypes.iter() {
+                out.push('_');
+                mangle(arg, names, out);
+            }
+        }
+        MonoType::TyVarApp(app) => {
+            out.push_str(names.get(app.var.as_str()).unwrap_or(&app.var));
+            for arg in app.monotypes.iter() {
+                out.push('_');
+                mangle(arg, names, out);
+            }
+        }
+    }
+}
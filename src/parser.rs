@@ -1,7 +1,9 @@
 use std::iter::Peekable;
 
+use crate::kinds::Kind;
 use crate::lex::*;
 use crate::models::*;
+use crate::system_f::FType;
 
 use anyhow::Context;
 
@@ -47,63 +49,377 @@ impl<'c> Parser<'c> {
         }
     }
 
+    /// Parses a whole expression, rejecting any trailing tokens.
+    ///
+    /// ```text
+    /// e ::= \x .. -> e | let x = e in e | case e of C x .. -> e ; .. | i
+    /// i ::= i op i | a a ..
+    /// a ::= lit | x | (op) | (e)
+    /// ```
     pub fn parse(&mut self) -> anyhow::Result<Expr> {
+        let expr = self.parse_expr()?;
+        self.expect_eof()?;
+
+        Ok(expr)
+    }
+
+    /// Parses declarations followed by an expression, rejecting any trailing tokens.
+    ///
+    /// ```text
+    /// p ::= d in p | e
+    /// d ::= class (ctx =>)? C a where (x : t ; ..)?
+    ///     | instance (ctx =>)? C t where (x = e ; ..)?
+    ///     | default C t
+    ///     | data T a .. (= (forall b .. .)? C t .. | ..)?
+    ///     | newtype T a .. = C t
+    ///     | type T a .. = t
+    /// ```
+    pub fn parse_program(&mut self) -> anyhow::Result<Program> {
+        let mut decls = vec![];
+        while let Some(token) = self.lexer.next_if(|t| {
+            matches!(
+                t.kind,
+                TokenKind::Class
+                    | TokenKind::Instance
+                    | TokenKind::Default
+                    | TokenKind::Data
+                    | TokenKind::Newtype
+                    | TokenKind::Type
+            )
+        }) {
+            let decl = match token.kind {
+                TokenKind::Class => Decl::Class(self.parse_class(&token)?),
+                TokenKind::Instance => Decl::Instance(self.parse_instance(&token)?),
+                TokenKind::Data | TokenKind::Newtype => Decl::Data(self.parse_data(&token)?),
+                TokenKind::Type => Decl::Type(self.parse_synonym(&token)?),
+                _ => Decl::Default(self.parse_pred()?),
+            };
+            decls.push(decl);
+
+            let token_in = expected_next!(self.lexer, In, token.loc);
+            expected!(token_in, In);
+        }
+        let body = self.parse_expr()?;
+        self.expect_eof()?;
+
+        Ok(Program { decls, body })
+    }
+
+    /// ```text
+    /// data T a .. = forall b .. . C t .. | ..
+    /// ```
+    fn parse_data(&mut self, token_data: &Token) -> anyhow::Result<DataDecl> {
+        let (name, params) = self.parse_type_head(token_data)?;
+
+        let mut constructors = vec![];
+        if self.lexer.next_if(|t| t.kind == TokenKind::Eq).is_some() {
+            loop {
+                let exists = self.parse_exists(&params)?;
+                let con = expected_next!(self.lexer, Con, token_data.loc);
+                expected!(con, Con);
+                let mut fields = vec![];
+                while self.lexer.peek().is_some_and(|t| {
+                    matches!(t.kind, TokenKind::Var | TokenKind::OpenP) || is_type_func(t.kind)
+                }) {
+                    fields.push(self.parse_type_atom()?);
+                }
+                constructors.push(ConDecl {
+                    name: con.value,
+                    exists,
+                    fields,
+                });
+
+                if self
+                    .lexer
+                    .next_if(|t| t.kind == TokenKind::Op && t.value == "|")
+                    .is_none()
+                {
+                    break;
+                }
+            }
+        }
+
+        let newtype = token_data.kind == TokenKind::Newtype;
+        if newtype && !matches!(constructors.as_slice(), [con] if con.fields.len() == 1) {
+            anyhow::bail!(
+                "newtype {name} must have exactly one constructor with one field in {}",
+                token_data.loc
+            );
+        }
+        if newtype && !constructors[0].exists.is_empty() {
+            anyhow::bail!(
+                "newtype {name} can't have existential variables in {}",
+                token_data.loc
+            );
+        }
+
+        Ok(DataDecl {
+            name,
+            params,
+            constructors,
+            newtype,
+        })
+    }
+
+    /// `forall b .. .` before a constructor, its existential variables, distinct from
+    /// each other and from the parameters of the type.
+    fn parse_exists(&mut self, params: &[String]) -> anyhow::Result<Vec<String>> {
+        let Some(token_forall) = self.lexer.next_if(|t| t.kind == TokenKind::Forall) else {
+            return Ok(vec![]);
+        };
+        let mut exists: Vec<String> = vec![];
+        while let Some(var) = self.lexer.next_if(|t| t.kind == TokenKind::Var) {
+            if exists.contains(&var.value) || params.contains(&var.value) {
+                anyhow::bail!("`{}` is bound twice in {}", var.value, var.loc);
+            }
+            exists.push(var.value);
+        }
+        if exists.is_empty() {
+            anyhow::bail!(
+                "expected type variable after forall in {}",
+                token_forall.loc
+            );
+        }
+        let dot = expected_next!(self.lexer, Dot, token_forall.loc);
+        expected!(dot, Dot);
+
+        Ok(exists)
+    }
+
+    fn parse_synonym(&mut self, token_type: &Token) -> anyhow::Result<SynonymDecl> {
+        let (name, params) = self.parse_type_head(token_type)?;
+        let eq = expected_next!(self.lexer, Eq, token_type.loc);
+        expected!(eq, Eq);
+        let ty = self
+            .parse_mono_type()
+            .with_context(|| format!("expected the type `{name}` stands for"))?;
+
+        Ok(SynonymDecl { name, params, ty })
+    }
+
+    /// `T a ..`, the name of a declared type and its distinct parameters.
+    fn parse_type_head(&mut self, token: &Token) -> anyhow::Result<(String, Vec<String>)> {
+        let name = expected_next!(self.lexer, Con, token.loc);
+        expected!(name, Con);
+        let mut params = vec![];
+        while let Some(var) = self.lexer.next_if(|t| t.kind == TokenKind::Var) {
+            if params.contains(&var.value) {
+                anyhow::bail!(
+                    "`{}` is a parameter of {} twice in {}",
+                    var.value,
+                    name.value,
+                    var.loc
+                );
+            }
+            params.push(var.value);
+        }
+
+        Ok((name.value, params))
+    }
+
+    fn parse_class(&mut self, token_class: &Token) -> anyhow::Result<ClassDecl> {
+        let context = self.parse_context()?;
+        let head = self.parse_pred()?;
+        let MonoType::TyVar(var) = head.ty else {
+            anyhow::bail!(
+                "expected a type variable after {} in {}",
+                head.class,
+                token_class.loc
+            );
+        };
+        let mut supers = vec![];
+        for pred in context {
+            if pred.ty != MonoType::TyVar(var.clone()) {
+                anyhow::bail!(
+                    "superclass `{pred}` must constrain `{var}` in {}",
+                    token_class.loc
+                );
+            }
+            supers.push(pred.class);
+        }
+
+        let methods = self.parse_where(token_class, |parser| {
+            let colon = expected_next!(parser.lexer, Colon, token_class.loc);
+            expected!(colon, Colon);
+            parser.parse_mono_type()
+        })?;
+
+        Ok(ClassDecl {
+            name: head.class,
+            supers,
+            var,
+            methods,
+        })
+    }
+
+    fn parse_instance(&mut self, token_instance: &Token) -> anyhow::Result<InstanceDecl> {
+        let context = self.parse_context()?;
+        let head = self.parse_pred()?;
+        let methods = self.parse_where(token_instance, |parser| {
+            let eq = expected_next!(parser.lexer, Eq, token_instance.loc);
+            expected!(eq, Eq);
+            parser.parse_expr()
+        })?;
+
+        Ok(InstanceDecl {
+            context,
+            head,
+            methods,
+        })
+    }
+
+    /// Parses `where` and the `;` separated methods after it, `method` parses what
+    /// follows the name of each.
+    fn parse_where<T>(
+        &mut self,
+        token_decl: &Token,
+        mut method: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Vec<(String, T)>> {
+        let token_where = expected_next!(self.lexer, Where, token_decl.loc);
+        expected!(token_where, Where);
+
+        let mut methods = vec![];
+        if self.lexer.peek().is_some_and(|t| t.kind == TokenKind::In) {
+            return Ok(methods);
+        }
+        loop {
+            let name = self.parse_binder()?;
+            methods.push((name, method(self)?));
+            if self.lexer.next_if(|t| t.kind == TokenKind::Semi).is_none() {
+                return Ok(methods);
+            }
+        }
+    }
+
+    /// `x` or `(op)`
+    fn parse_binder(&mut self) -> anyhow::Result<String> {
         let token = next!(self.lexer);
         match token.kind {
-            TokenKind::LitBool => Ok(Expr::Lit(Literal::Bool)),
-            TokenKind::LitInt => Ok(Expr::Lit(Literal::Int)),
-            TokenKind::Var => Ok(Expr::Var(token.value)),
-            TokenKind::OpenP => self.parse_rest_expr(),
-            _ => {
-                anyhow::bail!("Unexpected tokenkind {:?} in {}", token.kind, token.loc)
+            TokenKind::Var => Ok(token.value),
+            TokenKind::OpenP => {
+                let op = expected_next!(self.lexer, Op, token.loc);
+                expected!(op, Op);
+                let closp = expected_next!(self.lexer, ClosP, op.loc);
+                expected!(closp, ClosP);
+
+                Ok(op.value)
             }
+            _ => Err(unexpected(&token)),
         }
     }
 
-    fn parse_rest_expr(&mut self) -> anyhow::Result<Expr> {
+    fn parse_expr(&mut self) -> anyhow::Result<Expr> {
         let token = match self.lexer.peek() {
             Some(t) => t,
             None => anyhow::bail!("Unexpected EOF"),
         };
-        let token_loc = token.loc;
 
-        let res = match token.kind {
-            TokenKind::Let => self.parse_let(),
-            TokenKind::Var => self.parse_app(),
+        match token.kind {
+            TokenKind::Let | TokenKind::LetRec => self.parse_let(),
             TokenKind::BackSlash => self.parse_abs(),
-            TokenKind::OpenP => {
-                let fun = self.parse().context("expected function expr")?;
-                self.parse_app_with_fun(fun)
-            }
-            _ => anyhow::bail!("Unexpected tokenkind {:?} in {}", token.kind, token.loc),
-        };
+            TokenKind::Case => self.parse_case(),
+            _ => self.parse_infix(0),
+        }
+    }
 
-        let closp = expected_next!(self.lexer, ClosP, token_loc);
-        expected!(closp, ClosP);
+    /// Parses applications separated by operators binding at least as tight as
+    /// `min_prec`.
+    fn parse_infix(&mut self, min_prec: u8) -> anyhow::Result<Expr> {
+        let mut lhs = self.parse_app()?;
+        while let Some(op) = self
+            .lexer
+            .next_if(|t| t.kind == TokenKind::Op && !is_prefix(t) && fixity(&t.value).0 >= min_prec)
+        {
+            let (prec, assoc) = fixity(&op.value);
+            let rhs = match assoc {
+                Assoc::Left => self.parse_infix(prec + 1),
+                Assoc::Right => self.parse_infix(prec),
+            }
+            .with_context(|| format!("expected an operand of `{}` in {}", op.value, op.loc))?;
+            let fun = Expr::App(Box::new(AppExpr {
+                fun: Expr::Var(op.value),
+                arg: lhs,
+            }));
+            lhs = Expr::App(Box::new(AppExpr { fun, arg: rhs }));
+        }
 
-        res
+        Ok(lhs)
     }
 
     fn parse_let(&mut self) -> anyhow::Result<Expr> {
         let token_let = next!(self.lexer);
-        let var = expected_next!(self.lexer, Var, token_let.loc);
-        expected!(var, Var);
+        let var = self.parse_binder()?;
 
-        let token_eq = expected_next!(self.lexer, Eq, var.loc);
+        let token_eq = expected_next!(self.lexer, Eq, token_let.loc);
         expected!(token_eq, Eq);
 
-        let vexpr = self.parse().context("expected vexpr")?;
+        let vexpr = self.parse_expr().context("expected vexpr")?;
 
         let token_in = expected_next!(self.lexer, In, token_eq.loc);
         expected!(token_in, In);
 
-        let aexpr = self.parse().context("expected aexpr")?;
+        let aexpr = self.parse_expr().context("expected aexpr")?;
 
-        Ok(Expr::Let(Box::new(LetExpr {
-            var: var.value,
-            vexpr,
-            aexpr,
-        })))
+        let let_expr = Box::new(LetExpr { var, vexpr, aexpr });
+        if token_let.kind == TokenKind::LetRec {
+            Ok(Expr::LetRec(let_expr))
+        } else {
+            Ok(Expr::Let(let_expr))
+        }
+    }
+
+    /// The arms extend as far as possible, a `case` in the body of an arm takes the
+    /// arms after it unless it is in parentheses.
+    fn parse_case(&mut self) -> anyhow::Result<Expr> {
+        let token_case = next!(self.lexer);
+        let expr = self
+            .parse_expr()
+            .context("expected the scrutinee of case")?;
+        let token_of = expected_next!(self.lexer, Of, token_case.loc);
+        expected!(token_of, Of);
+
+        let mut arms = vec![];
+        loop {
+            let con = next!(self.lexer);
+            let is_con_name = match con.kind {
+                TokenKind::Con => true,
+                TokenKind::QualName => is_con(con.value.rsplit('.').next().unwrap()),
+                _ => false,
+            };
+            if !is_con_name {
+                anyhow::bail!("expected a constructor pattern in {}", con.loc);
+            }
+            let mut vars: Vec<String> = vec![];
+            while let Some(var) = self.lexer.next_if(|t| t.kind == TokenKind::Var) {
+                if var.value != "_" && vars.contains(&var.value) {
+                    anyhow::bail!("`{}` is bound twice in {}", var.value, var.loc);
+                }
+                vars.push(var.value);
+            }
+            let arrow = expected_next!(self.lexer, Arrow, con.loc);
+            expected!(arrow, Arrow);
+            let body = self
+                .parse_expr()
+                .with_context(|| format!("expected the body of the arm `{}`", con.value))?;
+            arms.push(CaseArm {
+                con: con.value,
+                vars,
+                body,
+            });
+
+            // a `;` followed by anything else separates the methods of an instance
+            let mut ahead = self.lexer.clone();
+            if ahead.next_if(|t| t.kind == TokenKind::Semi).is_none()
+                || !ahead
+                    .peek()
+                    .is_some_and(|t| matches!(t.kind, TokenKind::Con | TokenKind::QualName))
+            {
+                break;
+            }
+            self.lexer = ahead;
+        }
+
+        Ok(Expr::Case(Box::new(CaseExpr { expr, arms })))
     }
 
     fn parse_abs(&mut self) -> anyhow::Result<Expr> {
@@ -111,25 +427,454 @@ impl<'c> Parser<'c> {
         let arg = expected_next!(self.lexer, Var, token_backslash.loc);
         expected!(arg, Var);
 
+        let mut args = vec![arg.value];
+        while let Some(arg) = self.lexer.next_if(|t| t.kind == TokenKind::Var) {
+            args.push(arg.value);
+        }
+
         let arrow = expected_next!(self.lexer, Arrow, arg.loc);
         expected!(arrow, Arrow);
 
-        let body = self.parse().context("expected function body")?;
+        let body = self.parse_expr().context("expected function body")?;
 
-        Ok(Expr::Abs(Box::new(AbsExpr {
-            arg: arg.value,
-            body,
-        })))
+        Ok(args
+            .into_iter()
+            .rev()
+            .fold(body, |body, arg| Expr::Abs(Box::new(AbsExpr { arg, body }))))
     }
 
-    fn parse_app_with_fun(&mut self, fun: Expr) -> anyhow::Result<Expr> {
-        let arg = self.parse().context("expected function arg")?;
+    fn parse_app(&mut self) -> anyhow::Result<Expr> {
+        let mut fun = match self.lexer.next_if(is_negation) {
+            Some(minus) => self.parse_negative(minus)?,
+            None => self.parse_atom().context("expected function expr")?,
+        };
+        while self
+            .lexer
+            .peek()
+            .is_some_and(|t| starts_atom(t.kind) || is_prefix(t))
+        {
+            let arg = self.parse_atom().context("expected function arg")?;
+            fun = Expr::App(Box::new(AppExpr { fun, arg }));
+        }
 
-        Ok(Expr::App(Box::new(AppExpr { fun, arg })))
+        Ok(fun)
     }
 
-    fn parse_app(&mut self) -> anyhow::Result<Expr> {
-        let fun = self.parse().context("expected function expr")?;
-        self.parse_app_with_fun(fun)
+    /// A negative literal, `-` is only a prefix at the start of an operand so that
+    /// `x -1` subtracts.
+    fn parse_negative(&mut self, minus: Token) -> anyhow::Result<Expr> {
+        let mut token = match self
+            .lexer
+            .next_if(|t| matches!(t.kind, TokenKind::LitInt | TokenKind::LitFloat))
+        {
+            Some(token) => token,
+            None => anyhow::bail!("expected a number after `-` in {}", minus.loc),
+        };
+        token.value.insert(0, '-');
+        token.loc = minus.loc;
+
+        number(token)
+    }
+
+    fn parse_atom(&mut self) -> anyhow::Result<Expr> {
+        let token = next!(self.lexer);
+        match token.kind {
+            TokenKind::LitBool => Ok(Expr::Lit(Literal::Bool(token.value == "true"))),
+            TokenKind::LitInt | TokenKind::LitFloat => number(token),
+            TokenKind::LitChar => Ok(Expr::Lit(Literal::Char(
+                token.value.chars().next().unwrap(),
+            ))),
+            TokenKind::LitString => Ok(Expr::Lit(Literal::Str(token.value))),
+            TokenKind::Var => Ok(Expr::Var(token.value)),
+            TokenKind::Con => Ok(Expr::Con(token.value)),
+            TokenKind::QualName if is_con(token.value.rsplit('.').next().unwrap()) => {
+                Ok(Expr::Con(token.value))
+            }
+            TokenKind::QualName => Ok(Expr::Var(token.value)),
+            TokenKind::Op if is_prefix(&token) => {
+                let arg = self
+                    .parse_atom()
+                    .with_context(|| format!("expected an operand of `!` in {}", token.loc))?;
+                Ok(Expr::App(Box::new(AppExpr {
+                    fun: Expr::Var(token.value),
+                    arg,
+                })))
+            }
+            TokenKind::OpenP => {
+                if self.lexer.next_if(|t| t.kind == TokenKind::ClosP).is_some() {
+                    return Ok(Expr::Lit(Literal::Unit));
+                }
+                // `(-)` is a section but `(-1)` or `(!r)` an expression
+                let mut ahead = self.lexer.clone();
+                let section = ahead.next_if(|t| t.kind == TokenKind::Op).filter(|op| {
+                    !is_prefix(op) && !is_negation(op)
+                        || ahead.peek().is_some_and(|t| t.kind == TokenKind::ClosP)
+                });
+                if let Some(op) = section {
+                    self.lexer = ahead;
+                    let closp = expected_next!(self.lexer, ClosP, op.loc);
+                    expected!(closp, ClosP);
+                    return Ok(Expr::Var(op.value));
+                }
+                let mut expr = self.parse_expr()?;
+                if self.lexer.next_if(|t| t.kind == TokenKind::Colon).is_some() {
+                    let ty = self.parse_rank_type().context("expected annotation")?;
+                    expr = Expr::Ann(Box::new(AnnExpr { expr, ty }));
+                }
+                let closp = expected_next!(self.lexer, ClosP, token.loc);
+                expected!(closp, ClosP);
+
+                Ok(expr)
+            }
+            _ => Err(unexpected(&token)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// The precedence and associativity of an operator, the higher the tighter it binds.
+pub fn fixity(op: &str) -> (u8, Assoc) {
+    match op {
+        ":=" => (1, Assoc::Right),
+        "||" => (2, Assoc::Right),
+        "&&" => (3, Assoc::Right),
+        "==" | "/=" | "<" | "<=" | ">" | ">=" => (4, Assoc::Left),
+        "+" | "-" => (6, Assoc::Left),
+        "*" | "/" => (7, Assoc::Left),
+        "^" => (8, Assoc::Right),
+        _ => (9, Assoc::Left),
+    }
+}
+
+/// `!`, which dereferences the atom right after it, is the only prefix operator.
+fn is_prefix(token: &Token) -> bool {
+    token.kind == TokenKind::Op && token.value == "!"
+}
+
+fn is_negation(token: &Token) -> bool {
+    token.kind == TokenKind::Op && token.value == "-"
+}
+
+/// An int or float literal, possibly negative.
+fn number(token: Token) -> anyhow::Result<Expr> {
+    if token.kind == TokenKind::LitInt {
+        let int = token
+            .value
+            .parse()
+            .with_context(|| format!("invalid int literal in {}", token.loc))?;
+        Ok(Expr::Lit(Literal::Int(int)))
+    } else {
+        let float = token
+            .value
+            .parse()
+            .with_context(|| format!("invalid float literal in {}", token.loc))?;
+        Ok(Expr::Lit(Literal::Float(float)))
     }
 }
+
+fn starts_atom(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::LitBool
+            | TokenKind::LitInt
+            | TokenKind::LitFloat
+            | TokenKind::LitChar
+            | TokenKind::LitString
+            | TokenKind::Var
+            | TokenKind::Con
+            | TokenKind::QualName
+            | TokenKind::OpenP
+    )
+}
+
+impl<'c> Parser<'c> {
+    /// ```text
+    /// s ::= forall a .. . s | ctx => t | t
+    /// ```
+    pub fn parse_poly_type(&mut self) -> anyhow::Result<PolyType> {
+        let token_forall = match self.lexer.next_if(|t| t.kind == TokenKind::Forall) {
+            Some(t) => t,
+            None => {
+                let preds = self.parse_context()?;
+                let ty = self.parse_mono_type()?;
+                return Ok(if preds.is_empty() {
+                    PolyType::Mono(ty)
+                } else {
+                    PolyType::Qualified(QualType { preds, ty })
+                });
+            }
+        };
+
+        let mut bounded = vec![];
+        while let Some(var) = self.lexer.next_if(|t| t.kind == TokenKind::Var) {
+            bounded.push(var.value);
+        }
+        if bounded.is_empty() {
+            anyhow::bail!(
+                "expected type variable after forall in {}",
+                token_forall.loc
+            );
+        }
+
+        let dot = expected_next!(self.lexer, Dot, token_forall.loc);
+        expected!(dot, Dot);
+
+        let ty = self.parse_poly_type().context("expected quantified type")?;
+
+        Ok(bounded.into_iter().rev().fold(ty, |acc, bounded| {
+            PolyType::TyQuantifier(TypeQuantifier {
+                bounded,
+                ty: Box::new(acc),
+            })
+        }))
+    }
+
+    /// ```text
+    /// k ::= * | k -> k | (k)
+    /// ```
+    pub fn parse_kind(&mut self) -> anyhow::Result<Kind> {
+        let token = next!(self.lexer);
+        let from = match token.kind {
+            TokenKind::Op if token.value == "*" => Kind::Star,
+            TokenKind::OpenP => {
+                let kind = self.parse_kind()?;
+                let closp = expected_next!(self.lexer, ClosP, token.loc);
+                expected!(closp, ClosP);
+                kind
+            }
+            _ => return Err(unexpected(&token)),
+        };
+        if self.lexer.next_if(|t| t.kind == TokenKind::Arrow).is_none() {
+            return Ok(from);
+        }
+        let to = self.parse_kind().context("expected result kind")?;
+
+        Ok(Kind::arrow(from, to))
+    }
+
+    /// A type with quantifiers anywhere, which need parentheses left of an arrow or as
+    /// arguments.
+    ///
+    /// ```text
+    /// r ::= forall a .. . r | r_app -> r | r_app
+    /// ```
+    pub fn parse_rank_type(&mut self) -> anyhow::Result<FType> {
+        let Some(token_forall) = self.lexer.next_if(|t| t.kind == TokenKind::Forall) else {
+            let from = self.parse_rank_app()?;
+            if self.lexer.next_if(|t| t.kind == TokenKind::Arrow).is_none() {
+                return Ok(from);
+            }
+            let to = self.parse_rank_type().context("expected result type")?;
+            return Ok(FType::arrow(from, to));
+        };
+
+        let mut bounded = vec![];
+        while let Some(var) = self.lexer.next_if(|t| t.kind == TokenKind::Var) {
+            bounded.push(var.value);
+        }
+        if bounded.is_empty() {
+            anyhow::bail!(
+                "expected type variable after forall in {}",
+                token_forall.loc
+            );
+        }
+
+        let dot = expected_next!(self.lexer, Dot, token_forall.loc);
+        expected!(dot, Dot);
+
+        let ty = self.parse_rank_type().context("expected quantified type")?;
+
+        Ok(bounded
+            .into_iter()
+            .rev()
+            .fold(ty, |acc, bounded| FType::Forall(bounded, Box::new(acc))))
+    }
+
+    fn parse_rank_app(&mut self) -> anyhow::Result<FType> {
+        let head = self.parse_rank_atom()?;
+        if !matches!(&head, FType::Var(_))
+            && !matches!(&head, FType::Con(_, args) if args.is_empty())
+        {
+            return Ok(head);
+        }
+
+        let mut args = vec![];
+        while let Some(t) = self.lexer.peek() {
+            if t.kind != TokenKind::Var && t.kind != TokenKind::OpenP && !is_type_func(t.kind) {
+                break;
+            }
+            args.push(self.parse_rank_atom()?);
+        }
+
+        Ok(head.applied_to(args))
+    }
+
+    fn parse_rank_atom(&mut self) -> anyhow::Result<FType> {
+        let token = next!(self.lexer);
+        match token.kind {
+            TokenKind::Var => Ok(FType::Var(token.value)),
+            TokenKind::Con | TokenKind::QualName => Ok(FType::Con(token.value, vec![])),
+            TokenKind::OpenP if self.parse_arrow_con(&token)? => {
+                Ok(FType::Con("->".to_string(), vec![]))
+            }
+            TokenKind::OpenP => {
+                let ty = self.parse_rank_type()?;
+                let closp = expected_next!(self.lexer, ClosP, token.loc);
+                expected!(closp, ClosP);
+
+                Ok(ty)
+            }
+            _ => Err(unexpected(&token)),
+        }
+    }
+
+    /// Parses the rest of `(->)`, the function type constructor on its own, if it comes
+    /// after `open`.
+    fn parse_arrow_con(&mut self, open: &Token) -> anyhow::Result<bool> {
+        if self.lexer.next_if(|t| t.kind == TokenKind::Arrow).is_none() {
+            return Ok(false);
+        }
+        let closp = expected_next!(self.lexer, ClosP, open.loc);
+        expected!(closp, ClosP);
+
+        Ok(true)
+    }
+
+    pub fn parse_mono_type(&mut self) -> anyhow::Result<MonoType> {
+        let from = self.parse_type_app()?;
+        if self.lexer.next_if(|t| t.kind == TokenKind::Arrow).is_none() {
+            return Ok(from);
+        }
+        let to = self.parse_mono_type().context("expected result type")?;
+
+        Ok(MonoType::arrow(from, to))
+    }
+
+    /// Parses `C t =>` or `(C t, ..) =>` if it comes next, nothing otherwise.
+    fn parse_context(&mut self) -> anyhow::Result<Vec<Pred>> {
+        // a context starts like a type, only the `=>` tells them apart
+        let mut ahead = Parser {
+            lexer: self.lexer.clone(),
+        };
+        let preds = match ahead.lexer.next_if(|t| t.kind == TokenKind::OpenP) {
+            Some(_) => {
+                let mut preds = vec![];
+                loop {
+                    match ahead.parse_pred() {
+                        Ok(pred) => preds.push(pred),
+                        Err(_) => return Ok(vec![]),
+                    }
+                    if ahead
+                        .lexer
+                        .next_if(|t| t.kind == TokenKind::Comma)
+                        .is_none()
+                    {
+                        break;
+                    }
+                }
+                if ahead
+                    .lexer
+                    .next_if(|t| t.kind == TokenKind::ClosP)
+                    .is_none()
+                {
+                    return Ok(vec![]);
+                }
+                preds
+            }
+            None => match ahead.parse_pred() {
+                Ok(pred) => vec![pred],
+                Err(_) => return Ok(vec![]),
+            },
+        };
+        if ahead
+            .lexer
+            .next_if(|t| t.kind == TokenKind::FatArrow)
+            .is_none()
+        {
+            return Ok(vec![]);
+        }
+        self.lexer = ahead.lexer;
+
+        Ok(preds)
+    }
+
+    /// `C t`
+    fn parse_pred(&mut self) -> anyhow::Result<Pred> {
+        let class = next!(self.lexer);
+        if !is_type_func(class.kind) {
+            return Err(unexpected(&class));
+        }
+        let ty = self.parse_type_atom()?;
+
+        Ok(Pred {
+            class: class.value,
+            ty,
+        })
+    }
+
+    pub fn expect_eof(&mut self) -> anyhow::Result<()> {
+        match self.lexer.next() {
+            Some(t) => Err(unexpected(&t)),
+            None => Ok(()),
+        }
+    }
+
+    /// `C τ1 .. τn`, or `f τ1 .. τn` for a type variable of a higher kind.
+    fn parse_type_app(&mut self) -> anyhow::Result<MonoType> {
+        let head = self.parse_type_atom()?;
+        if !matches!(&head, MonoType::TyVar(_))
+            && !matches!(&head, MonoType::TyApp(app) if app.monotypes.is_empty())
+        {
+            return Ok(head);
+        }
+
+        let mut monotypes = vec![];
+        while let Some(t) = self.lexer.peek() {
+            if t.kind != TokenKind::Var && t.kind != TokenKind::OpenP && !is_type_func(t.kind) {
+                break;
+            }
+            monotypes.push(self.parse_type_atom()?);
+        }
+
+        Ok(head.applied_to(monotypes))
+    }
+
+    fn parse_type_atom(&mut self) -> anyhow::Result<MonoType> {
+        let token = next!(self.lexer);
+        match token.kind {
+            TokenKind::Var => Ok(MonoType::TyVar(token.value)),
+            TokenKind::Con | TokenKind::QualName => Ok(MonoType::TyApp(TypeApp {
+                func: token.value,
+                monotypes: vec![],
+            })),
+            TokenKind::OpenP if self.parse_arrow_con(&token)? => Ok(MonoType::TyApp(TypeApp {
+                func: "->".to_string(),
+                monotypes: vec![],
+            })),
+            TokenKind::OpenP => {
+                let ty = self.parse_mono_type()?;
+                let closp = expected_next!(self.lexer, ClosP, token.loc);
+                expected!(closp, ClosP);
+
+                Ok(ty)
+            }
+            _ => Err(unexpected(&token)),
+        }
+    }
+}
+
+fn unexpected(token: &Token) -> anyhow::Error {
+    match token.kind {
+        TokenKind::Error => anyhow::anyhow!("{} in {}", token.value, token.loc),
+        _ => anyhow::anyhow!("Unexpected tokenkind {:?} in {}", token.kind, token.loc),
+    }
+}
+
+fn is_type_func(kind: TokenKind) -> bool {
+    matches!(kind, TokenKind::Con | TokenKind::QualName)
+}
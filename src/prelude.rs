@@ -0,0 +1,261 @@
+use std::{cell::RefCell, rc::Rc};
+
+use std::collections::HashMap;
+
+use crate::eval::{force, Env, Value};
+use crate::kinds::KindEnv;
+use crate::models::*;
+
+/// A primitive function with its type and its implementation.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub ty: &'static str,
+    pub arity: usize,
+    pub fun: fn(&[Value]) -> anyhow::Result<Value>,
+}
+
+impl Builtin {
+    /// Whether the builtin makes, reads or writes a `ref`, which only makes sense when
+    /// evaluation happens in program order.
+    pub fn is_effectful(&self) -> bool {
+        matches!(self.name, "ref" | "!" | ":=")
+    }
+}
+
+pub static BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "list",
+        ty: "forall a. a -> List a",
+        arity: 1,
+        fun: list,
+    },
+    Builtin {
+        name: "inc",
+        ty: "Int -> Int",
+        arity: 1,
+        fun: inc,
+    },
+    Builtin {
+        name: "dec",
+        ty: "Int -> Int",
+        arity: 1,
+        fun: dec,
+    },
+    Builtin {
+        name: "isNull",
+        ty: "forall a. a -> Bool",
+        arity: 1,
+        fun: is_null,
+    },
+    Builtin {
+        name: "add",
+        ty: "Int -> Int -> Int",
+        arity: 2,
+        fun: add,
+    },
+    Builtin {
+        name: "head",
+        ty: "forall a. List a -> a",
+        arity: 1,
+        fun: head,
+    },
+    Builtin {
+        name: "tail",
+        ty: "forall a. List a -> List a",
+        arity: 1,
+        fun: tail,
+    },
+    Builtin {
+        name: "take",
+        ty: "forall a. Int -> List a -> List a",
+        arity: 2,
+        fun: take,
+    },
+    Builtin {
+        name: "ref",
+        ty: "forall a. a -> Ref a",
+        arity: 1,
+        fun: new_ref,
+    },
+    Builtin {
+        name: "!",
+        ty: "forall a. Ref a -> a",
+        arity: 1,
+        fun: deref,
+    },
+    Builtin {
+        name: ":=",
+        ty: "forall a. Ref a -> a -> Unit",
+        arity: 2,
+        fun: assign,
+    },
+];
+
+/// The kinds of the builtin type constructors.
+pub static TYPES: &[(&str, &str)] = &[
+    ("Int", "*"),
+    ("Bool", "*"),
+    ("Float", "*"),
+    ("Char", "*"),
+    ("String", "*"),
+    ("Unit", "*"),
+    ("->", "* -> * -> *"),
+    ("List", "* -> *"),
+    ("Ref", "* -> *"),
+];
+
+pub static CONSTRUCTORS: &[(&str, &str)] = &[
+    ("Nil", "forall a. List a"),
+    ("Cons", "forall a. a -> List a -> List a"),
+];
+
+/// Classes over the builtins, for programs to start with.
+pub static CLASSES: &str = "
+class Num a where (+) : a -> a -> a in
+instance Num Int where (+) = add in
+default Num Int in
+()";
+
+pub fn default_decls() -> Vec<Decl> {
+    let program: Program = CLASSES.parse().unwrap();
+    program.decls
+}
+
+/// Puts the declarations of [`CLASSES`] before those of `program`.
+pub fn with_prelude(mut program: Program) -> Program {
+    let mut decls = default_decls();
+    decls.append(&mut program.decls);
+    program.decls = decls;
+    program
+}
+
+pub fn default_ctx() -> Context {
+    let builtins = BUILTINS.iter().map(|b| (b.name, b.ty));
+    let constrains = builtins
+        .chain(CONSTRUCTORS.iter().copied())
+        .map(|(name, ty)| (name.to_string(), ty.parse().unwrap()))
+        .collect();
+
+    Context { constrains }
+}
+
+pub fn default_kinds() -> KindEnv {
+    let types = TYPES
+        .iter()
+        .map(|(name, kind)| (name.to_string(), kind.parse().unwrap()))
+        .collect();
+
+    KindEnv {
+        types,
+        classes: HashMap::new(),
+        synonyms: HashMap::new(),
+    }
+}
+
+pub fn default_env() -> Env {
+    BUILTINS.iter().fold(Env::default(), |env, builtin| {
+        env.extend_with(builtin.name.to_string(), Value::Builtin(builtin, vec![]))
+    })
+}
+
+// builtins may get thunks under call-by-need, so they force what they look at
+
+fn int(value: &Value) -> anyhow::Result<i64> {
+    match force(value.clone())? {
+        Value::Int(i) => Ok(i),
+        value => anyhow::bail!("expected an Int but got `{value}`"),
+    }
+}
+
+fn cell(value: &Value) -> anyhow::Result<Rc<RefCell<Value>>> {
+    match force(value.clone())? {
+        Value::Ref(cell) => Ok(cell),
+        value => anyhow::bail!("expected a Ref but got `{value}`"),
+    }
+}
+
+/// Splits a list into its head and tail, `None` if it is empty.
+fn uncons(value: &Value) -> anyhow::Result<Option<(Value, Value)>> {
+    match force(value.clone())? {
+        Value::Con(con, args) if con == "Nil" && args.is_empty() => Ok(None),
+        Value::Con(con, mut args) if con == "Cons" && args.len() == 2 => {
+            let tail = args.pop().unwrap();
+            let head = args.pop().unwrap();
+            Ok(Some((head, tail)))
+        }
+        value => anyhow::bail!("expected a List but got `{value}`"),
+    }
+}
+
+fn list(args: &[Value]) -> anyhow::Result<Value> {
+    let nil = Value::Con("Nil".to_string(), vec![]);
+    Ok(Value::Con("Cons".to_string(), vec![args[0].clone(), nil]))
+}
+
+fn inc(args: &[Value]) -> anyhow::Result<Value> {
+    Ok(Value::Int(int(&args[0])?.wrapping_add(1)))
+}
+
+fn dec(args: &[Value]) -> anyhow::Result<Value> {
+    Ok(Value::Int(int(&args[0])?.wrapping_sub(1)))
+}
+
+fn is_null(args: &[Value]) -> anyhow::Result<Value> {
+    Ok(Value::Bool(
+        matches!(force(args[0].clone())?, Value::Con(con, _) if con == "Nil"),
+    ))
+}
+
+fn add(args: &[Value]) -> anyhow::Result<Value> {
+    Ok(Value::Int(int(&args[0])?.wrapping_add(int(&args[1])?)))
+}
+
+fn head(args: &[Value]) -> anyhow::Result<Value> {
+    match uncons(&args[0])? {
+        Some((head, _)) => Ok(head),
+        None => anyhow::bail!("head of an empty list"),
+    }
+}
+
+fn tail(args: &[Value]) -> anyhow::Result<Value> {
+    match uncons(&args[0])? {
+        Some((_, tail)) => Ok(tail),
+        None => anyhow::bail!("tail of an empty list"),
+    }
+}
+
+/// Takes at most `n` elements, only forcing as much of the list as it needs.
+fn take(args: &[Value]) -> anyhow::Result<Value> {
+    let n = int(&args[0])?;
+    let mut list = args[1].clone();
+    let mut elems = vec![];
+    while elems.len() < n.max(0) as usize {
+        match uncons(&list)? {
+            Some((head, tail)) => {
+                elems.push(head);
+                list = tail;
+            }
+            None => break,
+        }
+    }
+
+    let nil = Value::Con("Nil".to_string(), vec![]);
+    Ok(elems.into_iter().rev().fold(nil, |tail, head| {
+        Value::Con("Cons".to_string(), vec![head, tail])
+    }))
+}
+
+fn new_ref(args: &[Value]) -> anyhow::Result<Value> {
+    Ok(Value::Ref(Rc::new(RefCell::new(args[0].clone()))))
+}
+
+fn deref(args: &[Value]) -> anyhow::Result<Value> {
+    let value = cell(&args[0])?.borrow().clone();
+    Ok(value)
+}
+
+fn assign(args: &[Value]) -> anyhow::Result<Value> {
+    *cell(&args[0])?.borrow_mut() = args[1].clone();
+    Ok(Value::Unit)
+}
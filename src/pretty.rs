@@ -0,0 +1,669 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::doc::Doc;
+use crate::lex::{is_op, Lexer, TokenKind};
+use crate::models::*;
+use crate::parser::{fixity, Assoc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// `forall a b. a -> b`
+    Ascii,
+    /// `∀a b. a -> b`
+    Unicode,
+}
+
+/// Prints types with minimal parentheses.
+///
+/// Nested quantifiers are collapsed into a single `∀a b.` and, when `rename` is set,
+/// variables are renamed to `a, b, c, ...` in order of appearance.
+#[derive(Debug, Clone, Copy)]
+pub struct TypePrinter {
+    pub charset: Charset,
+    pub rename: bool,
+}
+
+impl Default for TypePrinter {
+    fn default() -> Self {
+        Self {
+            charset: Charset::Unicode,
+            rename: true,
+        }
+    }
+}
+
+impl TypePrinter {
+    pub fn new(charset: Charset, rename: bool) -> Self {
+        Self { charset, rename }
+    }
+
+    pub fn print_mono(&self, mono: &MonoType) -> String {
+        let names = if self.rename {
+            rename(&HashSet::new(), &mono.type_vars())
+        } else {
+            HashMap::new()
+        };
+
+        let mut out = String::new();
+        write_mono(&mut out, mono, &names);
+        out
+    }
+
+    pub fn print_poly(&self, poly: &PolyType) -> String {
+        let (mut bounded, mono) = poly.split();
+        let names = if self.rename {
+            // order quantifiers by the first appearance of their variable in the body
+            let appearance = mono.type_vars();
+            bounded.sort_by_key(|b| appearance.iter().position(|a| a == b));
+            let free = mono
+                .free_vars()
+                .into_iter()
+                .filter(|v| !bounded.contains(v))
+                .collect();
+            rename(&free, &bounded)
+        } else {
+            HashMap::new()
+        };
+
+        let mut out = String::new();
+        if !bounded.is_empty() {
+            out.push_str(match self.charset {
+                Charset::Ascii => "forall ",
+                Charset::Unicode => "∀",
+            });
+            let bounded: Vec<_> = bounded
+                .iter()
+                .map(|b| names.get(b).map(String::as_str).unwrap_or(b))
+                .collect();
+            out.push_str(&bounded.join(" "));
+            out.push_str(". ");
+        }
+        let preds = poly.preds();
+        if !preds.is_empty() {
+            let preds: Vec<_> = preds
+                .iter()
+                .map(|pred| {
+                    let mut out = format!("{} ", pred.class);
+                    write_wrapped(&mut out, &pred.ty, !pred.ty.is_atom(), &names);
+                    out
+                })
+                .collect();
+            write_context(&mut out, &preds);
+        }
+        write_mono(&mut out, mono, &names);
+        out
+    }
+}
+
+/// Maps each of `vars` to a fresh short name that does not clash with `keep`.
+fn rename<'a>(keep: &HashSet<&str>, vars: &[&'a str]) -> HashMap<&'a str, String> {
+    let mut names = (0..).map(short_name).filter(|n| !keep.contains(n.as_str()));
+
+    vars.iter()
+        .map(|&var| (var, names.next().unwrap()))
+        .collect()
+}
+
+/// `a, b, ..., z, a1, b1, ...`
+pub(crate) fn short_name(n: usize) -> String {
+    let letter = (b'a' + (n % 26) as u8) as char;
+    match n / 26 {
+        0 => letter.to_string(),
+        suffix => format!("{letter}{suffix}"),
+    }
+}
+
+fn write_mono(out: &mut String, mono: &MonoType, names: &HashMap<&str, String>) {
+    match mono {
+        MonoType::TyVar(var) => out.push_str(names.get(var.as_str()).unwrap_or(var)),
+        MonoType::TyApp(app) if mono.is_arrow() => {
+            let (from, to) = (&app.monotypes[0], &app.monotypes[1]);
+            write_wrapped(out, from, from.is_arrow(), names);
+            out.push_str(" -> ");
+            write_mono(out, to, names);
+        }
+        MonoType::TyApp(app) => {
+            // `->` missing arguments is what a higher-kinded variable can stand for
+            match app.func.as_str() {
+                "->" => out.push_str("(->)"),
+                func => out.push_str(func),
+            }
+            write_args(out, &app.monotypes, names);
+        }
+        MonoType::TyVarApp(app) => {
+            out.push_str(names.get(app.var.as_str()).unwrap_or(&app.var));
+            write_args(out, &app.monotypes, names);
+        }
+    }
+}
+
+fn write_args(out: &mut String, monotypes: &[MonoType], names: &HashMap<&str, String>) {
+    for mono in monotypes {
+        out.push(' ');
+        write_wrapped(out, mono, !mono.is_atom(), names);
+    }
+}
+
+fn write_wrapped(out: &mut String, mono: &MonoType, wrap: bool, names: &HashMap<&str, String>) {
+    if wrap {
+        out.push('(');
+        write_mono(out, mono, names);
+        out.push(')');
+    } else {
+        write_mono(out, mono, names);
+    }
+}
+
+/// Lays out expressions within `width` columns, breaking `let` chains, lambdas and
+/// long applications across lines and printing only the parentheses the parser needs.
+#[derive(Debug, Clone, Copy)]
+pub struct ExprPrinter {
+    pub width: usize,
+}
+
+impl Default for ExprPrinter {
+    fn default() -> Self {
+        Self { width: 80 }
+    }
+}
+
+impl ExprPrinter {
+    pub fn new(width: usize) -> Self {
+        Self { width }
+    }
+
+    pub fn print(&self, expr: &Expr) -> String {
+        expr_doc(expr).render(self.width)
+    }
+
+    pub fn print_program(&self, program: &Program) -> String {
+        self.print_program_with_comments(program, &Comments::default())
+    }
+
+    /// Like [`ExprPrinter::print`], putting each comment back before the expression it
+    /// was attached to.
+    pub fn print_with_comments(&self, expr: &Expr, comments: &Comments) -> String {
+        let program = Program {
+            decls: vec![],
+            body: expr.clone(),
+        };
+        self.print_program_with_comments(&program, comments)
+    }
+
+    /// Like [`ExprPrinter::print_program`], putting each comment back before the
+    /// declaration or expression it was attached to.
+    pub fn print_program_with_comments(&self, program: &Program, comments: &Comments) -> String {
+        let mut builder = DocBuilder {
+            comments,
+            next_word: 0,
+            next_comment: 0,
+        };
+        let mut docs = vec![builder.program_doc(program)];
+        for comment in comments.trailing.iter() {
+            docs.push(Doc::HardLine);
+            docs.push(comment_doc(comment));
+        }
+
+        Doc::concat(docs).render(self.width)
+    }
+}
+
+/// Comments of a source file, each attached to the expression or declaration that
+/// follows it, or to the one it ends the line of.
+///
+/// The printer keeps the names, operators and literals of the source in order, so a
+/// comment is placed by the word that follows it, or the word it comes after.
+#[derive(Debug, Clone, Default)]
+pub struct Comments {
+    /// Comments before the n-th word of the source, counting from 0.
+    pub before: HashMap<usize, Vec<String>>,
+    /// Line comments at the end of the line of the n-th word.
+    pub after: HashMap<usize, Vec<String>>,
+    /// Comments after the last word.
+    pub trailing: Vec<String>,
+}
+
+impl Comments {
+    pub fn collect(source: &str) -> Comments {
+        let mut comments = Comments::default();
+        let mut pending = vec![];
+        let mut words = 0;
+        let mut last_row = None;
+        for token in Lexer::new(source).keep_comments() {
+            if token.kind == TokenKind::Comment {
+                let ends_line = last_row == Some(token.loc.row) && token.value.starts_with("--");
+                if ends_line && pending.is_empty() && words > 0 {
+                    comments
+                        .after
+                        .entry(words - 1)
+                        .or_default()
+                        .push(token.value);
+                } else {
+                    pending.push(token.value);
+                }
+                continue;
+            }
+            if is_word(&token.kind) {
+                if !pending.is_empty() {
+                    comments.before.insert(words, std::mem::take(&mut pending));
+                }
+                words += 1;
+            }
+            last_row = Some(token.loc.row);
+        }
+        comments.trailing = pending;
+
+        comments
+    }
+}
+
+pub fn expr_doc(expr: &Expr) -> Doc {
+    let comments = Comments::default();
+    let mut builder = DocBuilder {
+        comments: &comments,
+        next_word: 0,
+        next_comment: 0,
+    };
+    builder.expr_doc(expr)
+}
+
+/// Names, operators and literals, which the printer writes in the order of the source.
+fn is_word(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::LitBool
+            | TokenKind::LitInt
+            | TokenKind::LitFloat
+            | TokenKind::LitChar
+            | TokenKind::LitString
+            | TokenKind::Var
+            | TokenKind::Con
+            | TokenKind::QualName
+            | TokenKind::Op
+    )
+}
+
+/// Builds the document of an expression, writing its text in source order so the words
+/// can be matched up with their comments.
+struct DocBuilder<'a> {
+    comments: &'a Comments,
+    next_word: usize,
+    /// The first word whose comments are not written yet.
+    next_comment: usize,
+}
+
+impl DocBuilder<'_> {
+    /// Declarations break like a chain of `let`s, each one with its methods.
+    fn program_doc(&mut self, program: &Program) -> Doc {
+        let mut docs = vec![];
+        for decl in program.decls.iter() {
+            docs.extend(self.comments_doc());
+            let (head, methods) = match decl {
+                Decl::Default(pred) => {
+                    docs.push(self.text(format!("default {pred} in")));
+                    docs.push(Doc::Line);
+                    continue;
+                }
+                Decl::Data(data) => {
+                    docs.push(self.data_doc(data));
+                    docs.push(Doc::Line);
+                    continue;
+                }
+                Decl::Type(synonym) => {
+                    docs.push(self.text(format!("{synonym} in")));
+                    docs.push(Doc::Line);
+                    continue;
+                }
+                Decl::Class(class) => {
+                    let mut head = "class ".to_string();
+                    let supers: Vec<_> = class
+                        .supers
+                        .iter()
+                        .map(|name| format!("{name} {}", class.var))
+                        .collect();
+                    write_context(&mut head, &supers);
+                    write!(head, "{} {} where", class.name, class.var).unwrap();
+                    let head = self.text(head);
+                    let methods: Vec<_> = class
+                        .methods
+                        .iter()
+                        .map(|(name, ty)| self.text(format!("{} : {ty}", binder(name))))
+                        .collect();
+                    (head, methods)
+                }
+                Decl::Instance(instance) => {
+                    let mut head = "instance ".to_string();
+                    let context: Vec<_> = instance.context.iter().map(Pred::to_string).collect();
+                    write_context(&mut head, &context);
+                    write!(head, "{} where", instance.head).unwrap();
+                    let head = self.text(head);
+                    let methods = instance
+                        .methods
+                        .iter()
+                        .map(|(name, expr)| {
+                            let mut docs = self.comments_doc();
+                            let method = Doc::concat([
+                                self.text(format!("{} =", binder(name))),
+                                Doc::concat([Doc::Line, self.expr_doc(expr)]).nest(2),
+                            ]);
+                            docs.push(method.group());
+                            Doc::concat(docs)
+                        })
+                        .collect();
+                    (head, methods)
+                }
+            };
+
+            let mut method_docs = vec![];
+            for (i, method) in methods.into_iter().enumerate() {
+                if i > 0 {
+                    method_docs.push(Doc::text(";"));
+                }
+                method_docs.push(Doc::Line);
+                method_docs.push(method);
+            }
+            let decl = Doc::concat([
+                head,
+                Doc::concat(method_docs).nest(2),
+                Doc::Line,
+                Doc::text("in"),
+            ]);
+            docs.push(decl.group());
+            docs.push(Doc::Line);
+        }
+        docs.push(self.expr_doc(&program.body));
+
+        Doc::concat(docs).group()
+    }
+
+    /// The text, between the comments attached to its words.
+    fn text(&mut self, text: impl Into<String>) -> Doc {
+        let text = text.into();
+        let words = Lexer::new(&text)
+            .filter(|token| is_word(&token.kind))
+            .count();
+        let mut docs = self.comments_until(self.next_word + words);
+        docs.push(Doc::text(text));
+        for word in self.next_word..self.next_word + words {
+            for comment in self.comments.after.get(&word).into_iter().flatten() {
+                docs.push(Doc::LineSuffix(format!(" {comment}")));
+            }
+        }
+        self.next_word += words;
+
+        Doc::concat(docs)
+    }
+
+    /// The comments attached to the next word, to be put outside of the groups that
+    /// start with it.
+    fn comments_doc(&mut self) -> Vec<Doc> {
+        self.comments_until(self.next_word + 1)
+    }
+
+    /// The comments not written yet of the words before `end`, each on its own line.
+    fn comments_until(&mut self, end: usize) -> Vec<Doc> {
+        let mut docs = vec![];
+        for word in self.next_comment..end {
+            for comment in self.comments.before.get(&word).into_iter().flatten() {
+                docs.push(comment_doc(comment));
+                docs.push(Doc::HardLine);
+            }
+        }
+        self.next_comment = self.next_comment.max(end);
+
+        docs
+    }
+
+    fn expr_doc(&mut self, expr: &Expr) -> Doc {
+        let mut docs = self.comments_doc();
+        docs.push(self.node_doc(expr));
+        Doc::concat(docs)
+    }
+
+    fn node_doc(&mut self, expr: &Expr) -> Doc {
+        match expr {
+            Expr::Lit(lit) => self.text(lit.to_string()),
+            Expr::Var(var) | Expr::Con(var) => self.text(binder(var)),
+            Expr::App(_) if deref(expr).is_some() => {
+                // `!!r` would lex as one operator
+                let arg = deref(expr).unwrap();
+                let wrap = match arg {
+                    Expr::Lit(lit) => is_negative(lit),
+                    _ => !matches!(arg, Expr::Var(_) | Expr::Con(_)),
+                };
+                Doc::concat([self.text("!"), self.wrapped_doc(arg, wrap)])
+            }
+            Expr::App(_) if infix(expr).is_some() => {
+                let (op, lhs, rhs) = infix(expr).unwrap();
+                let (prec, assoc) = fixity(op);
+                // operands bind tighter, or as tight on the side the operator groups to
+                let wrap = |operand: &Expr, side: Assoc| match infix(operand) {
+                    Some((inner, _, _)) => {
+                        let inner = fixity(inner).0;
+                        inner < prec || (inner == prec && assoc != side)
+                    }
+                    None => !matches!(operand, Expr::App(_) | Expr::Lit(_)) && !is_atom(operand),
+                };
+                let lhs = self.wrapped_doc(lhs, wrap(lhs, Assoc::Left));
+                let op = self.text(format!(" {op}"));
+                let rhs = self.wrapped_doc(rhs, wrap(rhs, Assoc::Right));
+                Doc::concat([lhs, op, Doc::concat([Doc::Line, rhs]).nest(2)]).group()
+            }
+            Expr::App(_) => {
+                let mut args = vec![];
+                let mut fun = expr;
+                while let (Expr::App(app), None) = (fun, deref(fun)) {
+                    args.push(&app.arg);
+                    fun = &app.fun;
+                }
+
+                let fun = self.wrapped_doc(fun, !is_atom(fun));
+                let mut arg_docs = vec![];
+                for arg in args.into_iter().rev() {
+                    arg_docs.push(Doc::Line);
+                    arg_docs.push(self.wrapped_doc(arg, !is_atom(arg)));
+                }
+                Doc::concat([fun, Doc::concat(arg_docs).nest(2)]).group()
+            }
+            Expr::Abs(_) => {
+                let mut args = vec![];
+                let mut body = expr;
+                while let Expr::Abs(abs) = body {
+                    args.push(abs.arg.as_str());
+                    body = &abs.body;
+                }
+
+                Doc::concat([
+                    self.text(format!("\\{} ->", args.join(" "))),
+                    Doc::concat([Doc::Line, self.expr_doc(body)]).nest(2),
+                ])
+                .group()
+            }
+            Expr::Ann(ann) => Doc::concat([
+                Doc::text("("),
+                self.expr_doc(&ann.expr).nest(1),
+                self.text(format!(" : {})", ann.ty)),
+            ])
+            .group(),
+            Expr::Case(case) => {
+                let scrutinee = Doc::concat([
+                    Doc::text("case "),
+                    self.expr_doc(&case.expr).nest(5),
+                    Doc::text(" of"),
+                ]);
+                let mut arms = vec![];
+                for (i, arm) in case.arms.iter().enumerate() {
+                    // a `case` at the end of an arm would take the arms after it
+                    let last = i + 1 == case.arms.len();
+                    arms.push(Doc::Line);
+                    arms.extend(self.comments_doc());
+                    let pattern = self.text(format!("{} ->", arm.pattern()));
+                    let body = self.wrapped_doc(&arm.body, !last && ends_in_case(&arm.body));
+                    let mut arm_doc = vec![pattern, Doc::concat([Doc::Line, body]).nest(2)];
+                    if !last {
+                        arm_doc.push(Doc::text(";"));
+                    }
+                    arms.push(Doc::concat(arm_doc).group());
+                }
+
+                Doc::concat([scrutinee, Doc::concat(arms).nest(2)]).group()
+            }
+            Expr::Let(_) | Expr::LetRec(_) => {
+                // a chain of lets breaks as a whole, one binding per line
+                let mut bindings = vec![];
+                let mut body = expr;
+                while let Expr::Let(elet) | Expr::LetRec(elet) = body {
+                    let keyword = match body {
+                        Expr::LetRec(_) => "letrec",
+                        _ => "let",
+                    };
+                    bindings.extend(self.comments_doc());
+                    let binding = Doc::concat([
+                        self.text(format!("{keyword} {} =", binder(&elet.var))),
+                        Doc::concat([Doc::Line, self.expr_doc(&elet.vexpr)]).nest(2),
+                        Doc::Line,
+                        Doc::text("in"),
+                    ]);
+                    bindings.push(binding.group());
+                    bindings.push(Doc::Line);
+                    body = &elet.aexpr;
+                }
+                bindings.push(self.expr_doc(body));
+
+                Doc::concat(bindings).group()
+            }
+        }
+    }
+
+    fn wrapped_doc(&mut self, expr: &Expr, wrap: bool) -> Doc {
+        if wrap {
+            let mut docs = self.comments_doc();
+            docs.extend([Doc::text("("), self.expr_doc(expr).nest(1), Doc::text(")")]);
+            Doc::concat(docs)
+        } else {
+            self.expr_doc(expr)
+        }
+    }
+
+    /// `data T a = C1 t | C2 in`, breaking before each `|`.
+    fn data_doc(&mut self, data: &DataDecl) -> Doc {
+        let keyword = if data.newtype { "newtype" } else { "data" };
+        let mut head = format!("{keyword} {}", data.name);
+        for param in data.params.iter() {
+            write!(head, " {param}").unwrap();
+        }
+        if data.constructors.is_empty() {
+            return self.text(format!("{head} in"));
+        }
+
+        let head = self.text(format!("{head} ="));
+        let mut constructors = vec![];
+        for (i, con) in data.constructors.iter().enumerate() {
+            let mut constructor = if i > 0 {
+                "| ".to_string()
+            } else {
+                String::new()
+            };
+            if !con.exists.is_empty() {
+                write!(constructor, "forall {}. ", con.exists.join(" ")).unwrap();
+            }
+            constructor.push_str(&con.name);
+            for field in con.fields.iter() {
+                if field.is_atom() {
+                    write!(constructor, " {field}").unwrap();
+                } else {
+                    write!(constructor, " ({field})").unwrap();
+                }
+            }
+            constructors.push(Doc::Line);
+            constructors.push(self.text(constructor));
+        }
+        Doc::concat([
+            head,
+            Doc::concat(constructors).nest(2),
+            Doc::Line,
+            Doc::text("in"),
+        ])
+        .group()
+    }
+}
+
+/// The operator and operands of `lhs op rhs`.
+fn infix(expr: &Expr) -> Option<(&str, &Expr, &Expr)> {
+    let Expr::App(app) = expr else {
+        return None;
+    };
+    let Expr::App(op_app) = &app.fun else {
+        return None;
+    };
+    match &op_app.fun {
+        Expr::Var(op) if is_op(op) && op != "!" => Some((op, &op_app.arg, &app.arg)),
+        _ => None,
+    }
+}
+
+/// The operand of the prefix `!r`.
+fn deref(expr: &Expr) -> Option<&Expr> {
+    match expr {
+        Expr::App(app) if matches!(&app.fun, Expr::Var(op) if op == "!") => Some(&app.arg),
+        _ => None,
+    }
+}
+
+/// A variable where it is not applied infix, operators are parenthesised.
+fn binder(var: &str) -> String {
+    if is_op(var) {
+        format!("({var})")
+    } else {
+        var.to_string()
+    }
+}
+
+/// Writes `C a => ` or `(C a, D b) => `, nothing if there are no predicates.
+fn write_context(out: &mut String, preds: &[String]) {
+    match preds {
+        [] => {}
+        [pred] => write!(out, "{pred} => ").unwrap(),
+        _ => write!(out, "({}) => ", preds.join(", ")).unwrap(),
+    }
+}
+
+fn is_atom(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(lit) => !is_negative(lit),
+        _ => matches!(expr, Expr::Var(_) | Expr::Con(_) | Expr::Ann(_)) || deref(expr).is_some(),
+    }
+}
+
+/// A negative literal starts with `-`, which would subtract where an atom is expected.
+fn is_negative(lit: &Literal) -> bool {
+    match lit {
+        Literal::Int(int) => *int < 0,
+        Literal::Float(float) => float.is_sign_negative(),
+        _ => false,
+    }
+}
+
+/// Whether the expression ends in a `case`, whose arms extend as far as possible.
+fn ends_in_case(expr: &Expr) -> bool {
+    match expr {
+        Expr::Case(_) => true,
+        Expr::Abs(abs) => ends_in_case(&abs.body),
+        Expr::Let(elet) | Expr::LetRec(elet) => ends_in_case(&elet.aexpr),
+        _ => false,
+    }
+}
+
+/// Block comments keep their lines but are re-indented with the surrounding code.
+fn comment_doc(comment: &str) -> Doc {
+    let mut docs = vec![];
+    for (i, line) in comment.lines().enumerate() {
+        if i > 0 {
+            docs.push(Doc::HardLine);
+        }
+        docs.push(Doc::text(if i > 0 { line.trim_start() } else { line }));
+    }
+
+    Doc::concat(docs)
+}
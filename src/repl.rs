@@ -0,0 +1,58 @@
+//! One line of the read-eval-print loop run by `app`.
+
+use std::fmt::Write;
+
+use crate::classes::translate_program;
+use crate::eval::{eval_with, force_deep, Strategy};
+use crate::lex::Lexer;
+use crate::models::*;
+use crate::parser::Parser;
+use crate::prelude::{default_ctx, default_env, with_prelude};
+use crate::pretty::short_name;
+use crate::utils::*;
+
+/// Parses, checks and runs a program in the default context, and gives what the REPL
+/// prints for it. Errors are printed with all their causes.
+pub fn run_line(source: &str, strategy: Strategy) -> String {
+    let program = match Parser::new(Lexer::new(source)).parse_program() {
+        Ok(p) => with_prelude(p),
+        Err(err) => return format!("Syntax Error: {err:#}\n"),
+    };
+
+    let translated = match translate_program(&default_ctx(), &program) {
+        Ok(translated) => translated,
+        Err(err) => return format!("Type Error: {err:#}\n"),
+    };
+    let mut out = String::new();
+    for warning in translated.warnings.iter() {
+        writeln!(out, "Warning: {warning}").unwrap();
+    }
+
+    let ty = weak_names(&translated.ty);
+    match eval_with(strategy, &default_env(), &translated.expr).and_then(force_deep) {
+        Ok(value) => writeln!(out, "it : {ty} = {value}").unwrap(),
+        Err(err) => writeln!(out, "it : {ty}\nRuntime Error: {err:#}").unwrap(),
+    }
+
+    out
+}
+
+/// Names the variables the value restriction left unquantified `'_a, '_b, ..`, like
+/// OCaml's weak variables: they stand for one type yet to be known, not for any.
+fn weak_names(ty: &PolyType) -> PolyType {
+    let (bounded, mono) = ty.split();
+    let weak = mono
+        .type_vars()
+        .into_iter()
+        .filter(|var| !bounded.contains(var))
+        .enumerate()
+        .map(|(i, var)| {
+            (
+                var.to_string(),
+                MonoType::TyVar(format!("'_{}", short_name(i))),
+            )
+        });
+    let s = Substitution::from_map(weak.collect());
+
+    ty.clone().apply(&s)
+}
@@ -0,0 +1,300 @@
+/* The runtime of the generated C programs. Values are allocated and never freed. */
+
+#include <math.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef struct value value;
+typedef value *(*code)(value *self, value *arg);
+
+enum tag { INT, BOOL, FLOAT, CHAR, STR, UNIT, CLOSURE, CON };
+
+struct value {
+    enum tag tag;
+    union {
+        int64_t i;
+        bool b;
+        double f;
+        uint32_t c;
+        struct {
+            const char *chars;
+            size_t len;
+        } s;
+        struct {
+            code code;
+            /* the builtin it comes from, NULL for lambdas */
+            const char *name;
+            value **env;
+        } clo;
+        struct {
+            const char *name;
+            int arity;
+            int n;
+            value **args;
+        } con;
+    };
+};
+
+static value unit_value = {.tag = UNIT};
+
+static void fail(const char *message) {
+    fprintf(stderr, "error: %s\n", message);
+    exit(1);
+}
+
+static value *alloc(enum tag tag) {
+    value *v = malloc(sizeof *v);
+    if (v == NULL) fail("out of memory");
+    v->tag = tag;
+    return v;
+}
+
+static value *mk_int(int64_t i) {
+    value *v = alloc(INT);
+    v->i = i;
+    return v;
+}
+
+static value *mk_bool(bool b) {
+    value *v = alloc(BOOL);
+    v->b = b;
+    return v;
+}
+
+static value *mk_float(double f) {
+    value *v = alloc(FLOAT);
+    v->f = f;
+    return v;
+}
+
+static value *mk_char(uint32_t c) {
+    value *v = alloc(CHAR);
+    v->c = c;
+    return v;
+}
+
+static value *mk_str(const char *chars, size_t len) {
+    value *v = alloc(STR);
+    v->s.chars = chars;
+    v->s.len = len;
+    return v;
+}
+
+static value *mk_closure(code code, const char *name, int n) {
+    value *v = alloc(CLOSURE);
+    v->clo.code = code;
+    v->clo.name = name;
+    v->clo.env = calloc(n > 0 ? n : 1, sizeof(value *));
+    return v;
+}
+
+static value *mk_con(const char *name, int arity) {
+    value *v = alloc(CON);
+    v->con.name = name;
+    v->con.arity = arity;
+    v->con.n = 0;
+    v->con.args = NULL;
+    return v;
+}
+
+static int64_t wrapping_add(int64_t a, int64_t b) {
+    return (int64_t)((uint64_t)a + (uint64_t)b);
+}
+
+static value *apply(value *f, value *arg) {
+    switch (f->tag) {
+    case CLOSURE:
+        return f->clo.code(f, arg);
+    case CON: {
+        value *v = mk_con(f->con.name, f->con.arity);
+        v->con.n = f->con.n + 1;
+        v->con.args = malloc(v->con.n * sizeof(value *));
+        if (f->con.n > 0) memcpy(v->con.args, f->con.args, f->con.n * sizeof(value *));
+        v->con.args[f->con.n] = arg;
+        return v;
+    }
+    default:
+        fail("applied a value that is not a function");
+        return NULL;
+    }
+}
+
+/* builtins, see `prelude::BUILTINS` */
+
+static value *nil(void) { return mk_con("Nil", 0); }
+
+static value *cons(value *head, value *tail) { return apply(apply(mk_con("Cons", 2), head), tail); }
+
+static bool is_nil(value *v) { return strcmp(v->con.name, "Nil") == 0; }
+
+static value *builtin_list(value *self, value *arg) { return cons(arg, nil()); }
+
+static value *builtin_inc(value *self, value *arg) { return mk_int(wrapping_add(arg->i, 1)); }
+
+static value *builtin_dec(value *self, value *arg) { return mk_int(wrapping_add(arg->i, -1)); }
+
+static value *builtin_isNull(value *self, value *arg) {
+    return mk_bool(arg->tag == CON && is_nil(arg));
+}
+
+static value *builtin_add2(value *self, value *arg) {
+    return mk_int(wrapping_add(self->clo.env[0]->i, arg->i));
+}
+
+static value *builtin_add(value *self, value *arg) {
+    value *v = mk_closure(builtin_add2, "add", 1);
+    v->clo.env[0] = arg;
+    return v;
+}
+
+static value *builtin_head(value *self, value *arg) {
+    if (is_nil(arg)) fail("head of an empty list");
+    return arg->con.args[0];
+}
+
+static value *builtin_tail(value *self, value *arg) {
+    if (is_nil(arg)) fail("tail of an empty list");
+    return arg->con.args[1];
+}
+
+static value *builtin_take2(value *self, value *arg) {
+    int64_t n = self->clo.env[0]->i;
+    value **elems = malloc((n > 0 ? n : 1) * sizeof(value *));
+    int64_t len = 0;
+    for (value *list = arg; len < n && !is_nil(list); list = list->con.args[1]) {
+        elems[len++] = list->con.args[0];
+    }
+
+    value *result = nil();
+    while (len > 0) result = cons(elems[--len], result);
+    free(elems);
+    return result;
+}
+
+static value *builtin_take(value *self, value *arg) {
+    value *v = mk_closure(builtin_take2, "take", 1);
+    v->clo.env[0] = arg;
+    return v;
+}
+
+/* printing, the same way as `Display for Value` */
+
+static void print_escaped(uint32_t c, char quote) {
+    switch (c) {
+    case '\n': fputs("\\n", stdout); return;
+    case '\t': fputs("\\t", stdout); return;
+    case '\r': fputs("\\r", stdout); return;
+    case '\0': fputs("\\0", stdout); return;
+    case '\\': fputs("\\\\", stdout); return;
+    }
+    if (c == (uint32_t)quote) {
+        printf("\\%c", quote);
+    } else if (c < 0x80) {
+        putchar(c);
+    } else if (c < 0x800) {
+        putchar(0xc0 | c >> 6);
+        putchar(0x80 | (c & 0x3f));
+    } else if (c < 0x10000) {
+        putchar(0xe0 | c >> 12);
+        putchar(0x80 | (c >> 6 & 0x3f));
+        putchar(0x80 | (c & 0x3f));
+    } else {
+        putchar(0xf0 | c >> 18);
+        putchar(0x80 | (c >> 12 & 0x3f));
+        putchar(0x80 | (c >> 6 & 0x3f));
+        putchar(0x80 | (c & 0x3f));
+    }
+}
+
+/* Like Rust's `{:?}`: the shortest digits that read back the same, in plain notation
+   from 1e-4 up to 1e16 and in scientific notation otherwise. */
+static void print_float(double f) {
+    if (isnan(f)) {
+        fputs("NaN", stdout);
+        return;
+    }
+    if (signbit(f)) {
+        putchar('-');
+        f = -f;
+    }
+    if (isinf(f)) {
+        fputs("inf", stdout);
+        return;
+    }
+    if (f == 0) {
+        fputs("0.0", stdout);
+        return;
+    }
+
+    char buf[32];
+    for (int precision = 0; precision <= 17; precision++) {
+        snprintf(buf, sizeof buf, "%.*e", precision, f);
+        if (strtod(buf, NULL) == f) break;
+    }
+    /* buf is d.ddde[+-]xx, keep the digits and the exponent */
+    char digits[20];
+    int n = 0;
+    char *p = buf;
+    for (; *p != 'e'; p++) {
+        if (*p != '.') digits[n++] = *p;
+    }
+    digits[n] = '\0';
+    int exp = atoi(p + 1);
+
+    if (f < 1e-4 || f >= 1e16) {
+        putchar(digits[0]);
+        if (n > 1) printf(".%s", digits + 1);
+        printf("e%d", exp);
+    } else if (exp < 0) {
+        fputs("0.", stdout);
+        for (int i = -1; i > exp; i--) putchar('0');
+        fputs(digits, stdout);
+    } else {
+        for (int i = 0; i <= exp; i++) putchar(i < n ? digits[i] : '0');
+        putchar('.');
+        fputs(n > exp + 1 ? digits + exp + 1 : "0", stdout);
+    }
+}
+
+static void print_value(value *v) {
+    switch (v->tag) {
+    case INT: printf("%lld", (long long)v->i); break;
+    case BOOL: fputs(v->b ? "true" : "false", stdout); break;
+    case FLOAT: print_float(v->f); break;
+    case CHAR:
+        putchar('\'');
+        print_escaped(v->c, '\'');
+        putchar('\'');
+        break;
+    case STR:
+        /* the bytes are UTF-8, only ASCII ones can need escaping */
+        putchar('"');
+        for (size_t i = 0; i < v->s.len; i++) {
+            unsigned char c = v->s.chars[i];
+            if (c < 0x80) print_escaped(c, '"');
+            else putchar(c);
+        }
+        putchar('"');
+        break;
+    case UNIT: fputs("()", stdout); break;
+    case CLOSURE:
+        if (v->clo.name) printf("<builtin %s>", v->clo.name);
+        else fputs("<closure>", stdout);
+        break;
+    case CON:
+        fputs(v->con.name, stdout);
+        for (int i = 0; i < v->con.n; i++) {
+            value *arg = v->con.args[i];
+            /* `C -1` would read as a subtraction */
+            bool wrap = (arg->tag == CON && arg->con.n > 0) || (arg->tag == INT && arg->i < 0)
+                || (arg->tag == FLOAT && signbit(arg->f));
+            fputs(wrap ? " (" : " ", stdout);
+            print_value(arg);
+            if (wrap) putchar(')');
+        }
+        break;
+    }
+}
@@ -0,0 +1,554 @@
+//! Explicitly typed System F, the elaboration of inferred expressions into it, and a
+//! type checker for it that doesn't share any code with inference.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::lex::Lexer;
+use crate::models::*;
+use crate::parser::Parser;
+use crate::typed::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FType {
+    Var(String),
+    /// A type constructor applied to its arguments, `->` included.
+    Con(String, Vec<FType>),
+    /// A type variable of a higher kind applied to its arguments, never empty.
+    VarApp(String, Vec<FType>),
+    Forall(String, Box<FType>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FTerm {
+    Lit(Literal),
+    Var(String),
+    Con(String),
+    Abs(String, FType, Box<FTerm>),
+    App(Box<FTerm>, Box<FTerm>),
+    /// `Λa. e`
+    TyAbs(String, Box<FTerm>),
+    /// `e [τ]`
+    TyApp(Box<FTerm>, FType),
+    Let(String, FType, Box<FTerm>, Box<FTerm>),
+    LetRec(String, FType, Box<FTerm>, Box<FTerm>),
+    Case(Box<FTerm>, Vec<FArm>),
+}
+
+/// `C [σ ..] [k ..] (x : τ) .. -> e`: the constructor applied to the types of the
+/// scrutinee, opening its existential variables with the skolem constants `k`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FArm {
+    pub con: String,
+    pub inst: Vec<FType>,
+    pub skolems: Vec<String>,
+    pub vars: Vec<(String, FType)>,
+    pub body: FTerm,
+}
+
+impl From<&MonoType> for FType {
+    fn from(mono: &MonoType) -> Self {
+        match mono {
+            MonoType::TyVar(var) => FType::Var(var.clone()),
+            MonoType::TyApp(app) => FType::Con(
+                app.func.clone(),
+                app.monotypes.iter().map(FType::from).collect(),
+            ),
+            MonoType::TyVarApp(app) => FType::VarApp(
+                app.var.clone(),
+                app.monotypes.iter().map(FType::from).collect(),
+            ),
+        }
+    }
+}
+
+impl From<&PolyType> for FType {
+    fn from(poly: &PolyType) -> Self {
+        match poly {
+            PolyType::Mono(mono) => mono.into(),
+            // dictionaries are not elaborated, only plain Hindley-Milner is
+            PolyType::Qualified(qual) => (&qual.ty).into(),
+            PolyType::TyQuantifier(quan) => {
+                FType::Forall(quan.bounded.clone(), Box::new(quan.ty.as_ref().into()))
+            }
+        }
+    }
+}
+
+impl FromStr for FType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(Lexer::new(s));
+        let ty = parser.parse_rank_type()?;
+        parser.expect_eof()?;
+
+        Ok(ty)
+    }
+}
+
+/// The types of rank 0, without any quantifier.
+impl TryFrom<&FType> for MonoType {
+    type Error = anyhow::Error;
+
+    fn try_from(ty: &FType) -> Result<Self, Self::Error> {
+        match ty {
+            FType::Var(var) => Ok(MonoType::TyVar(var.clone())),
+            FType::Con(con, args) => Ok(MonoType::TyApp(TypeApp {
+                func: con.clone(),
+                monotypes: args
+                    .iter()
+                    .map(MonoType::try_from)
+                    .collect::<Result<_, _>>()?,
+            })),
+            FType::VarApp(var, args) => Ok(MonoType::TyVarApp(TypeVarApp {
+                var: var.clone(),
+                monotypes: args
+                    .iter()
+                    .map(MonoType::try_from)
+                    .collect::<Result<_, _>>()?,
+            })),
+            FType::Forall(..) => anyhow::bail!("`{ty}` is not a monotype"),
+        }
+    }
+}
+
+impl FType {
+    pub fn arrow(from: FType, to: FType) -> FType {
+        FType::Con("->".to_string(), vec![from, to])
+    }
+
+    /// Applies the type to more arguments, keeping the head of the result a name.
+    pub fn applied_to(self, more: Vec<FType>) -> FType {
+        match self {
+            _ if more.is_empty() => self,
+            FType::Var(var) => FType::VarApp(var, more),
+            FType::Con(con, mut args) => {
+                args.extend(more);
+                FType::Con(con, args)
+            }
+            FType::VarApp(var, mut args) => {
+                args.extend(more);
+                FType::VarApp(var, args)
+            }
+            FType::Forall(..) => unreachable!("type variables only stand for monotypes"),
+        }
+    }
+
+    pub fn free_vars(&self) -> HashSet<&str> {
+        match self {
+            FType::Var(var) => HashSet::from([var.as_str()]),
+            FType::Con(_, args) => args.iter().flat_map(FType::free_vars).collect(),
+            FType::VarApp(var, args) => {
+                let mut vars: HashSet<_> = args.iter().flat_map(FType::free_vars).collect();
+                vars.insert(var);
+                vars
+            }
+            FType::Forall(var, ty) => {
+                let mut vars = ty.free_vars();
+                vars.remove(var.as_str());
+                vars
+            }
+        }
+    }
+
+    /// Substitutes `ty` for `var`, renaming quantifiers that would capture it.
+    pub fn subst(&self, var: &str, ty: &FType) -> FType {
+        match self {
+            FType::Var(v) if v == var => ty.clone(),
+            FType::Var(_) => self.clone(),
+            FType::Con(con, args) => {
+                FType::Con(con.clone(), args.iter().map(|a| a.subst(var, ty)).collect())
+            }
+            FType::VarApp(head, args) => {
+                let args = args.iter().map(|a| a.subst(var, ty)).collect();
+                FType::Var(head.clone()).subst(var, ty).applied_to(args)
+            }
+            FType::Forall(bound, _) if bound == var => self.clone(),
+            FType::Forall(bound, body) if ty.free_vars().contains(bound.as_str()) => {
+                let avoid: HashSet<_> = ty.free_vars().union(&body.free_vars()).copied().collect();
+                let fresh = (1..)
+                    .map(|i| format!("{bound}{i}"))
+                    .find(|name| !avoid.contains(name.as_str()))
+                    .unwrap();
+                let body = body.subst(bound, &FType::Var(fresh.clone()));
+                FType::Forall(fresh, Box::new(body.subst(var, ty)))
+            }
+            FType::Forall(bound, body) => {
+                FType::Forall(bound.clone(), Box::new(body.subst(var, ty)))
+            }
+        }
+    }
+
+    /// Compares two types up to renaming of their quantified variables.
+    pub fn alpha_eq(&self, other: &FType) -> bool {
+        fn eq(t1: &FType, t2: &FType, bound: &mut Vec<(String, String)>) -> bool {
+            // the innermost quantifier binding either variable decides
+            let var_eq = |v1: &String, v2: &String, bound: &[(String, String)]| {
+                let binding = bound.iter().rev().find(|(b1, b2)| b1 == v1 || b2 == v2);
+                match binding {
+                    Some((b1, b2)) => b1 == v1 && b2 == v2,
+                    None => v1 == v2,
+                }
+            };
+            match (t1, t2) {
+                (FType::Var(v1), FType::Var(v2)) => var_eq(v1, v2, bound),
+                (FType::VarApp(v1, args1), FType::VarApp(v2, args2)) => {
+                    var_eq(v1, v2, bound)
+                        && args1.len() == args2.len()
+                        && args1.iter().zip(args2).all(|(a1, a2)| eq(a1, a2, bound))
+                }
+                (FType::Con(c1, args1), FType::Con(c2, args2)) => {
+                    c1 == c2
+                        && args1.len() == args2.len()
+                        && args1.iter().zip(args2).all(|(a1, a2)| eq(a1, a2, bound))
+                }
+                (FType::Forall(v1, body1), FType::Forall(v2, body2)) => {
+                    bound.push((v1.clone(), v2.clone()));
+                    let result = eq(body1, body2, bound);
+                    bound.pop();
+                    result
+                }
+                _ => false,
+            }
+        }
+
+        eq(self, other, &mut vec![])
+    }
+}
+
+/// Elaborates a typed expression: every generalised `let` binds a type abstraction
+/// and every instantiated variable is applied to the types it was instantiated with.
+pub fn elaborate(typed: &TypedExpr) -> FTerm {
+    elaborate_impl(typed, &HashMap::new())
+}
+
+/// `recursive` maps the variables of the `letrec`s being defined to their quantified
+/// variables: inside its definition, a variable is used at its own type variables.
+fn elaborate_impl(typed: &TypedExpr, recursive: &HashMap<String, Vec<String>>) -> FTerm {
+    match &typed.node {
+        TypedNode::Lit(lit) => FTerm::Lit(lit.clone()),
+        TypedNode::Var(var) => {
+            let fun = FTerm::Var(var.name.clone());
+            match recursive.get(&var.name) {
+                Some(bounded) => bounded.iter().fold(fun, |fun, b| {
+                    FTerm::TyApp(Box::new(fun), FType::Var(b.clone()))
+                }),
+                None => ty_apps(fun, &var.inst),
+            }
+        }
+        TypedNode::Con(con) => ty_apps(FTerm::Con(con.name.clone()), &con.inst),
+        TypedNode::App(app) => FTerm::App(
+            Box::new(elaborate_impl(&app.fun, recursive)),
+            Box::new(elaborate_impl(&app.arg, recursive)),
+        ),
+        TypedNode::Abs(abs) => {
+            let mut recursive = recursive.clone();
+            recursive.remove(&abs.arg);
+            let body = elaborate_impl(&abs.body, &recursive);
+            FTerm::Abs(abs.arg.clone(), (&abs.arg_ty).into(), Box::new(body))
+        }
+        TypedNode::Let(elet) | TypedNode::LetRec(elet) => {
+            let is_rec = matches!(typed.node, TypedNode::LetRec(_));
+            let (bounded, _) = elet.scheme.split();
+            let bounded: Vec<_> = bounded.into_iter().map(str::to_string).collect();
+
+            let mut inner = recursive.clone();
+            if is_rec {
+                inner.insert(elet.var.clone(), bounded.clone());
+            } else {
+                inner.remove(&elet.var);
+            }
+            let vexpr = elaborate_impl(&elet.vexpr, &inner);
+            let vexpr = bounded
+                .iter()
+                .rev()
+                .fold(vexpr, |e, b| FTerm::TyAbs(b.clone(), Box::new(e)));
+
+            let mut recursive = recursive.clone();
+            recursive.remove(&elet.var);
+            let aexpr = elaborate_impl(&elet.aexpr, &recursive);
+
+            let ty = (&elet.scheme).into();
+            let (var, vexpr, aexpr) = (elet.var.clone(), Box::new(vexpr), Box::new(aexpr));
+            if is_rec {
+                FTerm::LetRec(var, ty, vexpr, aexpr)
+            } else {
+                FTerm::Let(var, ty, vexpr, aexpr)
+            }
+        }
+        TypedNode::Case(case) => {
+            let arms = case
+                .arms
+                .iter()
+                .map(|arm| {
+                    let mut recursive = recursive.clone();
+                    for (var, _) in arm.vars.iter() {
+                        recursive.remove(var);
+                    }
+                    let params = arm.con.inst.len() - arm.skolems.len();
+                    FArm {
+                        con: arm.con.name.clone(),
+                        inst: arm.con.inst[..params].iter().map(FType::from).collect(),
+                        skolems: arm.skolems.clone(),
+                        vars: arm
+                            .vars
+                            .iter()
+                            .map(|(var, ty)| (var.clone(), ty.into()))
+                            .collect(),
+                        body: elaborate_impl(&arm.body, &recursive),
+                    }
+                })
+                .collect();
+            FTerm::Case(Box::new(elaborate_impl(&case.expr, recursive)), arms)
+        }
+    }
+}
+
+fn ty_apps(term: FTerm, inst: &[MonoType]) -> FTerm {
+    inst.iter()
+        .fold(term, |term, ty| FTerm::TyApp(Box::new(term), ty.into()))
+}
+
+/// The type of `term`, whose free variables and constructors are typed by `ctx`.
+pub fn type_check(ctx: &Context, term: &FTerm) -> anyhow::Result<FType> {
+    let env = ctx
+        .constrains
+        .iter()
+        .map(|(name, poly)| (name.clone(), poly.into()))
+        .collect();
+
+    type_of(&env, term)
+}
+
+fn type_of(env: &HashMap<String, FType>, term: &FTerm) -> anyhow::Result<FType> {
+    match term {
+        FTerm::Lit(lit) => Ok((&lit.ty()).into()),
+        FTerm::Var(name) | FTerm::Con(name) => match env.get(name) {
+            Some(ty) => Ok(ty.clone()),
+            None => anyhow::bail!("Unbound variable `{name}`"),
+        },
+        FTerm::Abs(arg, arg_ty, body) => {
+            let mut env = env.clone();
+            env.insert(arg.clone(), arg_ty.clone());
+            Ok(FType::arrow(arg_ty.clone(), type_of(&env, body)?))
+        }
+        FTerm::App(fun, arg) => {
+            let fun_ty = type_of(env, fun)?;
+            let arg_ty = type_of(env, arg)?;
+            match fun_ty {
+                FType::Con(con, args) if con == "->" && args[0].alpha_eq(&arg_ty) => {
+                    Ok(args[1].clone())
+                }
+                _ => anyhow::bail!("`{fun}: {fun_ty}` can't be applied to `{arg}: {arg_ty}`"),
+            }
+        }
+        FTerm::TyAbs(var, body) => {
+            if let Some((name, _)) = env
+                .iter()
+                .find(|(_, ty)| ty.free_vars().contains(var.as_str()))
+            {
+                anyhow::bail!("Can't abstract over `{var}`, the type of `{name}` mentions it");
+            }
+            Ok(FType::Forall(var.clone(), Box::new(type_of(env, body)?)))
+        }
+        FTerm::TyApp(fun, arg) => match type_of(env, fun)? {
+            FType::Forall(var, body) => Ok(body.subst(&var, arg)),
+            ty => anyhow::bail!("`{fun}: {ty}` can't be applied to the type `{arg}`"),
+        },
+        FTerm::Let(var, ty, vexpr, aexpr) | FTerm::LetRec(var, ty, vexpr, aexpr) => {
+            let mut inner = env.clone();
+            inner.insert(var.clone(), ty.clone());
+            let vexpr_env = if matches!(term, FTerm::LetRec(..)) {
+                &inner
+            } else {
+                env
+            };
+            let vexpr_ty = type_of(vexpr_env, vexpr)?;
+            if !vexpr_ty.alpha_eq(ty) {
+                anyhow::bail!("`{var}` is annotated with `{ty}` but has type `{vexpr_ty}`");
+            }
+
+            type_of(&inner, aexpr)
+        }
+        FTerm::Case(expr, arms) => {
+            let expr_ty = type_of(env, expr)?;
+            let mut result: Option<FType> = None;
+            for arm in arms.iter() {
+                let body_ty = arm_type(env, &expr_ty, arm)?;
+                match &result {
+                    Some(ty) if !ty.alpha_eq(&body_ty) => {
+                        anyhow::bail!("The arm `{}` has type `{body_ty}`, not `{ty}`", arm.con)
+                    }
+                    _ => result = Some(body_ty),
+                }
+            }
+            match result {
+                Some(ty) => Ok(ty),
+                None => anyhow::bail!("`{term}` has no arms"),
+            }
+        }
+    }
+}
+
+/// The type of the body of an arm matching a value of type `expr_ty`.
+fn arm_type(env: &HashMap<String, FType>, expr_ty: &FType, arm: &FArm) -> anyhow::Result<FType> {
+    let Some(mut con_ty) = env.get(&arm.con).cloned() else {
+        anyhow::bail!("Unbound constructor `{}`", arm.con);
+    };
+    let skolems = arm
+        .skolems
+        .iter()
+        .map(|skolem| FType::Con(skolem.clone(), vec![]));
+    for ty in arm.inst.iter().cloned().chain(skolems) {
+        let FType::Forall(var, body) = con_ty else {
+            anyhow::bail!(
+                "`{}: {con_ty}` can't be applied to the type `{ty}`",
+                arm.con
+            );
+        };
+        con_ty = body.subst(&var, &ty);
+    }
+
+    let mut env = env.clone();
+    for (var, ty) in arm.vars.iter() {
+        match con_ty {
+            FType::Con(con, args) if con == "->" && args[0].alpha_eq(ty) => {
+                con_ty = args[1].clone();
+            }
+            _ => anyhow::bail!("`{}` has no field of type `{ty}` for `{var}`", arm.con),
+        }
+        env.insert(var.clone(), ty.clone());
+    }
+    if !con_ty.alpha_eq(expr_ty) {
+        anyhow::bail!("`{}` builds `{con_ty}`, not `{expr_ty}`", arm.con);
+    }
+
+    let body_ty = type_of(&env, &arm.body)?;
+    if let Some(skolem) = arm.skolems.iter().find(|k| mentions(&body_ty, k)) {
+        anyhow::bail!("`{skolem}` escapes the arm `{}`: {body_ty}", arm.con);
+    }
+    Ok(body_ty)
+}
+
+/// Whether the type constant `con` occurs in `ty`.
+fn mentions(ty: &FType, con: &str) -> bool {
+    match ty {
+        FType::Var(_) => false,
+        FType::Con(head, args) => head == con || args.iter().any(|arg| mentions(arg, con)),
+        FType::VarApp(_, args) => args.iter().any(|arg| mentions(arg, con)),
+        FType::Forall(_, body) => mentions(body, con),
+    }
+}
+
+/// Infers the type of `expr` and checks that its System F elaboration has that type,
+/// a failure is a bug of inference.
+pub fn verify_inference(ctx: &Context, expr: &Expr) -> anyhow::Result<FType> {
+    let typed = crate::alg_w::infer(ctx.clone(), expr.clone())?;
+    let term = elaborate(&typed);
+    let inferred = FType::from(&typed.ty);
+    let checked = type_check(ctx, &term)?;
+    if !checked.alpha_eq(&inferred) {
+        anyhow::bail!("Inferred `{inferred}` but the elaboration has type `{checked}`: {term}");
+    }
+
+    Ok(checked)
+}
+
+impl Display for FType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FType::Var(var) => write!(f, "{var}"),
+            FType::Con(con, args) if con == "->" && args.len() == 2 => {
+                if matches!(&args[0], FType::Var(_) | FType::Con(..) | FType::VarApp(..))
+                    && !is_arrow(&args[0])
+                {
+                    write!(f, "{} -> {}", args[0], args[1])
+                } else {
+                    write!(f, "({}) -> {}", args[0], args[1])
+                }
+            }
+            FType::Con(con, args) if con == "->" => write_args(f, "(->)", args),
+            FType::Con(head, args) | FType::VarApp(head, args) => write_args(f, head, args),
+            FType::Forall(var, body) => write!(f, "∀{var}. {body}"),
+        }
+    }
+}
+
+fn write_args(f: &mut std::fmt::Formatter<'_>, head: &str, args: &[FType]) -> std::fmt::Result {
+    write!(f, "{head}")?;
+    for arg in args {
+        match arg {
+            FType::Con(_, args) | FType::VarApp(_, args) if !args.is_empty() => {
+                write!(f, " ({arg})")?
+            }
+            FType::Forall(..) => write!(f, " ({arg})")?,
+            _ => write!(f, " {arg}")?,
+        }
+    }
+    Ok(())
+}
+
+fn is_arrow(ty: &FType) -> bool {
+    matches!(ty, FType::Con(con, _) if con == "->")
+}
+
+impl Display for FTerm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FTerm::Lit(lit) => write!(f, "{lit}"),
+            FTerm::Var(name) | FTerm::Con(name) => write!(f, "{name}"),
+            FTerm::Abs(arg, ty, body) => write!(f, "\\({arg} : {ty}) -> {body}"),
+            FTerm::TyAbs(var, body) => write!(f, "Λ{var}. {body}"),
+            FTerm::App(fun, arg) => {
+                write_fun(f, fun)?;
+                if matches!(**arg, FTerm::Lit(_) | FTerm::Var(_) | FTerm::Con(_)) {
+                    write!(f, " {arg}")
+                } else {
+                    write!(f, " ({arg})")
+                }
+            }
+            FTerm::TyApp(fun, ty) => {
+                write_fun(f, fun)?;
+                write!(f, " [{ty}]")
+            }
+            FTerm::Let(var, ty, vexpr, aexpr) => {
+                write!(f, "let {var} : {ty} = {vexpr} in {aexpr}")
+            }
+            FTerm::LetRec(var, ty, vexpr, aexpr) => {
+                write!(f, "letrec {var} : {ty} = {vexpr} in {aexpr}")
+            }
+            FTerm::Case(expr, arms) => {
+                write!(f, "case {expr} of")?;
+                for (i, arm) in arms.iter().enumerate() {
+                    let separator = if i > 0 { ";" } else { "" };
+                    write!(f, "{separator} {}", arm.con)?;
+                    for ty in arm.inst.iter() {
+                        write!(f, " [{ty}]")?;
+                    }
+                    for skolem in arm.skolems.iter() {
+                        write!(f, " [{skolem}]")?;
+                    }
+                    for (var, ty) in arm.vars.iter() {
+                        write!(f, " ({var} : {ty})")?;
+                    }
+                    write!(f, " -> {}", arm.body)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes the function of an application, in parentheses if it is a binder.
+fn write_fun(f: &mut std::fmt::Formatter<'_>, fun: &FTerm) -> std::fmt::Result {
+    match fun {
+        FTerm::Abs(..)
+        | FTerm::TyAbs(..)
+        | FTerm::Let(..)
+        | FTerm::LetRec(..)
+        | FTerm::Case(..) => write!(f, "({fun})"),
+        _ => write!(f, "{fun}"),
+    }
+}
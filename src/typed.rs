@@ -0,0 +1,211 @@
+//! Expressions annotated with their types, as produced by [`crate::alg_w::infer`].
+
+use std::fmt::Display;
+
+use crate::models::*;
+use crate::utils::*;
+
+/// A variable or constructor with the scheme it was instantiated from and the types
+/// its bound variables were instantiated with, outermost first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedVar {
+    pub name: String,
+    pub scheme: PolyType,
+    pub inst: Vec<MonoType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedApp {
+    pub fun: TypedExpr,
+    pub arg: TypedExpr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedAbs {
+    pub arg: String,
+    pub arg_ty: MonoType,
+    pub body: TypedExpr,
+}
+
+/// A `let` or `letrec` with the scheme its variable was generalised to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedLet {
+    pub var: String,
+    pub scheme: PolyType,
+    pub vexpr: TypedExpr,
+    pub aexpr: TypedExpr,
+}
+
+/// An arm of a `case`, its constructor instantiated with the types of the scrutinee
+/// then with the skolems of its existential variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedArm {
+    pub con: TypedVar,
+    /// The rigid constants the existential variables were opened with.
+    pub skolems: Vec<String>,
+    pub vars: Vec<(String, MonoType)>,
+    pub body: TypedExpr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedCase {
+    pub expr: TypedExpr,
+    pub arms: Vec<TypedArm>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedNode {
+    Lit(Literal),
+    Var(TypedVar),
+    Con(TypedVar),
+    App(Box<TypedApp>),
+    Abs(Box<TypedAbs>),
+    Let(Box<TypedLet>),
+    LetRec(Box<TypedLet>),
+    Case(Box<TypedCase>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedExpr {
+    pub node: TypedNode,
+    pub ty: MonoType,
+}
+
+impl TypedExpr {
+    /// Drops the types, giving back the expression that was inferred.
+    pub fn erase(&self) -> Expr {
+        match &self.node {
+            TypedNode::Lit(lit) => Expr::Lit(lit.clone()),
+            TypedNode::Var(var) => Expr::Var(var.name.clone()),
+            TypedNode::Con(con) => Expr::Con(con.name.clone()),
+            TypedNode::App(app) => Expr::App(Box::new(AppExpr {
+                fun: app.fun.erase(),
+                arg: app.arg.erase(),
+            })),
+            TypedNode::Abs(abs) => Expr::Abs(Box::new(AbsExpr {
+                arg: abs.arg.clone(),
+                body: abs.body.erase(),
+            })),
+            TypedNode::Let(elet) | TypedNode::LetRec(elet) => {
+                let elet_expr = Box::new(LetExpr {
+                    var: elet.var.clone(),
+                    vexpr: elet.vexpr.erase(),
+                    aexpr: elet.aexpr.erase(),
+                });
+                match self.node {
+                    TypedNode::LetRec(_) => Expr::LetRec(elet_expr),
+                    _ => Expr::Let(elet_expr),
+                }
+            }
+            TypedNode::Case(case) => Expr::Case(Box::new(CaseExpr {
+                expr: case.expr.erase(),
+                arms: case
+                    .arms
+                    .iter()
+                    .map(|arm| CaseArm {
+                        con: arm.con.name.clone(),
+                        vars: arm.vars.iter().map(|(var, _)| var.clone()).collect(),
+                        body: arm.body.erase(),
+                    })
+                    .collect(),
+            })),
+        }
+    }
+
+    /// The expression and its subexpressions, parents before their children.
+    pub fn nodes(&self) -> Vec<&TypedExpr> {
+        let mut nodes = vec![self];
+        match &self.node {
+            TypedNode::Lit(_) | TypedNode::Var(_) | TypedNode::Con(_) => {}
+            TypedNode::App(app) => {
+                nodes.extend(app.fun.nodes());
+                nodes.extend(app.arg.nodes());
+            }
+            TypedNode::Abs(abs) => nodes.extend(abs.body.nodes()),
+            TypedNode::Let(elet) | TypedNode::LetRec(elet) => {
+                nodes.extend(elet.vexpr.nodes());
+                nodes.extend(elet.aexpr.nodes());
+            }
+            TypedNode::Case(case) => {
+                nodes.extend(case.expr.nodes());
+                for arm in case.arms.iter() {
+                    nodes.extend(arm.body.nodes());
+                }
+            }
+        }
+
+        nodes
+    }
+}
+
+impl Display for TypedExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} : {}", self.erase(), self.ty)
+    }
+}
+
+impl Subst for TypedVar {
+    fn apply(self, s: &Substitution) -> Self {
+        TypedVar {
+            name: self.name,
+            scheme: self.scheme.apply(s),
+            inst: self.inst.into_iter().map(|ty| ty.apply(s)).collect(),
+        }
+    }
+}
+
+impl Subst for TypedLet {
+    fn apply(self, s: &Substitution) -> Self {
+        TypedLet {
+            var: self.var,
+            scheme: self.scheme.apply(s),
+            vexpr: self.vexpr.apply(s),
+            aexpr: self.aexpr.apply(s),
+        }
+    }
+}
+
+impl Subst for TypedArm {
+    fn apply(self, s: &Substitution) -> Self {
+        TypedArm {
+            con: self.con.apply(s),
+            skolems: self.skolems,
+            vars: self
+                .vars
+                .into_iter()
+                .map(|(var, ty)| (var, ty.apply(s)))
+                .collect(),
+            body: self.body.apply(s),
+        }
+    }
+}
+
+impl Subst for TypedExpr {
+    fn apply(self, s: &Substitution) -> Self {
+        let node = match self.node {
+            TypedNode::Lit(lit) => TypedNode::Lit(lit),
+            TypedNode::Var(var) => TypedNode::Var(var.apply(s)),
+            TypedNode::Con(con) => TypedNode::Con(con.apply(s)),
+            TypedNode::App(app) => TypedNode::App(Box::new(TypedApp {
+                fun: app.fun.apply(s),
+                arg: app.arg.apply(s),
+            })),
+            TypedNode::Abs(abs) => TypedNode::Abs(Box::new(TypedAbs {
+                arg: abs.arg,
+                arg_ty: abs.arg_ty.apply(s),
+                body: abs.body.apply(s),
+            })),
+            TypedNode::Let(elet) => TypedNode::Let(Box::new(elet.apply(s))),
+            TypedNode::LetRec(elet) => TypedNode::LetRec(Box::new(elet.apply(s))),
+            TypedNode::Case(case) => TypedNode::Case(Box::new(TypedCase {
+                expr: case.expr.apply(s),
+                arms: case.arms.into_iter().map(|arm| arm.apply(s)).collect(),
+            })),
+        };
+
+        TypedExpr {
+            node,
+            ty: self.ty.apply(s),
+        }
+    }
+}
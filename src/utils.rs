@@ -1,4 +1,7 @@
 use crate::models::*;
+use crate::system_f::FType;
+use anyhow::Context as _;
+use std::cell::Cell;
 use std::collections::HashMap;
 
 pub trait Subst: Clone + Sized {
@@ -21,15 +24,18 @@ impl Substitution {
         Substitution { map }
     }
 
-    pub fn combine(self, mut other: Substitution) -> Substitution {
-        let map: Vec<_> = self
+    /// Composes `self ∘ other`: applying the result applies `other`, then `self`.
+    pub fn combine(self, other: Substitution) -> Substitution {
+        let mut map: HashMap<_, _> = other
             .map
             .into_iter()
-            .map(|(k, v)| (k, v.apply(&other)))
+            .map(|(k, v)| (k, v.apply(&self)))
             .collect();
-        other.map.extend(map.into_iter());
+        for (k, v) in self.map {
+            map.entry(k).or_insert(v);
+        }
 
-        other
+        Substitution { map }
     }
 }
 
@@ -41,6 +47,19 @@ impl Subst for MonoType {
                 let monotypes = app.monotypes.into_iter().map(|ty| ty.apply(s)).collect();
                 MonoType::TyApp(TypeApp { monotypes, ..app })
             }
+            MonoType::TyVarApp(app) => {
+                let monotypes = app.monotypes.into_iter().map(|ty| ty.apply(s)).collect();
+                MonoType::TyVar(app.var).apply(s).applied_to(monotypes)
+            }
+        }
+    }
+}
+
+impl Subst for Pred {
+    fn apply(self, s: &Substitution) -> Self {
+        Pred {
+            class: self.class,
+            ty: self.ty.apply(s),
         }
     }
 }
@@ -49,6 +68,10 @@ impl Subst for PolyType {
     fn apply(self, s: &Substitution) -> Self {
         match self {
             PolyType::Mono(mono) => PolyType::Mono(mono.apply(s)),
+            PolyType::Qualified(qual) => PolyType::Qualified(QualType {
+                preds: qual.preds.into_iter().map(|p| p.apply(s)).collect(),
+                ty: qual.ty.apply(s),
+            }),
             PolyType::TyQuantifier(quan) => {
                 let ty = Box::new(quan.ty.apply(s));
                 PolyType::TyQuantifier(TypeQuantifier { ty, ..quan })
@@ -69,27 +92,169 @@ impl Subst for Context {
     }
 }
 
-static mut TID: usize = 0;
+thread_local! {
+    /// The id of the next fresh type variable, per thread so that programs checked at
+    /// the same time don't share it.
+    static TID: Cell<usize> = const { Cell::new(0) };
+}
+
 pub fn new_type_var() -> MonoType {
-    unsafe {
-        let id = TID;
-        TID += 1;
+    let id = TID.replace(TID.get() + 1);
 
-        MonoType::TyVar(format!("t{id}"))
-    }
+    MonoType::TyVar(format!("t{id}"))
+}
+
+/// A rigid type standing for the existential variable `var` of a constructor, a
+/// constant no other type is equal to.
+pub fn new_skolem(var: &str) -> MonoType {
+    let MonoType::TyVar(fresh) = new_type_var() else {
+        unreachable!()
+    };
+
+    MonoType::TyApp(TypeApp {
+        func: format!("{var}#{}", &fresh[1..]),
+        monotypes: vec![],
+    })
 }
 
 pub fn reset_tid() {
-    unsafe {
-        TID = 0;
+    TID.set(0);
+}
+
+/// The type synonyms in scope, which unification only expands when it has to.
+#[derive(Debug, Clone, Default)]
+pub struct Synonyms {
+    map: HashMap<String, SynonymDecl>,
+}
+
+impl Synonyms {
+    pub fn declare(&mut self, synonym: SynonymDecl) {
+        self.map.insert(synonym.name.clone(), synonym);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    /// Replaces the synonym at the head of `ty` with the type it stands for, `None`
+    /// if there is none.
+    pub fn expand(&self, ty: &MonoType) -> Option<MonoType> {
+        let MonoType::TyApp(app) = ty else {
+            return None;
+        };
+        let synonym = self.map.get(&app.func)?;
+        if app.monotypes.len() < synonym.params.len() {
+            return None;
+        }
+        let mut args = app.monotypes.clone();
+        let rest = args.split_off(synonym.params.len());
+        let mapping = synonym.params.iter().cloned().zip(args).collect();
+
+        Some(
+            synonym
+                .ty
+                .clone()
+                .apply(&Substitution::from_map(mapping))
+                .applied_to(rest),
+        )
+    }
+
+    /// Expands every synonym of a type of any rank, for the bidirectional checker that
+    /// knows nothing of them.
+    pub fn expand_ftype(&self, ty: &FType) -> FType {
+        match ty {
+            FType::Var(_) => ty.clone(),
+            FType::Con(con, args) => {
+                let mut args: Vec<_> = args.iter().map(|arg| self.expand_ftype(arg)).collect();
+                match self.map.get(con) {
+                    Some(synonym) if args.len() >= synonym.params.len() => {
+                        let rest = args.split_off(synonym.params.len());
+                        let mapping = synonym.params.iter().map(String::as_str).zip(args);
+                        let expanded = substitute(&synonym.ty, &mapping.collect());
+                        // the synonym may stand for another one
+                        self.expand_ftype(&expanded.applied_to(rest))
+                    }
+                    _ => FType::Con(con.clone(), args),
+                }
+            }
+            FType::VarApp(var, args) => FType::VarApp(
+                var.clone(),
+                args.iter().map(|arg| self.expand_ftype(arg)).collect(),
+            ),
+            FType::Forall(var, body) => {
+                FType::Forall(var.clone(), Box::new(self.expand_ftype(body)))
+            }
+        }
+    }
+
+    /// Expands every synonym of `ty`.
+    pub fn expand_all(&self, ty: &MonoType) -> MonoType {
+        if let Some(expanded) = self.expand(ty) {
+            return self.expand_all(&expanded);
+        }
+        match ty {
+            MonoType::TyVar(_) => ty.clone(),
+            MonoType::TyApp(app) => MonoType::TyApp(TypeApp {
+                func: app.func.clone(),
+                monotypes: app.monotypes.iter().map(|m| self.expand_all(m)).collect(),
+            }),
+            MonoType::TyVarApp(app) => MonoType::TyVarApp(TypeVarApp {
+                var: app.var.clone(),
+                monotypes: app.monotypes.iter().map(|m| self.expand_all(m)).collect(),
+            }),
+        }
+    }
+}
+
+/// Replaces the variables of `ty` with the types of any rank in `mapping`, all at once.
+fn substitute(ty: &MonoType, mapping: &HashMap<&str, FType>) -> FType {
+    match ty {
+        MonoType::TyVar(var) => match mapping.get(var.as_str()) {
+            Some(ty) => ty.clone(),
+            None => FType::Var(var.clone()),
+        },
+        MonoType::TyApp(app) => FType::Con(
+            app.func.clone(),
+            app.monotypes
+                .iter()
+                .map(|m| substitute(m, mapping))
+                .collect(),
+        ),
+        MonoType::TyVarApp(app) => {
+            let args = app
+                .monotypes
+                .iter()
+                .map(|m| substitute(m, mapping))
+                .collect();
+            match mapping.get(app.var.as_str()) {
+                Some(ty) => ty.clone().applied_to(args),
+                None => FType::VarApp(app.var.clone(), args),
+            }
+        }
     }
 }
 
+/// Unifies types without any synonym in scope.
 pub fn unify(ty1: MonoType, ty2: MonoType) -> anyhow::Result<Substitution> {
+    unify_with(&Synonyms::default(), ty1, ty2)
+}
+
+/// Unifies types, expanding the synonyms at their heads only where they differ, so
+/// the types they are solved with keep their names.
+pub fn unify_with(
+    synonyms: &Synonyms,
+    ty1: MonoType,
+    ty2: MonoType,
+) -> anyhow::Result<Substitution> {
     match (ty1, ty2) {
         (MonoType::TyVar(var1), MonoType::TyVar(var2)) if var1 == var2 => Ok(Substitution::empty()),
         (MonoType::TyVar(var), ty) => {
             if contains(&ty, &var) {
+                // the occurrence may be dropped by a synonym, like the one of `Const a`
+                let expanded = synonyms.expand_all(&ty);
+                if expanded != ty {
+                    return unify_with(synonyms, MonoType::TyVar(var), expanded);
+                }
                 anyhow::bail!("Infinite type detected");
             } else {
                 let mut map = HashMap::new();
@@ -97,35 +262,133 @@ pub fn unify(ty1: MonoType, ty2: MonoType) -> anyhow::Result<Substitution> {
                 Ok(Substitution::from_map(map))
             }
         }
-        (ty, MonoType::TyVar(var)) => unify(MonoType::TyVar(var), ty),
-        (MonoType::TyApp(app1), MonoType::TyApp(app2)) => {
-            if app1.func != app2.func {
-                anyhow::bail!(
-                    "Could not unify types (different type functions): {} and {}",
-                    app1.func,
-                    app2.func
-                );
+        (ty, MonoType::TyVar(var)) => unify_with(synonyms, MonoType::TyVar(var), ty),
+        (MonoType::TyVarApp(app1), MonoType::TyVarApp(app2))
+            if app1.monotypes.len() > app2.monotypes.len() =>
+        {
+            unify_with(synonyms, MonoType::TyVarApp(app2), MonoType::TyVarApp(app1))
+        }
+        (MonoType::TyVarApp(app1), MonoType::TyVarApp(app2)) => {
+            // `f a ~ g b c` takes `f` to be `g b`
+            let split = app2.monotypes.len() - app1.monotypes.len();
+            let mut monotypes = app2.monotypes;
+            let args = monotypes.split_off(split);
+            let head = MonoType::TyVar(app2.var).applied_to(monotypes);
+            unify_all(
+                synonyms,
+                MonoType::TyVar(app1.var),
+                head,
+                app1.monotypes,
+                args,
+            )
+        }
+        (MonoType::TyVarApp(var_app), MonoType::TyApp(app))
+        | (MonoType::TyApp(app), MonoType::TyVarApp(var_app)) => {
+            // a synonym is never partially applied, `f` can't stand for it
+            if synonyms.contains(&app.func) {
+                let ty1 = MonoType::TyVarApp(var_app.clone());
+                let ty2 = MonoType::TyApp(app.clone());
+                if let Some(result) = unify_expanded(synonyms, &ty1, &ty2) {
+                    return result;
+                }
             }
-            if app1.monotypes.len() != app2.monotypes.len() {
+
+            // `f a ~ Either b c` takes `f` to be `Either b`
+            if app.monotypes.len() < var_app.monotypes.len() {
                 anyhow::bail!(
-                    "Could not unify types (different argument lengths): {app1} and {app2}"
+                    "Could not unify types (different argument lengths): {} and {app}",
+                    MonoType::TyVarApp(var_app)
                 );
             }
-            let mut subst = Substitution::empty();
-            let mono_tuples = app1.monotypes.into_iter().zip(app2.monotypes.into_iter());
-            for (mono1, mono2) in mono_tuples {
-                let unit_subst = unify(mono1.apply(&subst), mono2.apply(&subst))?;
-                subst = subst.combine(unit_subst);
+            let split = app.monotypes.len() - var_app.monotypes.len();
+            let mut head = app;
+            let args = head.monotypes.split_off(split);
+            unify_all(
+                synonyms,
+                MonoType::TyVar(var_app.var),
+                MonoType::TyApp(head),
+                var_app.monotypes,
+                args,
+            )
+        }
+        (MonoType::TyApp(app1), MonoType::TyApp(app2)) => {
+            let result = unify_apps(synonyms, &app1, &app2);
+            if result.is_ok() {
+                return result;
             }
-
-            Ok(subst)
+            let (ty1, ty2) = (MonoType::TyApp(app1), MonoType::TyApp(app2));
+            unify_expanded(synonyms, &ty1, &ty2).unwrap_or(result)
         }
     }
 }
 
+fn unify_apps(synonyms: &Synonyms, app1: &TypeApp, app2: &TypeApp) -> anyhow::Result<Substitution> {
+    if app1.func != app2.func {
+        anyhow::bail!(
+            "Could not unify types (different type functions): {} and {}",
+            app1.func,
+            app2.func
+        );
+    }
+    if app1.monotypes.len() != app2.monotypes.len() {
+        anyhow::bail!("Could not unify types (different argument lengths): {app1} and {app2}");
+    }
+    let mut subst = Substitution::empty();
+    let mono_tuples = app1.monotypes.iter().zip(app2.monotypes.iter());
+    for (mono1, mono2) in mono_tuples {
+        let unit_subst = unify_with(
+            synonyms,
+            mono1.clone().apply(&subst),
+            mono2.clone().apply(&subst),
+        )?;
+        subst = unit_subst.combine(subst);
+    }
+
+    Ok(subst)
+}
+
+/// Unifies the types again with the synonym at the head of one of them expanded,
+/// naming them as they were if that fails too. `None` if neither has a synonym.
+fn unify_expanded(
+    synonyms: &Synonyms,
+    ty1: &MonoType,
+    ty2: &MonoType,
+) -> Option<anyhow::Result<Substitution>> {
+    let (expanded1, expanded2) = match (synonyms.expand(ty1), synonyms.expand(ty2)) {
+        (Some(expanded1), _) => (expanded1, ty2.clone()),
+        (None, Some(expanded2)) => (ty1.clone(), expanded2),
+        (None, None) => return None,
+    };
+
+    Some(
+        unify_with(synonyms, expanded1, expanded2)
+            .with_context(|| format!("Could not unify types: {ty1} and {ty2}")),
+    )
+}
+
+/// Unifies the heads of two applications, then their arguments pairwise.
+fn unify_all(
+    synonyms: &Synonyms,
+    head1: MonoType,
+    head2: MonoType,
+    args1: Vec<MonoType>,
+    args2: Vec<MonoType>,
+) -> anyhow::Result<Substitution> {
+    let mut subst = unify_with(synonyms, head1, head2)?;
+    for (mono1, mono2) in args1.into_iter().zip(args2) {
+        let unit_subst = unify_with(synonyms, mono1.apply(&subst), mono2.apply(&subst))?;
+        subst = unit_subst.combine(subst);
+    }
+
+    Ok(subst)
+}
+
 fn contains(ty1: &MonoType, var: &str) -> bool {
     match ty1 {
         MonoType::TyVar(tvar) => tvar == var,
         MonoType::TyApp(app) => app.monotypes.iter().any(|mono| contains(mono, var)),
+        MonoType::TyVarApp(app) => {
+            app.var == var || app.monotypes.iter().any(|mono| contains(mono, var))
+        }
     }
 }
@@ -0,0 +1,396 @@
+use std::rc::Rc;
+
+use crate::eval::{self, Env, Thunk, Value};
+use crate::models::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    /// Pushes a constant.
+    Const(u32),
+    /// Pushes the value of a slot of the current frame, slot 0 holds the argument.
+    Local(u32),
+    /// Pushes a value captured by the current closure.
+    Captured(u32),
+    /// Pops a value into a slot.
+    SetLocal(u32),
+    /// Puts a pending thunk in a slot, so that a recursive binding can capture itself.
+    RecLocal(u32),
+    /// Pops a value and fills the pending thunk of a slot with it.
+    FillRec(u32),
+    /// Pushes a closure of a function, capturing what the function's `captures` say.
+    Closure(u32),
+    /// Pops an argument and a function, pushes the result of the call.
+    Call,
+    /// Like `Call` followed by `Return`, reusing the frame of the current function.
+    TailCall,
+    /// Pops the result and returns it to the caller.
+    Return,
+    /// If the value on top of the stack is the constructor of the constant `con`
+    /// applied to `arity` fields, pops it into the slots from `fields` on. Otherwise
+    /// jumps to `next`, leaving it there.
+    Match {
+        con: u32,
+        arity: u32,
+        fields: u32,
+        next: u32,
+    },
+    Jump(u32),
+    /// Pops a value that no arm of a `case` matches.
+    NoMatch,
+}
+
+/// Where a closure gets a captured value from when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    Local(u32),
+    Captured(u32),
+}
+
+#[derive(Debug)]
+pub struct Function {
+    /// The argument and the `let` bindings of the function.
+    pub slots: u32,
+    pub captures: Vec<Capture>,
+    pub code: Vec<Instr>,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub consts: Vec<Value>,
+    pub functions: Vec<Function>,
+    pub main: u32,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: u32,
+    pub captured: Vec<Value>,
+}
+
+/// Compiles a type-checked `expr`, resolving names it doesn't bind in `globals`.
+pub fn compile(globals: &Env, expr: &Expr) -> anyhow::Result<Program> {
+    let mut compiler = Compiler {
+        globals,
+        consts: vec![],
+        functions: vec![],
+        scopes: vec![],
+    };
+    let main = compiler.compile_function("", expr)?;
+
+    Ok(Program {
+        consts: compiler.consts,
+        functions: compiler.functions,
+        main,
+    })
+}
+
+enum Location {
+    Local(u32),
+    Captured(u32),
+}
+
+/// The function being compiled at each level of nesting.
+#[derive(Default)]
+struct Scope {
+    /// Names in scope and their slot, innermost last.
+    locals: Vec<(String, u32)>,
+    slots: u32,
+    captured: Vec<String>,
+    captures: Vec<Capture>,
+    code: Vec<Instr>,
+}
+
+struct Compiler<'a> {
+    globals: &'a Env,
+    consts: Vec<Value>,
+    functions: Vec<Function>,
+    scopes: Vec<Scope>,
+}
+
+impl Compiler<'_> {
+    fn compile_function(&mut self, arg: &str, body: &Expr) -> anyhow::Result<u32> {
+        self.scopes.push(Scope {
+            locals: vec![(arg.to_string(), 0)],
+            slots: 1,
+            ..Scope::default()
+        });
+        self.compile_expr(body, true)?;
+        self.emit(Instr::Return);
+
+        let scope = self.scopes.pop().unwrap();
+        self.functions.push(Function {
+            slots: scope.slots,
+            captures: scope.captures,
+            code: scope.code,
+        });
+
+        Ok(self.functions.len() as u32 - 1)
+    }
+
+    fn compile_expr(&mut self, expr: &Expr, tail: bool) -> anyhow::Result<()> {
+        match expr {
+            Expr::Lit(lit) => self.emit_const(lit.into()),
+            Expr::Var(var) => match self.resolve(var, self.scopes.len() - 1) {
+                Some(Location::Local(slot)) => self.emit(Instr::Local(slot)),
+                Some(Location::Captured(i)) => self.emit(Instr::Captured(i)),
+                None => match self.globals.get(var) {
+                    Some(value) => self.emit_const(value.clone()),
+                    None => anyhow::bail!("Undefiend variable: {var}"),
+                },
+            },
+            Expr::Con(con) => self.emit_const(Value::Con(con.clone(), vec![])),
+            Expr::Abs(abs) => {
+                let function = self.compile_function(&abs.arg, &abs.body)?;
+                self.emit(Instr::Closure(function));
+            }
+            Expr::App(app) => {
+                self.compile_expr(&app.fun, false)?;
+                self.compile_expr(&app.arg, false)?;
+                self.emit(if tail { Instr::TailCall } else { Instr::Call });
+            }
+            Expr::Let(elet) => {
+                self.compile_expr(&elet.vexpr, false)?;
+                let slot = self.new_slot();
+                self.emit(Instr::SetLocal(slot));
+                self.scope().locals.push((elet.var.clone(), slot));
+                self.compile_expr(&elet.aexpr, tail)?;
+                self.scope().locals.pop();
+            }
+            Expr::LetRec(elet) => {
+                let slot = self.new_slot();
+                self.emit(Instr::RecLocal(slot));
+                self.scope().locals.push((elet.var.clone(), slot));
+                self.compile_expr(&elet.vexpr, false)?;
+                self.emit(Instr::FillRec(slot));
+                self.compile_expr(&elet.aexpr, tail)?;
+                self.scope().locals.pop();
+            }
+            Expr::Ann(ann) => self.compile_expr(&ann.expr, tail)?,
+            Expr::Case(case) => {
+                self.compile_expr(&case.expr, false)?;
+                let mut jumps = vec![];
+                for arm in case.arms.iter() {
+                    self.consts.push(Value::Con(arm.con.clone(), vec![]));
+                    let con = self.consts.len() as u32 - 1;
+                    let slots: Vec<_> = arm.vars.iter().map(|_| self.new_slot()).collect();
+                    let matching = self.scope().code.len();
+                    self.emit(Instr::Match {
+                        con,
+                        arity: arm.vars.len() as u32,
+                        fields: slots.first().copied().unwrap_or(0),
+                        next: 0,
+                    });
+
+                    let depth = self.scope().locals.len();
+                    for (var, slot) in arm.vars.iter().zip(slots) {
+                        self.scope().locals.push((var.clone(), slot));
+                    }
+                    self.compile_expr(&arm.body, tail)?;
+                    self.scope().locals.truncate(depth);
+                    jumps.push(self.scope().code.len());
+                    self.emit(Instr::Jump(0));
+
+                    let here = self.scope().code.len() as u32;
+                    if let Instr::Match { next, .. } = &mut self.scope().code[matching] {
+                        *next = here;
+                    }
+                }
+                self.emit(Instr::NoMatch);
+
+                let end = self.scope().code.len() as u32;
+                for jump in jumps {
+                    self.scope().code[jump] = Instr::Jump(end);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds `name` in the function at `depth`, capturing it from the enclosing
+    /// functions if it is bound there.
+    fn resolve(&mut self, name: &str, depth: usize) -> Option<Location> {
+        let scope = &self.scopes[depth];
+        if let Some((_, slot)) = scope.locals.iter().rev().find(|(n, _)| n == name) {
+            return Some(Location::Local(*slot));
+        }
+        if let Some(i) = scope.captured.iter().position(|n| n == name) {
+            return Some(Location::Captured(i as u32));
+        }
+        if depth == 0 {
+            return None;
+        }
+
+        let capture = match self.resolve(name, depth - 1)? {
+            Location::Local(slot) => Capture::Local(slot),
+            Location::Captured(i) => Capture::Captured(i),
+        };
+        let scope = &mut self.scopes[depth];
+        scope.captured.push(name.to_string());
+        scope.captures.push(capture);
+
+        Some(Location::Captured(scope.captures.len() as u32 - 1))
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn new_slot(&mut self) -> u32 {
+        let scope = self.scope();
+        scope.slots += 1;
+        scope.slots - 1
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        self.scope().code.push(instr);
+    }
+
+    fn emit_const(&mut self, value: Value) {
+        self.consts.push(value);
+        self.emit(Instr::Const(self.consts.len() as u32 - 1));
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Where the slots of the frame start on the stack.
+    bp: usize,
+}
+
+/// Runs a compiled program to its result.
+pub fn run(program: &Program) -> anyhow::Result<Value> {
+    let main = Rc::new(Closure {
+        function: program.main,
+        captured: vec![],
+    });
+    let mut stack = vec![];
+    let mut frames = vec![];
+    let mut frame = enter(program, &mut stack, main, 0, Value::Unit);
+
+    loop {
+        let code = &program.functions[frame.closure.function as usize].code;
+        let instr = code[frame.ip];
+        frame.ip += 1;
+
+        let result = match instr {
+            Instr::Const(i) => {
+                stack.push(program.consts[i as usize].clone());
+                continue;
+            }
+            Instr::Local(slot) => {
+                stack.push(eval::force(stack[frame.bp + slot as usize].clone())?);
+                continue;
+            }
+            Instr::Captured(i) => {
+                stack.push(eval::force(frame.closure.captured[i as usize].clone())?);
+                continue;
+            }
+            Instr::SetLocal(slot) => {
+                stack[frame.bp + slot as usize] = stack.pop().unwrap();
+                continue;
+            }
+            Instr::RecLocal(slot) => {
+                stack[frame.bp + slot as usize] = Value::Thunk(Thunk::pending());
+                continue;
+            }
+            Instr::FillRec(slot) => {
+                let value = stack.pop().unwrap();
+                if let Value::Thunk(thunk) = &stack[frame.bp + slot as usize] {
+                    thunk.fill(value);
+                }
+                continue;
+            }
+            Instr::Closure(function) => {
+                let captures = &program.functions[function as usize].captures;
+                let captured = captures
+                    .iter()
+                    .map(|capture| match *capture {
+                        Capture::Local(slot) => stack[frame.bp + slot as usize].clone(),
+                        Capture::Captured(i) => frame.closure.captured[i as usize].clone(),
+                    })
+                    .collect();
+                stack.push(Value::VmClosure(Rc::new(Closure { function, captured })));
+                continue;
+            }
+            Instr::Call | Instr::TailCall => {
+                let arg = stack.pop().unwrap();
+                let fun = stack.pop().unwrap();
+                match fun {
+                    Value::VmClosure(closure) if instr == Instr::TailCall => {
+                        stack.truncate(frame.bp);
+                        frame = enter(program, &mut stack, closure, frame.bp, arg);
+                        continue;
+                    }
+                    Value::VmClosure(closure) => {
+                        let bp = stack.len();
+                        let callee = enter(program, &mut stack, closure, bp, arg);
+                        frames.push(std::mem::replace(&mut frame, callee));
+                        continue;
+                    }
+                    fun if instr == Instr::TailCall => eval::apply(fun, arg)?,
+                    fun => {
+                        stack.push(eval::apply(fun, arg)?);
+                        continue;
+                    }
+                }
+            }
+            Instr::Match {
+                con,
+                arity,
+                fields,
+                next,
+            } => {
+                let value = eval::force(stack.pop().unwrap())?;
+                match (&value, &program.consts[con as usize]) {
+                    (Value::Con(name, args), Value::Con(expected, _))
+                        if name == expected && args.len() == arity as usize =>
+                    {
+                        for (i, arg) in args.iter().enumerate() {
+                            stack[frame.bp + fields as usize + i] = arg.clone();
+                        }
+                    }
+                    _ => {
+                        stack.push(value);
+                        frame.ip = next as usize;
+                    }
+                }
+                continue;
+            }
+            Instr::Jump(target) => {
+                frame.ip = target as usize;
+                continue;
+            }
+            Instr::NoMatch => {
+                let value = stack.pop().unwrap();
+                anyhow::bail!("No arm of the case matches `{value}`")
+            }
+            Instr::Return => stack.pop().unwrap(),
+        };
+
+        stack.truncate(frame.bp);
+        match frames.pop() {
+            Some(caller) => {
+                frame = caller;
+                stack.push(result);
+            }
+            None => return Ok(result),
+        }
+    }
+}
+
+/// Pushes the slots of a call to `closure` at `bp`, with `arg` in slot 0.
+fn enter(
+    program: &Program,
+    stack: &mut Vec<Value>,
+    closure: Rc<Closure>,
+    bp: usize,
+    arg: Value,
+) -> Frame {
+    let slots = program.functions[closure.function as usize].slots as usize;
+    stack.push(arg);
+    stack.resize(bp + slots, Value::Unit);
+
+    Frame { closure, ip: 0, bp }
+}